@@ -0,0 +1,74 @@
+//! Timing of the downmix of an 8 channel 96 kHz stream, through the fast path
+//! for contiguous channels and through the indexed loop for the same channels
+//! listed in reverse order.
+//!
+//! Run with `cargo bench --bench downmix`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use silentcmd::detect::{self, Downmix};
+
+const CHANNELS: usize = 8;
+const RATE: usize = 96000;
+/// frames per read, as an ALSA period would bring them
+const PERIOD: usize = 1024;
+/// seconds of audio mixed down per run
+const SECONDS: usize = 10;
+const RUNS: usize = 5;
+
+/// Fastest of the runs mixing `SECONDS` of `interleaved` periods down.
+fn time<S>(interleaved: &[S], channels: &[usize]) -> Duration
+where
+    S: dasp::Sample + dasp::sample::ToSample<f32>,
+{
+    let mut mono = vec![0.0; PERIOD];
+    let periods = SECONDS * RATE / PERIOD;
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..periods {
+                detect::downmix(
+                    black_box(interleaved),
+                    CHANNELS,
+                    channels,
+                    Downmix::Avg,
+                    &mut mono,
+                );
+                black_box(&mono);
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn bench<S>(format: &str, interleaved: &[S])
+where
+    S: dasp::Sample + dasp::sample::ToSample<f32>,
+{
+    let all: Vec<usize> = (0..CHANNELS).collect();
+    let reversed: Vec<usize> = (0..CHANNELS).rev().collect();
+    let range = time(interleaved, &all);
+    let indexed = time(interleaved, &reversed);
+    println!(
+        "{}: {} s at {} Hz, {} channels: range {:?}, indexed {:?}, speedup {:.2}x",
+        format,
+        SECONDS,
+        RATE,
+        CHANNELS,
+        range,
+        indexed,
+        indexed.as_secs_f64() / range.as_secs_f64()
+    );
+}
+
+fn main() {
+    let samples = PERIOD * CHANNELS;
+    let f32s: Vec<f32> = (0..samples).map(|i| (i as f32 * 0.01).sin()).collect();
+    let i32s: Vec<i32> = f32s.iter().map(|&s| (s * i32::MAX as f32) as i32).collect();
+    let i16s: Vec<i16> = f32s.iter().map(|&s| (s * i16::MAX as f32) as i16).collect();
+    bench("f32", &f32s);
+    bench("i32", &i32s);
+    bench("i16", &i16s);
+}
@@ -2,5 +2,6 @@
 
 plot \
     "/tmp/data.dat" using 1 title "RMS (dB)" with lines, \
-    "/tmp/data.dat" using 2 title "Switch Status" with lines
+    "/tmp/data.dat" using 2 title "Switch Status" with lines, \
+    "/tmp/data.dat" using 3 title "Off Countdown (s)" with lines
 pause -1
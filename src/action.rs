@@ -0,0 +1,532 @@
+use std::env;
+use std::fs;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::common;
+use crate::journal::Journal;
+use crate::switch::Transition;
+
+/// Something done on each switch transition, from the switch thread.
+pub trait SwitchAction: Send {
+    fn run(&mut self, transition: &Transition);
+}
+
+/// Command run instead of the default one when the previous state lasted at least `min`.
+#[derive(Clone, Debug)]
+pub struct DurationRule {
+    pub min: Duration,
+    pub cmd: String,
+}
+
+impl DurationRule {
+    /// Parse `<s>:<cmd>` rules separated by `;`.
+    pub fn parse_list(rules: &str) -> Result<Vec<DurationRule>, String> {
+        rules
+            .split(';')
+            .map(|rule| {
+                let (min, cmd) = rule
+                    .split_once(':')
+                    .ok_or_else(|| format!("missing ':' in rule {:?}", rule))?;
+                let min: f32 = min
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid duration in rule {:?}", rule))?;
+                Ok(DurationRule {
+                    min: Duration::from_secs_f32(min),
+                    cmd: cmd.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Parameters of an input stream as negotiated with the device.
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    pub rate: u32,
+    /// sample format, as named by the backend
+    pub format: String,
+    pub channels: u32,
+}
+
+/// Run a command on each transition, waiting for it to complete.
+///
+/// The command gets the transition in `SILENTCMD_STATE` (on/off), `SILENTCMD_LEVEL` (dB)
+/// and `SILENTCMD_PREVIOUS_DURATION` (s, how long the previous state lasted), the
+/// input in `SILENTCMD_DEVICE` if set with `set_device`, and its parameters in
+/// `SILENTCMD_RATE` (Hz), `SILENTCMD_FORMAT` and `SILENTCMD_CHANNELS` if set with
+/// `set_stream`.
+pub struct CommandAction {
+    cmd_on: String,
+    /// None to run nothing on switching off, unless a rule applies
+    cmd_off: Option<String>,
+    on_rules: Vec<DurationRule>,
+    off_rules: Vec<DurationRule>,
+    allowed: Option<Vec<PathBuf>>,
+    first_on: Option<String>,
+    /// start commands from an empty environment rather than the inherited one
+    clear_env: bool,
+    env: Vec<(String, String)>,
+    cwd: Option<PathBuf>,
+    device: Option<String>,
+    stream: Option<StreamInfo>,
+    /// niceness of the commands, None to inherit it
+    #[cfg(unix)]
+    nice: Option<i32>,
+    report_latency: bool,
+    /// set once a command failed to run or exited with an error
+    failed: Option<Arc<AtomicBool>>,
+}
+
+impl CommandAction {
+    pub fn new(cmd_on: String, cmd_off: Option<String>) -> CommandAction {
+        CommandAction {
+            cmd_on,
+            cmd_off,
+            on_rules: Vec::new(),
+            off_rules: Vec::new(),
+            allowed: None,
+            first_on: None,
+            clear_env: false,
+            env: Vec::new(),
+            cwd: None,
+            device: None,
+            stream: None,
+            #[cfg(unix)]
+            nice: None,
+            report_latency: false,
+            failed: None,
+        }
+    }
+
+    /// Also run this command before the on command, on the first switch on only.
+    pub fn set_first_on(&mut self, cmd: String) {
+        self.first_on = Some(cmd);
+    }
+
+    /// Only run commands whose resolved executable is one of these paths or lies
+    /// under one of them. Symlinks in the paths are resolved once here, so that
+    /// e.g. `/bin` also allows executables resolved to `/usr/bin`.
+    pub fn set_allowed(&mut self, prefixes: Vec<String>) {
+        self.allowed = Some(
+            prefixes
+                .iter()
+                .map(|prefix| fs::canonicalize(prefix).unwrap_or_else(|_| PathBuf::from(prefix)))
+                .collect(),
+        );
+    }
+
+    /// Set these variables in the environment of commands, starting from an empty
+    /// one if `clear`. The `SILENTCMD_*` variables are set in any case.
+    pub fn set_env(&mut self, clear: bool, vars: Vec<(String, String)>) {
+        self.clear_env = clear;
+        self.env = vars;
+    }
+
+    /// Run commands from this working directory rather than the inherited one.
+    pub fn set_cwd(&mut self, dir: PathBuf) {
+        self.cwd = Some(dir);
+    }
+
+    /// Name the input in the environment of commands.
+    pub fn set_device(&mut self, device: &str) {
+        self.device = Some(device.to_string());
+    }
+
+    /// Give the parameters of the input stream to commands.
+    pub fn set_stream(&mut self, stream: StreamInfo) {
+        self.stream = Some(stream);
+    }
+
+    /// Run commands with this niceness, from -20 for the highest priority to 19
+    /// for the lowest, rather than the inherited one.
+    ///
+    /// Set in the child between fork and exec, a command fails to start if its
+    /// niceness cannot be set, as when going below the current one without
+    /// CAP_SYS_NICE. Unix only.
+    #[cfg(unix)]
+    pub fn set_nice(&mut self, nice: i32) {
+        self.nice = Some(nice);
+    }
+
+    pub fn has_cmd_off(&self) -> bool {
+        self.cmd_off.is_some()
+    }
+
+    /// Run the off command if any once, from the calling thread, so that the
+    /// target starts in a known off state.
+    pub fn prime_off(&self) {
+        let Some(cmd) = &self.cmd_off else {
+            return;
+        };
+        let transition = Transition {
+            state: false,
+            level_db: f32::NEG_INFINITY,
+            time: SystemTime::now(),
+            previous: Duration::ZERO,
+            first: false,
+            instant: Instant::now(),
+        };
+        self.spawn(cmd, &transition);
+    }
+
+    /// Print the time from the switch decision to each command being spawned,
+    /// including the wait for the previous commands.
+    pub fn set_report_latency(&mut self) {
+        self.report_latency = true;
+    }
+
+    /// Set `failed` once a command fails to run or exits with an error.
+    pub fn set_failure_flag(&mut self, failed: Arc<AtomicBool>) {
+        self.failed = Some(failed);
+    }
+
+    /// Whether the command is allowed to run, returning its resolved executable
+    /// either way, None without an allowlist.
+    fn check_allowed(&self, cmd: &str) -> Result<Option<PathBuf>, PathBuf> {
+        let Some(prefixes) = &self.allowed else {
+            return Ok(None);
+        };
+        let path = resolve_executable(cmd)?;
+        // whole components only: /usr/bin does not allow /usr/bin-evil
+        if prefixes.iter().any(|prefix| path.starts_with(prefix)) {
+            Ok(Some(path))
+        } else {
+            Err(path)
+        }
+    }
+
+    /// Commands depending on how long the switch was off before turning on,
+    /// and on before turning off. The rule with the longest duration reached wins.
+    pub fn set_rules(&mut self, on_rules: Vec<DurationRule>, off_rules: Vec<DurationRule>) {
+        self.on_rules = on_rules;
+        self.off_rules = off_rules;
+    }
+
+    fn command(&self, transition: &Transition) -> Option<&str> {
+        let (default, rules) = if transition.state {
+            (Some(self.cmd_on.as_str()), &self.on_rules)
+        } else {
+            (self.cmd_off.as_deref(), &self.off_rules)
+        };
+        rules
+            .iter()
+            .filter(|rule| transition.previous >= rule.min)
+            .max_by_key(|rule| rule.min)
+            .map_or(default, |rule| Some(&rule.cmd))
+    }
+
+    /// Run `cmd` with the transition in its environment, waiting for it to complete.
+    fn spawn(&self, cmd: &str, transition: &Transition) {
+        let allowed = match self.check_allowed(cmd) {
+            Ok(allowed) => allowed,
+            Err(path) => {
+                eprintln!(
+                    "Refused to run {:?}: {} is not allowed",
+                    cmd,
+                    path.display()
+                );
+                self.fail();
+                return;
+            }
+        };
+        println!("Run {:?}", cmd);
+        // run the very executable checked, not one found through the PATH
+        // given to the command, which --cmd-env can change
+        let program = match allowed {
+            Some(path) => path,
+            // without PATH in the environment, the lookup would not find bare names
+            None if self.clear_env => find_in_path(cmd),
+            None => PathBuf::from(cmd),
+        };
+        let mut command = new_command(program);
+        if self.clear_env {
+            command.env_clear();
+        }
+        if let Some(dir) = &self.cwd {
+            command.current_dir(dir);
+        }
+        if let Some(device) = &self.device {
+            command.env("SILENTCMD_DEVICE", device);
+        }
+        if let Some(stream) = &self.stream {
+            command
+                .env("SILENTCMD_RATE", stream.rate.to_string())
+                .env("SILENTCMD_FORMAT", &stream.format)
+                .env("SILENTCMD_CHANNELS", stream.channels.to_string());
+        }
+        #[cfg(unix)]
+        if let Some(nice) = self.nice {
+            // only async-signal-safe calls between fork and exec
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let child = command
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .env(
+                "SILENTCMD_STATE",
+                if transition.state { "on" } else { "off" },
+            )
+            .env("SILENTCMD_LEVEL", transition.level_db.to_string())
+            .env(
+                "SILENTCMD_PREVIOUS_DURATION",
+                format!("{:.3}", transition.previous.as_secs_f32()),
+            )
+            .spawn();
+        if self.report_latency && child.is_ok() {
+            println!(
+                "Spawned {:?} {:.2} ms after switching",
+                cmd,
+                transition.instant.elapsed().as_secs_f64() * 1000.0
+            );
+        }
+        let error = match child.and_then(|mut child| child.wait()) {
+            Ok(status) if status.success() => return,
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        };
+        eprintln!("Warning: command {:?} failed, {}", cmd, error);
+        self.fail();
+    }
+
+    fn fail(&self) {
+        if let Some(failed) = &self.failed {
+            failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl SwitchAction for CommandAction {
+    fn run(&mut self, transition: &Transition) {
+        if let Some(cmd) = self.first_on.as_deref().filter(|_| transition.first) {
+            self.spawn(cmd, transition);
+        }
+        if let Some(cmd) = self.command(transition) {
+            self.spawn(cmd, transition);
+        }
+    }
+}
+
+/// Command running `program`.
+#[cfg(not(windows))]
+fn new_command(program: PathBuf) -> Command {
+    Command::new(program)
+}
+
+/// Command running `program`, through PowerShell for a `.ps1` script. Batch
+/// files already run through cmd, as std does for `.bat` and `.cmd`.
+#[cfg(windows)]
+fn new_command(program: PathBuf) -> Command {
+    if program
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ps1"))
+    {
+        let mut command = Command::new("powershell");
+        command
+            .args([
+                "-NoProfile",
+                "-NonInteractive",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+            ])
+            .arg(program);
+        command
+    } else {
+        Command::new(program)
+    }
+}
+
+/// Path of the executable run for `cmd`, looked up in PATH when it is a bare name,
+/// with symlinks and `..` resolved so that prefixes cannot be escaped. Returns the
+/// unresolved path as error if it does not exist.
+fn resolve_executable(cmd: &str) -> Result<PathBuf, PathBuf> {
+    let path = find_in_path(cmd);
+    fs::canonicalize(&path).map_err(|_| path)
+}
+
+/// Path of `cmd` in the PATH of silentcmd when it is a bare name, `cmd` otherwise.
+fn find_in_path(cmd: &str) -> PathBuf {
+    if cmd.contains('/') {
+        return PathBuf::from(cmd);
+    }
+    env::var_os("PATH")
+        .and_then(|paths| {
+            env::split_paths(&paths)
+                .map(|dir| dir.join(cmd))
+                .find(|path| path.is_file())
+        })
+        .unwrap_or_else(|| PathBuf::from(cmd))
+}
+
+/// Format of the transition log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// `Turn on` / `Turn off`
+    Plain,
+    Json,
+    /// `ts=... state=on db=-23.4 dev=hw:1`
+    Logfmt,
+    /// plain lines to the systemd journal, with `STATE`, `LEVEL`, `DEVICE` and
+    /// `PREVIOUS_DURATION` fields
+    Journal,
+}
+
+impl LogFormat {
+    pub fn parse(name: &str) -> Option<LogFormat> {
+        match name {
+            "plain" => Some(LogFormat::Plain),
+            "json" => Some(LogFormat::Json),
+            "logfmt" => Some(LogFormat::Logfmt),
+            "journal" => Some(LogFormat::Journal),
+            _ => None,
+        }
+    }
+}
+
+/// Log each transition on stderr, one line each, or to the systemd journal.
+pub struct LogAction {
+    format: LogFormat,
+    device: String,
+    /// start plain lines with the device
+    plain_device: bool,
+    journal: Option<Journal>,
+}
+
+impl LogAction {
+    /// `device` names the input in logfmt lines and journal fields.
+    ///
+    /// Without a journal to connect to, the journal format falls back to
+    /// plain lines on stderr, with a warning.
+    pub fn new(format: LogFormat, device: &str) -> LogAction {
+        let journal = match format {
+            LogFormat::Journal => Journal::connect()
+                .map_err(|e| {
+                    eprintln!("Warning: no systemd journal, logging on stderr: {}", e);
+                })
+                .ok(),
+            _ => None,
+        };
+        LogAction {
+            format,
+            device: device.to_string(),
+            plain_device: false,
+            journal,
+        }
+    }
+
+    /// Also name the device in plain lines, to tell several apart.
+    pub fn set_plain_device(&mut self) {
+        self.plain_device = true;
+    }
+
+    fn line(&self, transition: &Transition) -> String {
+        let state = if transition.state { "on" } else { "off" };
+        match self.format {
+            LogFormat::Plain | LogFormat::Journal if self.plain_device => {
+                format!("{}: Turn {}", self.device, state)
+            }
+            LogFormat::Plain | LogFormat::Journal => format!("Turn {}", state),
+            LogFormat::Json => transition.to_json(),
+            LogFormat::Logfmt => format!(
+                "ts={:.3} state={} db={:.1} dev={}",
+                common::unix_time(transition.time),
+                state,
+                transition.level_db,
+                common::kv_value(&self.device)
+            ),
+        }
+    }
+}
+
+impl SwitchAction for LogAction {
+    fn run(&mut self, transition: &Transition) {
+        let line = self.line(transition);
+        if let Some(journal) = &self.journal {
+            let fields = [
+                ("MESSAGE", line.as_str()),
+                ("PRIORITY", "6"),
+                ("SYSLOG_IDENTIFIER", "silentcmd"),
+                ("STATE", if transition.state { "on" } else { "off" }),
+                ("LEVEL", &format!("{:.1}", transition.level_db)),
+                ("DEVICE", &self.device),
+                (
+                    "PREVIOUS_DURATION",
+                    &format!("{:.3}", transition.previous.as_secs_f32()),
+                ),
+            ];
+            match journal.send(&fields) {
+                Ok(()) => return,
+                Err(e) => eprintln!("Warning: unable to log to the journal, {}", e),
+            }
+        }
+        eprintln!("{}", line);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    /// Directory with `bin/tool`, `bin-evil/tool` and a `link` to `bin`.
+    fn temp_tree(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("silentcmd-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["bin", "bin-evil"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("tool"), "").unwrap();
+        }
+        symlink(root.join("bin"), root.join("link")).unwrap();
+        root
+    }
+
+    fn allowing(prefix: &Path) -> CommandAction {
+        let mut action = CommandAction::new("true".to_string(), None);
+        action.set_allowed(vec![prefix.to_string_lossy().into_owned()]);
+        action
+    }
+
+    fn allowed(action: &CommandAction, cmd: &Path) -> bool {
+        action.check_allowed(&cmd.to_string_lossy()).is_ok()
+    }
+
+    #[test]
+    fn allowlist_matches_whole_components() {
+        let root = temp_tree("allow-prefix");
+        let action = allowing(&root.join("bin"));
+        assert!(allowed(&action, &root.join("bin/tool")));
+        assert!(!allowed(&action, &root.join("bin-evil/tool")));
+        assert!(!allowed(&action, &root.join("bin/../bin-evil/tool")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn allowlist_resolves_symlinks() {
+        let root = temp_tree("allow-symlink");
+        // an entry through a symlinked directory allows what it points to
+        let action = allowing(&root.join("link"));
+        assert!(allowed(&action, &root.join("bin/tool")));
+        assert!(allowed(&action, &root.join("link/tool")));
+        assert!(!allowed(&action, &root.join("bin-evil/tool")));
+        // and a command through it is checked where it resolves
+        let action = allowing(&root.join("bin"));
+        assert!(allowed(&action, &root.join("link/tool")));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
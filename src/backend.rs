@@ -0,0 +1,15 @@
+//! Entry points of the backends, each taking the full command line.
+
+#[cfg(target_os = "linux")]
+pub mod alsa;
+#[cfg(target_os = "macos")]
+pub mod coreaudio;
+#[cfg(feature = "cpal")]
+pub mod cpal;
+pub mod jack;
+pub mod level;
+pub mod pipewire;
+pub mod pulse;
+pub mod wav;
+#[cfg(windows)]
+pub mod wasapi;
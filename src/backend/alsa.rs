@@ -0,0 +1,949 @@
+use alsa::pcm::{Access, Format, HwParams, PCM};
+use alsa::{Direction, ValueOr};
+use dasp::sample::ToSample;
+use dasp::Sample;
+use docopt::ArgvMap;
+use std::error::Error;
+use std::mem;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use crate::action::StreamInfo;
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::detect::{self, Detector, Downmix};
+use crate::output::Output;
+use crate::shutdown;
+use crate::switch::SwitchStatus;
+use crate::xrun::XrunRate;
+
+const USAGE: &str = concat!(
+    "
+Silent Command for ALSA.
+
+Usage:
+  silentcmd-alsa <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [--device=<alsa-device>]... [options]
+  silentcmd-alsa --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --device=<alsa-device>     ALSA device to record from [default: default]. Repeat for several,
+                             each recorded on its own thread with its own switch running the
+                             commands, which get it in SILENTCMD_DEVICE. Status lines and the
+                             plain log then start with the device. A device failing stops
+                             alone, the exit status being 1 only if all of them failed. Not
+                             with several devices: --control, --http-status, --status-file,
+                             the --event-fifo, --level-history, --debug-audio, as well as
+                             the --transition-dump and --wait-silence.
+  --channels=<1,2,4>         List of channel numbers to record from, each once and in the
+                             order --downmix follows, or auto for all those of the device
+                             [default: 1]
+  --channel-map=<list>       Device channel of each logical channel, e.g. 3,4,1,2 for logical
+                             1 and 2 on the device 3 and 4. --channels are then logical, and
+                             the map must list all the device channels.
+  --threshold=<db>           Minimal signal level to turn on [default: -60.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 30]
+                             With --per-channel, --threshold and --timeout can be lists
+                             aligned with --channels, the switch being on while any is.
+  --bits=<value>             ALSA device to record from: 16/24/32, or auto for the widest
+                             the device supports [default: 32]
+  --format=<fmt>             Sample format overriding --bits: s16le, s24le (24 bits in 4 bytes),
+                             s24_3le (24 bits packed in 3 bytes) or s32le.
+  --downmix=<mode>           Mix the channels down by avg or sum, or keep the mid (L+R)/2
+                             or side (L-R)/2 of the first two channels [default: avg].
+  --analyze-channel=<n>      Detect on this channel only, instead of --channels, without any
+                             mixdown.
+  --per-channel              Detect on each channel separately, the loudest one drives the
+                             switch. Channels missing on the device are dropped with a warning.
+  --warmup-periods=<n>       Periods read and discarded before detecting, skipping startup
+                             garbage from the device [default: 0].
+  --buffer-size=<samples>    Samples per read, and window size unless --analysis-ms is given
+                             [default: 1024].
+  --sample-rate=<Hz>         Recording sample rate, or auto for the first the device supports
+                             of 48000, 44100, 32000, 16000 and 8000 [default: 48000].
+  --xrun-silence=<n>         Count the input as silent whatever its level while xruns exceed
+                             <n> per second over the last 5s, for sources stalling instead of
+                             going quiet. It only lets the timeout run out as silence would:
+                             cmd-off still waits for the timeout, --ack-required still needs
+                             an ack, and levels printed are unchanged. Xruns are only seen
+                             while audio keeps coming, even if late.
+  --reopen-after=<n>         Close and reopen the device from scratch, with the settings
+                             negotiated at startup, after <n> failed reads in a row or a
+                             failed recovery, which otherwise stops the capture. The switch
+                             is forced off meanwhile, running cmd-off if on. Reopening is
+                             retried every second until it succeeds.
+",
+    switch_options!()
+);
+
+/// Option value negotiating the setting with the device.
+const AUTO: &str = "auto";
+
+/// Wait between attempts to reopen the device.
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+/// Rates tried in order for `--sample-rate=auto`.
+const AUTO_RATES: [u32; 5] = [48000, 44100, 32000, 16000, 8000];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SampleFormat {
+    S16,
+    /// 24 bits in the low bytes of a 32-bit word
+    S24,
+    /// 24 bits packed in 3 bytes
+    S24Packed,
+    S32,
+}
+
+impl SampleFormat {
+    fn parse(name: &str) -> Option<SampleFormat> {
+        match name {
+            "s16le" => Some(SampleFormat::S16),
+            "s24le" => Some(SampleFormat::S24),
+            "s24_3le" => Some(SampleFormat::S24Packed),
+            "s32le" => Some(SampleFormat::S32),
+            _ => None,
+        }
+    }
+
+    /// Name as given to --format.
+    fn name(self) -> &'static str {
+        match self {
+            SampleFormat::S16 => "s16le",
+            SampleFormat::S24 => "s24le",
+            SampleFormat::S24Packed => "s24_3le",
+            SampleFormat::S32 => "s32le",
+        }
+    }
+
+    /// Bytes per sample.
+    fn width(self) -> usize {
+        match self {
+            SampleFormat::S16 => 2,
+            SampleFormat::S24Packed => 3,
+            SampleFormat::S24 | SampleFormat::S32 => 4,
+        }
+    }
+
+    fn alsa(self) -> Format {
+        match self {
+            SampleFormat::S16 => Format::s16(),
+            SampleFormat::S24 => Format::s24(),
+            SampleFormat::S24Packed => Format::s24_3(),
+            SampleFormat::S32 => Format::s32(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_device: Vec<String>,
+    flag_buffer_size: usize,
+    flag_channels: String,
+    flag_channel_map: Option<String>,
+    flag_bits: String,
+    flag_format: Option<String>,
+    flag_downmix: String,
+    flag_analyze_channel: Option<usize>,
+    flag_per_channel: bool,
+    flag_sample_rate: String,
+    flag_warmup_periods: usize,
+    flag_xrun_silence: Option<f32>,
+    flag_reopen_after: Option<usize>,
+}
+
+/// Record from an ALSA device and switch on signal.
+pub fn run(argv: Vec<String>) {
+    let argv = cli::parse(USAGE, "ALSA", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    if args.flag_per_channel && args.flag_analyze_channel.is_some() {
+        eprintln!("--analyze-channel and --per-channel are exclusive");
+        process::exit(1);
+    }
+    if args.flag_per_channel && switch_args.flag_min_silence.is_some() {
+        eprintln!("Warning: --min-silence is ignored with --per-channel");
+    }
+    // an invalid list is reported before opening any device
+    if args.flag_channels.trim() != AUTO {
+        parse_channels(args.flag_channels.trim());
+    }
+
+    let mut switch_args = switch_args;
+    if args.flag_device.len() > 1 {
+        let single = [
+            ("--control", switch_args.flag_control.is_some()),
+            ("--http-status", switch_args.flag_http_status.is_some()),
+            ("--status-file", switch_args.flag_status_file.is_some()),
+            ("--level-history", switch_args.flag_level_history.is_some()),
+            ("--debug-audio", switch_args.flag_debug_audio.is_some()),
+            (
+                "--transition-dump",
+                switch_args.flag_transition_dump.is_some(),
+            ),
+            ("--event-fifo", switch_args.flag_event_fifo.is_some()),
+            ("--wait-silence", switch_args.flag_wait_silence),
+        ];
+        if let Some((option, _)) = single.iter().find(|&&(_, given)| given) {
+            eprintln!("{} needs a single --device", option);
+            process::exit(1);
+        }
+        switch_args.set_device_in_log();
+    }
+
+    switch_args.self_test(&args.flag_device.join(","));
+    // each device on its own thread, failing alone
+    let (detected, failed) = thread::scope(|scope| {
+        let captures: Vec<_> = args
+            .flag_device
+            .iter()
+            .map(|device| {
+                let (args, switch_args, argv) = (&args, &switch_args, &argv);
+                scope.spawn(move || {
+                    capture(device, args, switch_args, argv).map_err(|e| {
+                        eprintln!("Error: ALSA device {} stopped, {}", device, e);
+                    })
+                })
+            })
+            .collect();
+        captures
+            .into_iter()
+            .fold((false, 0), |(detected, failed), capture| {
+                match capture.join() {
+                    Ok(Ok(device_detected)) => (detected || device_detected, failed),
+                    // a panic was already reported by the hook
+                    _ => (detected, failed + 1),
+                }
+            })
+    });
+    if failed == args.flag_device.len() {
+        process::exit(1);
+    }
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
+
+/// Record from `device` until shut down, returning whether its switch turned
+/// on, or the error that stopped it.
+fn capture(
+    alsa_device_name: &str,
+    args: &Args,
+    switch_args: &SwitchArgs,
+    argv: &ArgvMap,
+) -> Result<bool, Box<dyn Error>> {
+    let pcm = PCM::new(alsa_device_name, Direction::Capture, false)?;
+    let hwp = HwParams::any(&pcm)?;
+
+    // validate channels
+    let channels: Vec<usize> = if args.flag_channels.trim() == AUTO {
+        let count = auto_channels(&hwp);
+        eprintln!("Resolved --channels=auto to {} channel(s)", count);
+        (1..=count).collect()
+    } else {
+        parse_channels(args.flag_channels.trim())
+    };
+    let channels = match args.flag_analyze_channel {
+        Some(0) => {
+            eprintln!("Channels are numbered from 1");
+            process::exit(1);
+        }
+        Some(channel) => vec![channel],
+        None => channels,
+    };
+    let channel_map = args.flag_channel_map.as_deref().map(parse_channel_map);
+    let channels = match &channel_map {
+        Some(map) => map_channels(map, &channels),
+        None => channels,
+    };
+    // per-channel thresholds and timeouts follow their channel
+    let settings = switch_args.channel_settings(if args.flag_per_channel {
+        channels.len()
+    } else {
+        1
+    });
+    let (mut channels, mut settings): (Vec<usize>, Vec<(f32, u64)>) = channels
+        .into_iter()
+        .zip(settings.into_iter().cycle())
+        .unzip();
+    // a map covers all the device channels
+    let channel_count = channel_map
+        .as_ref()
+        .map_or(*channels.iter().max().unwrap(), Vec::len);
+
+    eprintln!(
+        "Recording {} channels from ALSA device: {}, keeping channel(s) {:?}",
+        channel_count, alsa_device_name, channels
+    );
+
+    if args.flag_per_channel {
+        hwp.set_channels_near(channel_count as u32)?;
+    } else if hwp.set_channels(channel_count as u32).is_err() {
+        return Err(format!("the device does not provide {} channels", channel_count).into());
+    }
+    if args.flag_sample_rate.trim() == AUTO {
+        let rate = auto_rate(&hwp);
+        eprintln!("Resolved --sample-rate=auto to {} Hz", rate);
+        hwp.set_rate(rate, ValueOr::Nearest)?;
+    } else {
+        let rate = args.flag_sample_rate.trim().parse().unwrap_or_else(|_| {
+            eprintln!("Invalid sample rate: {}", args.flag_sample_rate);
+            process::exit(1);
+        });
+        hwp.set_rate(rate, ValueOr::Nearest)?;
+    }
+    let format = match args.flag_format.as_deref() {
+        Some(name) => SampleFormat::parse(name).unwrap_or_else(|| {
+            eprintln!("Unknown sample format: {}", name);
+            process::exit(1);
+        }),
+        None => match args.flag_bits.trim() {
+            AUTO => {
+                let format = auto_format(&hwp);
+                eprintln!("Resolved --bits=auto to {}", format.alsa());
+                format
+            }
+            "16" => SampleFormat::S16,
+            // pick the 24-bit layout the device supports
+            "24" if hwp.test_format(Format::s24()).is_ok() => SampleFormat::S24,
+            "24" => SampleFormat::S24Packed,
+            "32" => SampleFormat::S32,
+            bits => {
+                eprintln!("Invalid bits: {}", bits);
+                process::exit(1);
+            }
+        },
+    };
+    eprintln!("Sample format: {}", format.alsa());
+    hwp.set_format(format.alsa())?;
+    hwp.set_access(Access::RWInterleaved)?;
+    pcm.hw_params(&hwp)?;
+    drop(hwp);
+
+    let hwp = pcm.hw_params_current()?;
+    eprintln!(
+        "HW buffer size: {}, period size: {}, periods: {}",
+        hwp.get_buffer_size()?,
+        hwp.get_period_size()?,
+        hwp.get_periods()?
+    );
+
+    let channel_count = hwp.get_channels()? as usize;
+    if let Some(map) = &channel_map {
+        if map.len() != channel_count {
+            return Err(format!(
+                "the channel map lists {} channels, the device provides {}",
+                map.len(),
+                channel_count
+            )
+            .into());
+        }
+    }
+    if channels.iter().any(|&c| c > channel_count) {
+        // only --per-channel records from the channels the device has
+        if !args.flag_per_channel {
+            return Err(format!(
+                "channel(s) {:?} out of the {} channels of the device",
+                channels, channel_count
+            )
+            .into());
+        }
+        let (kept, dropped): (Vec<_>, Vec<_>) = channels
+            .iter()
+            .zip(settings.iter())
+            .partition(|&(&c, _)| c <= channel_count);
+        eprintln!(
+            "Warning: device provides {} channels, dropping channel(s) {:?}",
+            channel_count,
+            dropped.iter().map(|&(c, _)| c).collect::<Vec<_>>()
+        );
+        if kept.is_empty() {
+            return Err("no channel left to record from".into());
+        }
+        (channels, settings) = kept.into_iter().unzip();
+    }
+    let downmix = cli::downmix(&args.flag_downmix, channels.len());
+    let mix_channels: Vec<usize> = channels.iter().map(|c| c - 1).collect();
+    // a single channel is copied as is
+    let downmix = if args.flag_analyze_channel.is_some() {
+        None
+    } else {
+        Some(downmix)
+    };
+
+    warmup(
+        &pcm,
+        args.flag_warmup_periods,
+        channel_count * format.width(),
+    )?;
+
+    let rate = hwp.get_rate()?;
+    let buf_size = args.flag_buffer_size;
+    let mut detector = switch_args.detector(buf_size, rate);
+    // levels come per analysis frame, accumulated across reads
+    let frame_len = switch_args
+        .flag_analysis_ms
+        .map(|ms| common::time_to_frames(ms, rate) as usize);
+    if let Some(frame_len) = frame_len {
+        detector.set_frame_len(frame_len);
+    }
+    let mut channel_detectors = vec![detector.clone(); channels.len()];
+    if args.flag_per_channel {
+        // the first channel gets the debug audio, clones do not
+        mem::swap(&mut detector, &mut channel_detectors[0]);
+    }
+    let mut channel_levels = Vec::new();
+    let mut output = switch_args.output();
+    if args.flag_device.len() > 1 {
+        output.set_label(alsa_device_name);
+    }
+    let mut dump = switch_args.transition_dump(rate);
+    let settings = if args.flag_per_channel {
+        settings
+    } else {
+        settings[..1].to_vec()
+    };
+    if switch_args.flag_print_config {
+        cli::print_config(argv);
+        cli::config_line("alsa.rate", &rate.to_string());
+        cli::config_line("alsa.format", &format!("{:?}", format.alsa()));
+        cli::config_line("alsa.channels", &channel_count.to_string());
+        cli::config_line("alsa.period-size", &hwp.get_period_size()?.to_string());
+        cli::config_line("alsa.buffer-size", &hwp.get_buffer_size()?.to_string());
+        cli::config_line("alsa.frame", &frame_len.unwrap_or(buf_size).to_string());
+    }
+    // the parameters borrow the PCM handed over to the capture
+    drop(hwp);
+    let mut capture = Capture {
+        device: alsa_device_name.to_string(),
+        pcm: Some(pcm),
+        channels: channel_count as u32,
+        rate,
+        format,
+        warmup_periods: args.flag_warmup_periods,
+        xruns: args.flag_xrun_silence.map(XrunRate::new),
+        reopen_after: args.flag_reopen_after,
+        errors: 0,
+    };
+    let stream = StreamInfo {
+        rate,
+        format: format.name().to_string(),
+        channels: channel_count as u32,
+    };
+    let (mut switch, worker) = switch_args.start_switch(alsa_device_name, Some(&stream), &settings);
+    // the switch still shuts down on a failure
+    let mut failure = None;
+
+    match format {
+        SampleFormat::S16 => {
+            let mut rec_buf_i16 = vec![0; buf_size * channel_count];
+            let mut channel_buf = vec![0.0; buf_size];
+            let mut mono = vec![0.0; buf_size];
+
+            while !shutdown::requested() {
+                let read = |pcm: &PCM| pcm.io_i16()?.readi(rec_buf_i16.as_mut_slice());
+                if let Err(e) = capture.read(read, &mut switch) {
+                    failure = Some(e);
+                    break;
+                }
+                if let Some(xruns) = &mut capture.xruns {
+                    switch.set_stalled(xruns.stalled());
+                }
+
+                mix(
+                    &rec_buf_i16,
+                    channel_count,
+                    &mix_channels,
+                    downmix,
+                    &mut mono,
+                );
+                if args.flag_per_channel {
+                    detect_channels(
+                        &rec_buf_i16,
+                        channel_count,
+                        &channels,
+                        &mut channel_buf,
+                        &mut channel_detectors,
+                        &mut channel_levels,
+                    );
+                    set_channel_meters(&channel_detectors, &mut switch);
+                    process_channel_frames(&channel_levels, channels.len(), &mut switch, &output);
+                } else {
+                    detector.process(&mono, |level, detector| {
+                        process_level(level, detector, &mut switch, &output)
+                    });
+                }
+                if let Some(dump) = &mut dump {
+                    dump.process(&mono, switch.is_on());
+                }
+            }
+        }
+        _ => {
+            let mut rec_buf_i32 = vec![0i32; buf_size * channel_count];
+            let mut rec_buf_bytes = vec![0u8; buf_size * channel_count * 3];
+            let mut channel_buf = vec![0.0; buf_size];
+            let mut mono = vec![0.0; buf_size];
+            while !shutdown::requested() {
+                // read as full scale 32-bit samples
+                match format {
+                    SampleFormat::S24Packed => {
+                        let read = |pcm: &PCM| pcm.io_bytes().readi(rec_buf_bytes.as_mut_slice());
+                        if let Err(e) = capture.read(read, &mut switch) {
+                            failure = Some(e);
+                            break;
+                        }
+                        unpack_s24_3le(&rec_buf_bytes, &mut rec_buf_i32);
+                    }
+                    _ => {
+                        let read = |pcm: &PCM| pcm.io_i32()?.readi(rec_buf_i32.as_mut_slice());
+                        if let Err(e) = capture.read(read, &mut switch) {
+                            failure = Some(e);
+                            break;
+                        }
+                        if format == SampleFormat::S24 {
+                            scale_s24(&mut rec_buf_i32);
+                        }
+                    }
+                }
+                if let Some(xruns) = &mut capture.xruns {
+                    switch.set_stalled(xruns.stalled());
+                }
+
+                mix(
+                    &rec_buf_i32,
+                    channel_count,
+                    &mix_channels,
+                    downmix,
+                    &mut mono,
+                );
+                if args.flag_per_channel {
+                    detect_channels(
+                        &rec_buf_i32,
+                        channel_count,
+                        &channels,
+                        &mut channel_buf,
+                        &mut channel_detectors,
+                        &mut channel_levels,
+                    );
+                    set_channel_meters(&channel_detectors, &mut switch);
+                    process_channel_frames(&channel_levels, channels.len(), &mut switch, &output);
+                } else {
+                    detector.process(&mono, |level, detector| {
+                        process_level(level, detector, &mut switch, &output)
+                    });
+                }
+                if let Some(dump) = &mut dump {
+                    dump.process(&mono, switch.is_on());
+                }
+            }
+        }
+    }
+
+    let detected = switch.detected();
+    switch.shutdown(worker, !switch_args.flag_keep_on_exit);
+    // finalize the debug audio and dumps before a possible exit
+    drop(detector);
+    drop(channel_detectors);
+    drop(dump);
+    match failure {
+        Some(e) => Err(format!("unable to recover the capture, {}", e).into()),
+        None => Ok(detected),
+    }
+}
+
+/// Capture PCM, recovered from failed reads, and reopened after too many of
+/// them with `--reopen-after`.
+struct Capture {
+    device: String,
+    /// None once closed to reopen, until reopened
+    pcm: Option<PCM>,
+    /// settings negotiated on opening, to reopen with
+    channels: u32,
+    rate: u32,
+    format: SampleFormat,
+    warmup_periods: usize,
+    xruns: Option<XrunRate>,
+    reopen_after: Option<usize>,
+    /// failed reads in a row
+    errors: usize,
+}
+
+impl Capture {
+    /// Read with `read`, recovering from a failure, or reopening the device after
+    /// too many of them in a row if enabled. Returns the error if not recovered.
+    ///
+    /// The buffer is processed as read so far, for the switch to keep updating.
+    fn read<F>(&mut self, read: F, switch: &mut SwitchStatus) -> alsa::Result<()>
+    where
+        F: FnOnce(&PCM) -> alsa::Result<usize>,
+    {
+        // closed by a shutdown while reopening
+        let Some(pcm) = &self.pcm else {
+            return Ok(());
+        };
+        let e = match read(pcm) {
+            Ok(_) => {
+                self.errors = 0;
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        self.errors += 1;
+        let recovered = recover(pcm, e, &mut self.xruns);
+        match self.reopen_after {
+            Some(max) if recovered.is_err() || self.errors >= max => {
+                self.reopen(switch);
+                Ok(())
+            }
+            _ => recovered,
+        }
+    }
+
+    /// Close the device and open it again, switching off meanwhile, retrying
+    /// until it succeeds or a shutdown is requested.
+    fn reopen(&mut self, switch: &mut SwitchStatus) {
+        eprintln!(
+            "Reinitializing the capture from {} after {} failed read(s)",
+            self.device, self.errors
+        );
+        if switch.is_on() {
+            eprintln!("Switching off until the capture is back");
+            switch.force(false);
+        }
+        // close first, the device may not open twice
+        self.pcm = None;
+        self.errors = 0;
+        while !shutdown::requested() {
+            match self.open() {
+                Ok(pcm) => {
+                    eprintln!("Capture from {} reinitialized", self.device);
+                    self.pcm = Some(pcm);
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("Warning: unable to reopen {}, {}", self.device, e);
+                    thread::sleep(REOPEN_DELAY);
+                }
+            }
+        }
+    }
+
+    /// Open the device with the settings negotiated at startup.
+    fn open(&self) -> alsa::Result<PCM> {
+        let pcm = PCM::new(&self.device, Direction::Capture, false)?;
+        {
+            let hwp = HwParams::any(&pcm)?;
+            hwp.set_channels(self.channels)?;
+            hwp.set_rate(self.rate, ValueOr::Nearest)?;
+            hwp.set_format(self.format.alsa())?;
+            hwp.set_access(Access::RWInterleaved)?;
+            pcm.hw_params(&hwp)?;
+        }
+        warmup(
+            &pcm,
+            self.warmup_periods,
+            self.channels as usize * self.format.width(),
+        )?;
+        Ok(pcm)
+    }
+}
+
+/// Read and discard `periods` periods of frames of `frame_bytes`, skipping
+/// startup garbage from the device.
+fn warmup(pcm: &PCM, periods: usize, frame_bytes: usize) -> alsa::Result<()> {
+    if periods == 0 {
+        return Ok(());
+    }
+    let period_size = pcm.hw_params_current()?.get_period_size()? as usize;
+    let mut warmup_buf = vec![0u8; period_size * frame_bytes];
+    let io = pcm.io_bytes();
+    for _ in 0..periods {
+        if let Err(e) = io.readi(&mut warmup_buf) {
+            eprintln!("Error: {}", e);
+        }
+    }
+    eprintln!("Discarded {} warmup period(s)", periods);
+    Ok(())
+}
+
+/// Recover from a failed read, counting overruns, returning the error if not
+/// recoverable.
+fn recover(pcm: &PCM, e: alsa::Error, xruns: &mut Option<XrunRate>) -> alsa::Result<()> {
+    eprintln!("Warning: ALSA read failed, {}", e);
+    if e.errno() == libc::EPIPE {
+        if let Some(xruns) = xruns {
+            xruns.add(1);
+        }
+    }
+    pcm.try_recover(e, true)
+}
+
+/// All the channels of the device, or the fewest it takes when its maximum
+/// is out of hardware range, as for plugins converting to any count.
+fn auto_channels(hwp: &HwParams) -> usize {
+    let max = hwp.get_channels_max().unwrap();
+    if max <= 64 {
+        max as usize
+    } else {
+        hwp.get_channels_min().unwrap() as usize
+    }
+}
+
+/// First of `AUTO_RATES` the device supports, or its rate nearest to the first.
+fn auto_rate(hwp: &HwParams) -> u32 {
+    AUTO_RATES
+        .iter()
+        .copied()
+        .find(|&rate| hwp.test_rate(rate).is_ok())
+        .unwrap_or(AUTO_RATES[0])
+}
+
+/// Widest sample format the device supports.
+fn auto_format(hwp: &HwParams) -> SampleFormat {
+    [
+        SampleFormat::S32,
+        SampleFormat::S24,
+        SampleFormat::S24Packed,
+        SampleFormat::S16,
+    ]
+    .into_iter()
+    .find(|format| hwp.test_format(format.alsa()).is_ok())
+    .unwrap_or(SampleFormat::S32)
+}
+
+/// Channel numbers of a `--channels` list, exiting if invalid.
+fn parse_channels(list: &str) -> Vec<usize> {
+    let channels: Vec<usize> = list
+        .split(',')
+        .map(|c| match c.trim().parse() {
+            Ok(0) => {
+                eprintln!("Channels are numbered from 1");
+                process::exit(1);
+            }
+            Ok(channel) => channel,
+            Err(_) => {
+                eprintln!("Invalid channel {:?} in --channels={}", c, list);
+                process::exit(1);
+            }
+        })
+        .collect();
+    // in the order given, which --downmix=side and the per-channel settings follow
+    if let Some(c) = channels
+        .iter()
+        .enumerate()
+        .find_map(|(i, c)| channels[..i].contains(c).then_some(c))
+    {
+        eprintln!("Channel {} is listed twice in --channels={}", c, list);
+        process::exit(1);
+    }
+    channels
+}
+
+/// Device channels (1-based) by logical position from a `--channel-map` list,
+/// exiting if invalid.
+fn parse_channel_map(list: &str) -> Vec<usize> {
+    let map: Vec<usize> = list
+        .split(',')
+        .map(|c| c.trim().parse().unwrap_or(0))
+        .collect();
+    let mut sorted = map.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    if sorted.len() != map.len() || map.iter().any(|&c| c == 0 || c > map.len()) {
+        eprintln!(
+            "Invalid channel map {}: give each device channel from 1 to {} once per position",
+            list,
+            map.len()
+        );
+        process::exit(1);
+    }
+    map
+}
+
+/// Device channels of logical `channels`, exiting if out of the map.
+fn map_channels(map: &[usize], channels: &[usize]) -> Vec<usize> {
+    let mapped = channels
+        .iter()
+        .map(|&c| match c.checked_sub(1).and_then(|i| map.get(i)) {
+            Some(&device) => device,
+            None => {
+                eprintln!("Channel {} is out of the {} mapped channels", c, map.len());
+                process::exit(1);
+            }
+        })
+        .collect();
+    eprintln!(
+        "Logical channel(s) {:?} from device channel(s) {:?}",
+        channels, mapped
+    );
+    mapped
+}
+
+/// Full-scale 32-bit samples from 24-bit ones packed in 3 bytes, little endian.
+fn unpack_s24_3le(bytes: &[u8], samples: &mut [i32]) {
+    for (sample, bytes) in samples.iter_mut().zip(bytes.chunks_exact(3)) {
+        *sample = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]);
+    }
+}
+
+/// Scale 24-bit samples in the low bytes of 32 to full scale, whether or not
+/// the driver sign-extends them into the high byte.
+fn scale_s24(samples: &mut [i32]) {
+    for sample in samples {
+        *sample <<= 8;
+    }
+}
+
+/// Mix the channels (0-based) down to `mono`, or copy the first one without a `downmix`.
+fn mix<S: Sample + ToSample<f32>>(
+    rec_buf: &[S],
+    channel_count: usize,
+    channels: &[usize],
+    downmix: Option<Downmix>,
+    mono: &mut [f32],
+) {
+    match downmix {
+        Some(downmix) => detect::downmix(rec_buf, channel_count, channels, downmix, mono),
+        None => detect::extract_channel(rec_buf, channel_count, channels[0], mono),
+    }
+}
+
+/// Detect each selected channel separately, into `levels` channel after
+/// channel: the level of each analysis frame completed by the buffer, or of
+/// the buffer without analysis frames.
+///
+/// Samples are converted to float on extraction, as for the mixdown, so that
+/// both sample widths share the same full scale.
+fn detect_channels<S: Sample + ToSample<f32>>(
+    rec_buf: &[S],
+    channel_count: usize,
+    channels: &[usize],
+    mono_buf: &mut [f32],
+    detectors: &mut [Detector],
+    levels: &mut Vec<f32>,
+) {
+    levels.clear();
+    for (&c, detector) in channels.iter().zip(detectors.iter_mut()) {
+        detect::extract_channel(rec_buf, channel_count, c - 1, mono_buf);
+        detector.process(mono_buf, |level, _| levels.push(level));
+    }
+}
+
+fn process_level(level: f32, detector: &Detector, switch: &mut SwitchStatus, output: &Output) {
+    let db = common::to_db(level);
+    switch.set_clip_ratio(detector.clip_ratio());
+    switch.set_peak_hold(detector.peak_hold().map(common::to_db));
+    switch.update_window(db, detector.silent(switch.active_threshold_db()));
+    updated(level, switch, output);
+}
+
+/// Report the clip ratio and peak-hold value of the channel having the highest.
+fn set_channel_meters(detectors: &[Detector], switch: &mut SwitchStatus) {
+    switch.set_clip_ratio(
+        detectors
+            .iter()
+            .map(Detector::clip_ratio)
+            .fold(0.0, f32::max),
+    );
+    switch.set_peak_hold(
+        detectors
+            .iter()
+            .filter_map(Detector::peak_hold)
+            .reduce(f32::max)
+            .map(common::to_db),
+    );
+}
+
+/// Switch on the channel levels of each frame, from `detect_channels`: the
+/// channels complete their frames together.
+fn process_channel_frames(
+    levels: &[f32],
+    channels: usize,
+    switch: &mut SwitchStatus,
+    output: &Output,
+) {
+    let frames = levels.len() / channels;
+    for frame in 0..frames {
+        let frame_levels: Vec<f32> = levels.iter().skip(frame).step_by(frames).copied().collect();
+        process_channel_levels(&frame_levels, switch, output);
+    }
+}
+
+/// Switch on channel levels, printing the loudest one.
+fn process_channel_levels(levels: &[f32], switch: &mut SwitchStatus, output: &Output) {
+    let levels_db: Vec<f32> = levels.iter().map(|&level| common::to_db(level)).collect();
+    switch.update_channel_levels(&levels_db);
+    updated(levels.iter().cloned().fold(0.0, f32::max), switch, output);
+}
+
+fn updated(level: f32, switch: &SwitchStatus, output: &Output) {
+    if switch.finished() {
+        shutdown::request();
+    }
+
+    output.print_status(level, switch);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1 kHz tone at 48 kHz of `peak_db` relative to 24-bit full scale.
+    fn tone_s24(peak_db: f32) -> Vec<i32> {
+        let amplitude = common::from_db(peak_db) * 8_388_607.0;
+        (0..4800)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 48000.0;
+                (amplitude * phase.sin()).round() as i32
+            })
+            .collect()
+    }
+
+    fn rms_db(samples: &[i32]) -> f32 {
+        let sum: f32 = samples.iter().map(|s| s.to_sample::<f32>().powi(2)).sum();
+        common::to_db((sum / samples.len() as f32).sqrt())
+    }
+
+    #[test]
+    fn s24_layouts_read_at_tone_level() {
+        // a sine RMS is 3.01 dB below its peak
+        let tone = tone_s24(-6.0);
+        assert!(tone.iter().any(|&s| s < 0));
+
+        let packed: Vec<u8> = tone
+            .iter()
+            .flat_map(|s| s.to_le_bytes().into_iter().take(3))
+            .collect();
+        let mut unpacked = vec![0; tone.len()];
+        unpack_s24_3le(&packed, &mut unpacked);
+        assert!((rms_db(&unpacked) + 9.01).abs() < 0.05);
+
+        // drivers may leave the high byte zero or sign-extend into it
+        let mut low = tone.iter().map(|s| s & 0x00ff_ffff).collect::<Vec<_>>();
+        let mut extended = tone.clone();
+        scale_s24(&mut low);
+        scale_s24(&mut extended);
+        assert_eq!(low, unpacked);
+        assert_eq!(extended, unpacked);
+    }
+
+    #[test]
+    fn s24_sign_extension() {
+        let mut unpacked = [0; 4];
+        unpack_s24_3le(
+            &[
+                0xff, 0xff, 0x7f, 0x00, 0x00, 0x80, 0xff, 0xff, 0xff, 0x01, 0x00, 0x00,
+            ],
+            &mut unpacked,
+        );
+        assert_eq!(unpacked, [i32::MAX - 0xff, i32::MIN, -0x100, 0x100]);
+
+        let mut scaled = [0x007f_ffff, 0x0080_0000, -0x80_0000, 0x00ff_ffff, -1];
+        scale_s24(&mut scaled);
+        assert_eq!(
+            scaled,
+            [i32::MAX - 0xff, i32::MIN, i32::MIN, -0x100, -0x100]
+        );
+    }
+}
@@ -0,0 +1,585 @@
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::action::StreamInfo;
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::detect::{self, Downmix};
+use crate::dump::TransitionDump;
+use crate::output::Output;
+use crate::processor::Processor;
+use crate::shutdown;
+
+const USAGE: &str = concat!(
+    "
+Silent Command for CoreAudio.
+
+Records from an input device of macOS through an audio queue.
+
+Usage:
+  silentcmd-coreaudio <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [options]
+  silentcmd-coreaudio --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --device=<name>            Input device to record from, by its name in Audio MIDI Setup,
+                             the default input if not given.
+  --channels=<n>             Channels to record, the first ones of the device [default: 1].
+  --downmix=<mode>           Mix the channels down by avg or sum, or keep the mid (L+R)/2
+                             or side (L-R)/2 of the first two channels [default: avg].
+  --sample-rate=<hz>         Sample rate to record at, converted by the queue if the device
+                             runs at another [default: 48000].
+  --buffer-size=<frames>     Frames per buffer of the queue, each giving a level
+                             [default: 1024].
+  --threshold=<db>           Minimal signal level to turn on [default: -60.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 30]
+",
+    switch_options!()
+);
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_device: Option<String>,
+    flag_channels: usize,
+    flag_downmix: String,
+    flag_sample_rate: u32,
+    flag_buffer_size: usize,
+}
+
+/// Buffers in the queue, one being processed while the others fill.
+const QUEUE_BUFFERS: usize = 3;
+
+type OSStatus = i32;
+type AudioObjectId = u32;
+type AudioQueueRef = *mut c_void;
+type CFStringRef = *const c_void;
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*code)
+}
+
+const SYSTEM_OBJECT: AudioObjectId = 1;
+const HARDWARE_DEVICES: u32 = fourcc(b"dev#");
+const DEVICE_STREAMS: u32 = fourcc(b"stm#");
+const DEVICE_UID: u32 = fourcc(b"uid ");
+const OBJECT_NAME: u32 = fourcc(b"lnam");
+const SCOPE_GLOBAL: u32 = fourcc(b"glob");
+const SCOPE_INPUT: u32 = fourcc(b"inpt");
+const ELEMENT_MAIN: u32 = 0;
+const QUEUE_CURRENT_DEVICE: u32 = fourcc(b"aqcd");
+const QUEUE_IS_RUNNING: u32 = fourcc(b"aqrn");
+const FORMAT_LINEAR_PCM: u32 = fourcc(b"lpcm");
+/// `kAudioFormatFlagIsFloat | kAudioFormatFlagIsPacked`, in native order
+const FORMAT_FLAGS_FLOAT: u32 = 1 | 1 << 3;
+const STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+
+/// `AudioStreamBasicDescription`
+#[repr(C)]
+struct StreamDescription {
+    sample_rate: f64,
+    format_id: u32,
+    format_flags: u32,
+    bytes_per_packet: u32,
+    frames_per_packet: u32,
+    bytes_per_frame: u32,
+    channels_per_frame: u32,
+    bits_per_channel: u32,
+    reserved: u32,
+}
+
+/// `AudioObjectPropertyAddress`
+#[repr(C)]
+struct PropertyAddress {
+    selector: u32,
+    scope: u32,
+    element: u32,
+}
+
+/// `AudioQueueBuffer`
+#[repr(C)]
+struct QueueBuffer {
+    capacity: u32,
+    data: *mut c_void,
+    byte_size: u32,
+    user_data: *mut c_void,
+    packet_description_capacity: u32,
+    packet_descriptions: *mut c_void,
+    packet_description_count: u32,
+}
+
+type InputCallback = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    queue: AudioQueueRef,
+    buffer: *mut QueueBuffer,
+    start_time: *const c_void,
+    packet_descriptions: u32,
+    packet_descs: *const c_void,
+);
+type PropertyListener = unsafe extern "C" fn(user_data: *mut c_void, queue: AudioQueueRef, id: u32);
+
+#[link(name = "CoreAudio", kind = "framework")]
+extern "C" {
+    fn AudioObjectGetPropertyDataSize(
+        object: AudioObjectId,
+        address: *const PropertyAddress,
+        qualifier_size: u32,
+        qualifier: *const c_void,
+        size: *mut u32,
+    ) -> OSStatus;
+    fn AudioObjectGetPropertyData(
+        object: AudioObjectId,
+        address: *const PropertyAddress,
+        qualifier_size: u32,
+        qualifier: *const c_void,
+        size: *mut u32,
+        data: *mut c_void,
+    ) -> OSStatus;
+}
+
+#[link(name = "AudioToolbox", kind = "framework")]
+extern "C" {
+    fn AudioQueueNewInput(
+        format: *const StreamDescription,
+        callback: InputCallback,
+        user_data: *mut c_void,
+        run_loop: *const c_void,
+        run_loop_mode: CFStringRef,
+        flags: u32,
+        queue: *mut AudioQueueRef,
+    ) -> OSStatus;
+    fn AudioQueueSetProperty(
+        queue: AudioQueueRef,
+        id: u32,
+        data: *const c_void,
+        size: u32,
+    ) -> OSStatus;
+    fn AudioQueueGetProperty(
+        queue: AudioQueueRef,
+        id: u32,
+        data: *mut c_void,
+        size: *mut u32,
+    ) -> OSStatus;
+    fn AudioQueueAddPropertyListener(
+        queue: AudioQueueRef,
+        id: u32,
+        listener: PropertyListener,
+        user_data: *mut c_void,
+    ) -> OSStatus;
+    fn AudioQueueAllocateBuffer(
+        queue: AudioQueueRef,
+        size: u32,
+        buffer: *mut *mut QueueBuffer,
+    ) -> OSStatus;
+    fn AudioQueueEnqueueBuffer(
+        queue: AudioQueueRef,
+        buffer: *mut QueueBuffer,
+        packet_descriptions: u32,
+        packet_descs: *const c_void,
+    ) -> OSStatus;
+    fn AudioQueueStart(queue: AudioQueueRef, start_time: *const c_void) -> OSStatus;
+    fn AudioQueueStop(queue: AudioQueueRef, immediate: u8) -> OSStatus;
+    fn AudioQueueDispose(queue: AudioQueueRef, immediate: u8) -> OSStatus;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFStringGetLength(string: CFStringRef) -> isize;
+    fn CFStringGetMaximumSizeForEncoding(length: isize, encoding: u32) -> isize;
+    fn CFStringGetCString(
+        string: CFStringRef,
+        buffer: *mut c_char,
+        size: isize,
+        encoding: u32,
+    ) -> u8;
+    fn CFRelease(object: *const c_void);
+}
+
+/// Error of a call returning `status`, as its four characters when printable.
+fn check(status: OSStatus, call: &str) -> Result<(), String> {
+    if status == 0 {
+        return Ok(());
+    }
+    let code = status.to_be_bytes();
+    if code.iter().all(|c| c.is_ascii_graphic() || *c == b' ') {
+        Err(format!(
+            "{} failed, '{}'",
+            call,
+            String::from_utf8_lossy(&code)
+        ))
+    } else {
+        Err(format!("{} failed, {}", call, status))
+    }
+}
+
+/// Take a CFString returned by a property, releasing it.
+unsafe fn take_string(string: CFStringRef) -> String {
+    let size =
+        CFStringGetMaximumSizeForEncoding(CFStringGetLength(string), STRING_ENCODING_UTF8) + 1;
+    let mut buffer = vec![0 as c_char; size as usize];
+    let converted = CFStringGetCString(string, buffer.as_mut_ptr(), size, STRING_ENCODING_UTF8);
+    CFRelease(string);
+    if converted == 0 {
+        return String::new();
+    }
+    CStr::from_ptr(buffer.as_ptr())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Value of the property `selector` of `object`, as `T`.
+unsafe fn property<T>(object: AudioObjectId, selector: u32, scope: u32) -> Result<T, String> {
+    let address = PropertyAddress {
+        selector,
+        scope,
+        element: ELEMENT_MAIN,
+    };
+    let mut value = std::mem::MaybeUninit::<T>::uninit();
+    let mut size = std::mem::size_of::<T>() as u32;
+    check(
+        AudioObjectGetPropertyData(
+            object,
+            &address,
+            0,
+            ptr::null(),
+            &mut size,
+            value.as_mut_ptr() as *mut c_void,
+        ),
+        "AudioObjectGetPropertyData",
+    )?;
+    Ok(value.assume_init())
+}
+
+/// Size in bytes of the property `selector` of `object`.
+unsafe fn property_size(object: AudioObjectId, selector: u32, scope: u32) -> Result<u32, String> {
+    let address = PropertyAddress {
+        selector,
+        scope,
+        element: ELEMENT_MAIN,
+    };
+    let mut size = 0;
+    check(
+        AudioObjectGetPropertyDataSize(object, &address, 0, ptr::null(), &mut size),
+        "AudioObjectGetPropertyDataSize",
+    )?;
+    Ok(size)
+}
+
+/// Devices with input streams, with their names.
+fn input_devices() -> Result<Vec<(AudioObjectId, String)>, String> {
+    unsafe {
+        let size = property_size(SYSTEM_OBJECT, HARDWARE_DEVICES, SCOPE_GLOBAL)?;
+        let mut devices = vec![0 as AudioObjectId; size as usize / 4];
+        let address = PropertyAddress {
+            selector: HARDWARE_DEVICES,
+            scope: SCOPE_GLOBAL,
+            element: ELEMENT_MAIN,
+        };
+        let mut size = size;
+        check(
+            AudioObjectGetPropertyData(
+                SYSTEM_OBJECT,
+                &address,
+                0,
+                ptr::null(),
+                &mut size,
+                devices.as_mut_ptr() as *mut c_void,
+            ),
+            "AudioObjectGetPropertyData",
+        )?;
+        devices.truncate(size as usize / 4);
+
+        let mut inputs = Vec::new();
+        for device in devices {
+            if property_size(device, DEVICE_STREAMS, SCOPE_INPUT)? == 0 {
+                continue;
+            }
+            let name = take_string(property(device, OBJECT_NAME, SCOPE_GLOBAL)?);
+            inputs.push((device, name));
+        }
+        Ok(inputs)
+    }
+}
+
+/// Flags shared with the property listener of the queue.
+struct QueueState {
+    /// stopped on purpose, not by the device going away
+    stopping: AtomicBool,
+    failed: AtomicBool,
+}
+
+/// State of the input callback, on the thread of the queue.
+struct Capture {
+    channels: usize,
+    mix_channels: Vec<usize>,
+    downmix: Downmix,
+    mono: Vec<f32>,
+    processor: Processor,
+    output: Output,
+    dump: Option<TransitionDump>,
+}
+
+impl Capture {
+    /// Detect on interleaved samples.
+    fn process(&mut self, samples: &[f32]) {
+        let frames = samples.len() / self.channels;
+        self.mono.resize(frames, 0.0);
+        detect::downmix(
+            &samples[..frames * self.channels],
+            self.channels,
+            &self.mix_channels,
+            self.downmix,
+            &mut self.mono,
+        );
+        let output = &self.output;
+        self.processor.process_with(&self.mono, |last, switch| {
+            if switch.finished() {
+                shutdown::request();
+            }
+            output.print_status(last, switch);
+        });
+        if let Some(dump) = &mut self.dump {
+            dump.process(&self.mono, self.processor.is_on());
+        }
+    }
+}
+
+unsafe extern "C" fn on_input(
+    data: *mut c_void,
+    queue: AudioQueueRef,
+    buffer: *mut QueueBuffer,
+    _start_time: *const c_void,
+    _packet_descriptions: u32,
+    _packet_descs: *const c_void,
+) {
+    let capture = &mut *(data as *mut Capture);
+    let samples = slice::from_raw_parts(
+        (*buffer).data as *const f32,
+        (*buffer).byte_size as usize / 4,
+    );
+    capture.process(samples);
+    // fails once stopping, the buffer being freed with the queue
+    AudioQueueEnqueueBuffer(queue, buffer, 0, ptr::null());
+}
+
+unsafe extern "C" fn on_running_changed(data: *mut c_void, queue: AudioQueueRef, id: u32) {
+    let state = &*(data as *const QueueState);
+    let mut running: u32 = 1;
+    let mut size = 4;
+    AudioQueueGetProperty(
+        queue,
+        id,
+        &mut running as *mut u32 as *mut c_void,
+        &mut size,
+    );
+    if running == 0 && !state.stopping.load(Ordering::SeqCst) {
+        state.failed.store(true, Ordering::SeqCst);
+        shutdown::request();
+    }
+}
+
+/// Create the input queue of `format` on `device`, None for the default
+/// input, with its buffers of `buffer_size` bytes enqueued.
+unsafe fn new_queue(
+    format: &StreamDescription,
+    device: Option<AudioObjectId>,
+    buffer_size: u32,
+    capture: *mut Capture,
+    state: *const QueueState,
+) -> Result<AudioQueueRef, String> {
+    let mut queue = ptr::null_mut();
+    // callbacks on a thread of the queue
+    check(
+        AudioQueueNewInput(
+            format,
+            on_input,
+            capture as *mut c_void,
+            ptr::null(),
+            ptr::null(),
+            0,
+            &mut queue,
+        ),
+        "AudioQueueNewInput",
+    )?;
+    let set_up = set_up_queue(queue, device, buffer_size, state);
+    if set_up.is_err() {
+        AudioQueueDispose(queue, 1);
+    }
+    set_up.map(|()| queue)
+}
+
+unsafe fn set_up_queue(
+    queue: AudioQueueRef,
+    device: Option<AudioObjectId>,
+    buffer_size: u32,
+    state: *const QueueState,
+) -> Result<(), String> {
+    if let Some(device) = device {
+        let uid: CFStringRef = property(device, DEVICE_UID, SCOPE_GLOBAL)?;
+        let status = AudioQueueSetProperty(
+            queue,
+            QUEUE_CURRENT_DEVICE,
+            &uid as *const CFStringRef as *const c_void,
+            std::mem::size_of::<CFStringRef>() as u32,
+        );
+        CFRelease(uid);
+        check(status, "Setting the device of the queue")?;
+    }
+    check(
+        AudioQueueAddPropertyListener(
+            queue,
+            QUEUE_IS_RUNNING,
+            on_running_changed,
+            state as *mut c_void,
+        ),
+        "AudioQueueAddPropertyListener",
+    )?;
+    for _ in 0..QUEUE_BUFFERS {
+        let mut buffer = ptr::null_mut();
+        check(
+            AudioQueueAllocateBuffer(queue, buffer_size, &mut buffer),
+            "AudioQueueAllocateBuffer",
+        )?;
+        check(
+            AudioQueueEnqueueBuffer(queue, buffer, 0, ptr::null()),
+            "AudioQueueEnqueueBuffer",
+        )?;
+    }
+    Ok(())
+}
+
+/// Record from a CoreAudio input device and switch on signal.
+pub fn run(argv: Vec<String>) {
+    let argv = cli::parse(USAGE, "CoreAudio", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    let device_name = args.flag_device.as_deref().unwrap_or("default");
+    switch_args.self_test(device_name);
+    if args.flag_channels == 0 {
+        eprintln!("--channels needs at least one channel");
+        process::exit(1);
+    }
+
+    let device = args.flag_device.as_ref().map(|name| {
+        let devices = input_devices().unwrap_or_else(|e| {
+            eprintln!("Unable to list the CoreAudio devices: {}", e);
+            process::exit(1);
+        });
+        match devices.iter().find(|(_, device)| device == name) {
+            Some(&(id, _)) => id,
+            None => {
+                let names: Vec<&str> = devices.iter().map(|(_, name)| name.as_str()).collect();
+                eprintln!(
+                    "No CoreAudio input device named {}, the input devices are: {}",
+                    name,
+                    names.join(", ")
+                );
+                process::exit(1);
+            }
+        }
+    });
+
+    let rate = args.flag_sample_rate;
+    let channels = args.flag_channels;
+    let downmix = cli::downmix(&args.flag_downmix, channels);
+    let output = switch_args.output();
+    let dump = switch_args.transition_dump(rate);
+    let mut detector = switch_args.detector(args.flag_buffer_size, rate);
+    if let Some(ms) = switch_args.flag_analysis_ms {
+        detector.set_frame_len(common::time_to_frames(ms, rate) as usize);
+    }
+    if switch_args.flag_print_config {
+        cli::print_config(&argv);
+        cli::config_line("coreaudio.device", device_name);
+        cli::config_line("coreaudio.rate", &rate.to_string());
+        cli::config_line("coreaudio.channels", &channels.to_string());
+        cli::config_line("coreaudio.buffer-size", &args.flag_buffer_size.to_string());
+    }
+    let stream_info = StreamInfo {
+        rate,
+        format: "float".to_string(),
+        channels: channels as u32,
+    };
+    let (switch, worker) = switch_args.start_switch(
+        device_name,
+        Some(&stream_info),
+        &switch_args.channel_settings(1),
+    );
+
+    let mut capture = Box::new(Capture {
+        channels,
+        mix_channels: (0..channels).collect(),
+        downmix,
+        mono: Vec::new(),
+        processor: Processor::from_parts(detector, switch),
+        output,
+        dump,
+    });
+    let state = Box::new(QueueState {
+        stopping: AtomicBool::new(false),
+        failed: AtomicBool::new(false),
+    });
+    let format = StreamDescription {
+        sample_rate: rate as f64,
+        format_id: FORMAT_LINEAR_PCM,
+        format_flags: FORMAT_FLAGS_FLOAT,
+        bytes_per_packet: 4 * channels as u32,
+        frames_per_packet: 1,
+        bytes_per_frame: 4 * channels as u32,
+        channels_per_frame: channels as u32,
+        bits_per_channel: 32,
+        reserved: 0,
+    };
+    let buffer_size = (args.flag_buffer_size * channels * 4) as u32;
+
+    let mut failure = None;
+    let queue = unsafe { new_queue(&format, device, buffer_size, &mut *capture, &*state) };
+    match queue {
+        Ok(queue) => {
+            let started = unsafe { check(AudioQueueStart(queue, ptr::null()), "AudioQueueStart") };
+            match started {
+                Ok(()) => {
+                    eprintln!(
+                        "Recording from CoreAudio device: {}, at {} Hz",
+                        device_name, rate
+                    );
+                    shutdown::wait();
+                }
+                Err(e) => failure = Some(e),
+            }
+            // no callback runs once stopped synchronously
+            state.stopping.store(true, Ordering::SeqCst);
+            unsafe {
+                AudioQueueStop(queue, 1);
+                AudioQueueDispose(queue, 1);
+            }
+        }
+        Err(e) => failure = Some(e),
+    }
+    if state.failed.load(Ordering::SeqCst) {
+        failure = Some("the device stopped".to_string());
+    }
+
+    let Capture {
+        processor, dump, ..
+    } = *capture;
+    let detected = processor.switch().detected();
+    processor
+        .into_switch()
+        .shutdown(worker, !switch_args.flag_keep_on_exit);
+    // finalize the dumps before a possible exit
+    drop(dump);
+    if let Some(e) = failure {
+        eprintln!(
+            "Error: unable to record from CoreAudio device {}, {}",
+            device_name, e
+        );
+        process::exit(1);
+    }
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
@@ -0,0 +1,279 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::action::StreamInfo;
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::detect::{self, Downmix};
+use crate::dump::TransitionDump;
+use crate::output::Output;
+use crate::processor::Processor;
+use crate::shutdown;
+
+const USAGE: &str = concat!(
+    "
+Silent Command for cpal.
+
+Records from an input device of the default cpal host: ALSA on Linux,
+CoreAudio on macOS and WASAPI on Windows.
+
+Usage:
+  silentcmd-cpal <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [options]
+  silentcmd-cpal --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --device=<name>            Input device to record from, by its name in the host, the
+                             default input if not given.
+  --channels=<n>             Channels to record, the default of the device if not given.
+  --downmix=<mode>           Mix the channels down by avg or sum, or keep the mid (L+R)/2
+                             or side (L-R)/2 of the first two channels [default: avg].
+  --sample-rate=<hz>         Sample rate to record at, the default of the device if not
+                             given.
+  --window=<frames>          Frames per level, whatever the buffers of the host
+                             [default: 1024].
+  --threshold=<db>           Minimal signal level to turn on [default: -60.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 30]
+",
+    switch_options!()
+);
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_device: Option<String>,
+    flag_channels: Option<u16>,
+    flag_downmix: String,
+    flag_sample_rate: Option<u32>,
+    flag_window: usize,
+}
+
+/// State of the input callback, on the thread of the host.
+struct Capture {
+    channels: usize,
+    mix_channels: Vec<usize>,
+    downmix: Downmix,
+    /// samples of the last buffer, as floats
+    samples: Vec<f32>,
+    mono: Vec<f32>,
+    processor: Processor,
+    output: Output,
+    dump: Option<TransitionDump>,
+}
+
+impl Capture {
+    /// Detect on interleaved samples.
+    fn process<T>(&mut self, samples: &[T])
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        self.samples.clear();
+        self.samples
+            .extend(samples.iter().map(|&sample| f32::from_sample(sample)));
+        let frames = samples.len() / self.channels;
+        self.mono.resize(frames, 0.0);
+        detect::downmix(
+            &self.samples[..frames * self.channels],
+            self.channels,
+            &self.mix_channels,
+            self.downmix,
+            &mut self.mono,
+        );
+        let output = &self.output;
+        self.processor.process_with(&self.mono, |last, switch| {
+            if switch.finished() {
+                shutdown::request();
+            }
+            output.print_status(last, switch);
+        });
+        if let Some(dump) = &mut self.dump {
+            dump.process(&self.mono, self.processor.is_on());
+        }
+    }
+}
+
+/// Input stream of samples of type `T`, detecting on them.
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    capture: &Arc<Mutex<Capture>>,
+    failed: &Arc<AtomicBool>,
+) -> Result<Stream, String>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let capture = Arc::clone(capture);
+    let failed = Arc::clone(failed);
+    device
+        .build_input_stream(
+            config,
+            move |samples: &[T], _| capture.lock().unwrap().process(samples),
+            move |e| {
+                eprintln!("Error: cpal stream failed, {}", e);
+                failed.store(true, Ordering::SeqCst);
+                shutdown::request();
+            },
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+/// Input device named `name`, or the default one, exiting if not found.
+fn find_device(host: &cpal::Host, name: Option<&str>) -> cpal::Device {
+    let Some(name) = name else {
+        return host.default_input_device().unwrap_or_else(|| {
+            eprintln!("No default cpal input device");
+            process::exit(1);
+        });
+    };
+    let devices: Vec<cpal::Device> =
+        host.input_devices()
+            .map(Iterator::collect)
+            .unwrap_or_else(|e| {
+                eprintln!("Unable to list the cpal devices: {}", e);
+                process::exit(1);
+            });
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+    match names.iter().position(|device| device == name) {
+        Some(index) => devices.into_iter().nth(index).unwrap(),
+        None => {
+            eprintln!(
+                "No cpal input device named {}, the input devices are: {}",
+                name,
+                names.join(", ")
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Record from an input device of the default cpal host and switch on signal.
+pub fn run(argv: Vec<String>) {
+    let argv = cli::parse(USAGE, "cpal", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    let device_name = args.flag_device.as_deref().unwrap_or("default");
+    switch_args.self_test(device_name);
+    if args.flag_channels == Some(0) {
+        eprintln!("--channels needs at least one channel");
+        process::exit(1);
+    }
+
+    let host = cpal::default_host();
+    let device = find_device(&host, args.flag_device.as_deref());
+    let default_config = device.default_input_config().unwrap_or_else(|e| {
+        eprintln!("Unable to record from cpal device {}: {}", device_name, e);
+        process::exit(1);
+    });
+    let sample_format = default_config.sample_format();
+    let mut config = default_config.config();
+    if let Some(channels) = args.flag_channels {
+        config.channels = channels;
+    }
+    if let Some(rate) = args.flag_sample_rate {
+        config.sample_rate = cpal::SampleRate(rate);
+    }
+
+    let rate = config.sample_rate.0;
+    let channels = usize::from(config.channels);
+    let downmix = cli::downmix(&args.flag_downmix, channels);
+    let output = switch_args.output();
+    let dump = switch_args.transition_dump(rate);
+    let mut detector = switch_args.detector(args.flag_window, rate);
+    // levels come per window rather than per buffer of the host
+    let frame_len = switch_args.flag_analysis_ms.map_or(args.flag_window, |ms| {
+        common::time_to_frames(ms, rate) as usize
+    });
+    detector.set_frame_len(frame_len);
+    if switch_args.flag_print_config {
+        cli::print_config(&argv);
+        cli::config_line("cpal.host", host.id().name());
+        cli::config_line("cpal.device", device_name);
+        cli::config_line("cpal.rate", &rate.to_string());
+        cli::config_line("cpal.format", &sample_format.to_string());
+        cli::config_line("cpal.channels", &channels.to_string());
+        cli::config_line("cpal.frame", &frame_len.to_string());
+    }
+    let stream_info = StreamInfo {
+        rate,
+        format: sample_format.to_string(),
+        channels: channels as u32,
+    };
+    let (switch, worker) = switch_args.start_switch(
+        device_name,
+        Some(&stream_info),
+        &switch_args.channel_settings(1),
+    );
+
+    let capture = Arc::new(Mutex::new(Capture {
+        channels,
+        mix_channels: (0..channels).collect(),
+        downmix,
+        samples: Vec::new(),
+        mono: Vec::new(),
+        processor: Processor::from_parts(detector, switch),
+        output,
+        dump,
+    }));
+    let failed = Arc::new(AtomicBool::new(false));
+    let stream = match sample_format {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, &capture, &failed),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, &capture, &failed),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, &capture, &failed),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, &capture, &failed),
+        format => Err(format!("unsupported sample format {}", format)),
+    };
+
+    let mut failure = None;
+    match stream {
+        Ok(stream) => {
+            match stream.play() {
+                Ok(()) => {
+                    eprintln!(
+                        "Recording from cpal device: {}, at {} Hz, {} channel(s) of {}",
+                        device_name, rate, channels, sample_format
+                    );
+                    shutdown::wait();
+                }
+                Err(e) => failure = Some(e.to_string()),
+            }
+            // the callback, and its reference to the capture, go with the stream
+            drop(stream);
+        }
+        Err(e) => failure = Some(e),
+    }
+    if failed.load(Ordering::SeqCst) {
+        failure = Some("the device stopped".to_string());
+    }
+
+    let Capture {
+        processor, dump, ..
+    } = Arc::try_unwrap(capture)
+        .ok()
+        .expect("cpal stream still running")
+        .into_inner()
+        .unwrap();
+    let detected = processor.switch().detected();
+    processor
+        .into_switch()
+        .shutdown(worker, !switch_args.flag_keep_on_exit);
+    // finalize the dumps before a possible exit
+    drop(dump);
+    if let Some(e) = failure {
+        eprintln!(
+            "Error: unable to record from cpal device {}, {}",
+            device_name, e
+        );
+        process::exit(1);
+    }
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
@@ -0,0 +1,614 @@
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::action::StreamInfo;
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::dump::TransitionDump;
+use crate::output::Output;
+use crate::processor::Processor;
+use crate::shutdown;
+use crate::xrun::XrunRate;
+
+const USAGE: &str = concat!(
+    "
+Silent Command JACK plugin.
+
+Usage:
+  silentcmd-jack <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [options]
+  silentcmd-jack --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --ports=<n>                Number of input ports to register, in_1 to in_<n> [default: 1].
+  --channel=<n>              Input port driving the detection [default: 1].
+  --grow-ports               Register one more input port whenever all of them are connected,
+                             detecting on the average of the connected ports instead of only
+                             the --channel port, so that each new source gets its own port,
+                             up to 64 ports.
+  --connect-app=<client>     Connect the audio outputs of this JACK client, e.g. a media
+                             player, to the --channel port, or each to a port of its own
+                             with --grow-ports. A client not running yet gets connected
+                             once it registers its ports.
+  --daemon                   Deprecated, does nothing: running until a signal only, never
+                             reading stdin, is the default without --interactive.
+  --interactive              Also quit when enter is pressed, prompting for it.
+  --cv-out                   Also output the detected level on a cv_out port, scaled to 0..1
+                             over the --cv-range, to modulate other JACK clients.
+  --cv-range=<db:db>         Levels in dB output as 0 and 1 by --cv-out [default: -60:0].
+  --xrun-silence=<n>         Count the input as silent whatever its level while xruns exceed
+                             <n> per second over the last 5s, for sources stalling instead of
+                             going quiet. It only lets the timeout run out as silence would:
+                             cmd-off still waits for the timeout, --ack-required still needs
+                             an ack, and levels printed are unchanged. Xruns are only seen
+                             while audio keeps coming, even if late.
+  --threshold=<db>           Minimal signal level to turn on [default: -40.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 60]
+",
+    switch_options!()
+);
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_ports: usize,
+    flag_channel: usize,
+    flag_grow_ports: bool,
+    flag_connect_app: Option<String>,
+    flag_daemon: bool,
+    flag_interactive: bool,
+    flag_cv_out: bool,
+    flag_cv_range: String,
+    flag_xrun_silence: Option<f32>,
+}
+
+/// Interval at which the main thread checks for requests of the notifications.
+const REQUEST_POLL: Duration = Duration::from_millis(200);
+
+/// Ports --grow-ports registers at most, reserved up front for the process callback.
+const MAX_GROWN_PORTS: usize = 64;
+
+/// Largest buffer size of JACK, in frames.
+const MAX_BUFFER_FRAMES: usize = 8192;
+
+/// Work asked of the main thread, as the notification thread cannot call the server.
+enum Request {
+    /// check for a port to register, with --grow-ports
+    GrowPorts,
+    /// connect the ports of the --connect-app client
+    ConnectApp,
+}
+
+/// Samples detection runs on.
+enum Input {
+    /// the --channel port
+    Port(jack::Port<jack::AudioIn>),
+    Grow(GrowPorts),
+}
+
+impl Input {
+    fn samples<'a>(&'a mut self, ps: &'a jack::ProcessScope) -> &'a [f32] {
+        match self {
+            Input::Port(port) => port.as_slice(ps),
+            Input::Grow(grow_ports) => grow_ports.mix(ps),
+        }
+    }
+}
+
+/// Input ports registered as sources get connected, mixed down for detection.
+struct GrowPorts {
+    /// with the capacity for every port the main thread may register
+    ports: Vec<jack::Port<jack::AudioIn>>,
+    /// ports registered since by the main thread
+    rx: mpsc::Receiver<jack::Port<jack::AudioIn>>,
+    /// sized for the largest buffer, so the callback never allocates
+    mono: Vec<f32>,
+}
+
+impl GrowPorts {
+    /// Average of the connected ports, after taking the ports registered since
+    /// the last cycle.
+    fn mix(&mut self, ps: &jack::ProcessScope) -> &[f32] {
+        while let Ok(port) = self.rx.try_recv() {
+            // within the capacity, as the main thread stops at the maximum
+            self.ports.push(port);
+        }
+        let frames = (ps.n_frames() as usize).min(self.mono.len());
+        let mono = &mut self.mono[..frames];
+        mono.fill(0.0);
+        let mut connected = 0;
+        for port in &self.ports {
+            if port.connected_count().unwrap_or(0) == 0 {
+                continue;
+            }
+            connected += 1;
+            for (mono, &sample) in mono.iter_mut().zip(port.as_slice(ps)) {
+                *mono += sample;
+            }
+        }
+        if connected > 1 {
+            let scale = 1.0 / connected as f32;
+            for mono in mono.iter_mut() {
+                *mono *= scale;
+            }
+        }
+        mono
+    }
+}
+
+/// Output port carrying the detected level as a control signal.
+struct CvOut {
+    port: jack::Port<jack::AudioOut>,
+    min_db: f32,
+    max_db: f32,
+    value: f32,
+}
+
+impl CvOut {
+    /// Register the port, scaling `range` as `min:max` dB to 0..1, exiting if invalid.
+    fn new(client: &jack::Client, range: &str) -> CvOut {
+        let (min_db, max_db) = match range
+            .split_once(':')
+            .map(|(min, max)| (min.trim().parse::<f32>(), max.trim().parse::<f32>()))
+        {
+            Some((Ok(min), Ok(max))) if min < max => (min, max),
+            _ => {
+                eprintln!(
+                    "Invalid CV range {}, expected min:max in dB, e.g. -60:0",
+                    range
+                );
+                process::exit(1);
+            }
+        };
+        CvOut {
+            port: client
+                .register_port("cv_out", jack::AudioOut::default())
+                .unwrap(),
+            min_db,
+            max_db,
+            value: 0.0,
+        }
+    }
+
+    /// Fill the output buffer, ramping to the scaled `db` if a level was detected
+    /// to avoid steps at each buffer.
+    fn write(&mut self, db: Option<f32>, ps: &jack::ProcessScope) {
+        let buf = self.port.as_mut_slice(ps);
+        let target = match db {
+            Some(db) => ((db - self.min_db) / (self.max_db - self.min_db)).clamp(0.0, 1.0),
+            None => self.value,
+        };
+        let step = (target - self.value) / buf.len().max(1) as f32;
+        for (i, sample) in buf.iter_mut().enumerate() {
+            *sample = self.value + step * (i + 1) as f32;
+        }
+        self.value = target;
+    }
+}
+
+/// Run as a JACK client and switch on signal from its input.
+pub fn run(argv: Vec<String>) {
+    // process command line arguments
+    let argv = cli::parse(USAGE, "JACK", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    if args.flag_daemon && args.flag_interactive {
+        eprintln!("--daemon and --interactive are exclusive");
+        process::exit(1);
+    }
+    if args.flag_daemon {
+        eprintln!("Warning: --daemon is deprecated, running until a signal is the default");
+    }
+    switch_args.self_test("silentcmd");
+
+    // Create client
+    let (client, _status) =
+        jack::Client::new("silentcmd", jack::ClientOptions::NO_START_SERVER).unwrap();
+
+    // Register ports. They will be used in a callback that will be
+    // called when new data is available.
+    if args.flag_channel == 0 || args.flag_channel > args.flag_ports {
+        eprintln!(
+            "Channel {} out of the {} registered port(s)",
+            args.flag_channel, args.flag_ports
+        );
+        process::exit(1);
+    }
+    let mut in_ports = (1..=args.flag_ports)
+        .map(|n| {
+            client
+                .register_port(&format!("in_{}", n), jack::AudioIn::default())
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    // requests from the notifications, and the ports registered for the
+    // process callback
+    let (request_tx, request_rx) = mpsc::channel();
+    let (port_tx, port_rx) = mpsc::channel();
+    let max_ports = MAX_GROWN_PORTS.max(args.flag_ports);
+    let mut input = if args.flag_grow_ports {
+        let mut ports = Vec::with_capacity(max_ports);
+        ports.append(&mut in_ports);
+        Input::Grow(GrowPorts {
+            ports,
+            rx: port_rx,
+            mono: vec![0.0; MAX_BUFFER_FRAMES.max(client.buffer_size() as usize)],
+        })
+    } else {
+        // the other ports stay registered, unused, until exit
+        Input::Port(in_ports.remove(args.flag_channel - 1))
+    };
+    let mut cv_out = args
+        .flag_cv_out
+        .then(|| CvOut::new(&client, &args.flag_cv_range));
+
+    let buffer_size = client.buffer_size() as usize;
+    let output = switch_args.output();
+    let mut dump = switch_args.transition_dump(client.sample_rate() as u32);
+
+    let mut detector = switch_args.detector(buffer_size, client.sample_rate() as u32);
+    if let Some(ms) = switch_args.flag_analysis_ms {
+        detector.set_frame_len(common::time_to_frames(ms, client.sample_rate() as u32) as usize);
+    }
+    if switch_args.flag_print_config {
+        cli::print_config(&argv);
+        cli::config_line("jack.client", client.name());
+        cli::config_line("jack.rate", &client.sample_rate().to_string());
+        cli::config_line("jack.buffer-size", &buffer_size.to_string());
+    }
+    let stream = StreamInfo {
+        rate: client.sample_rate() as u32,
+        format: "float".to_string(),
+        channels: args.flag_ports as u32,
+    };
+    let (switch, worker) = switch_args.start_switch(
+        client.name(),
+        Some(&stream),
+        &switch_args.channel_settings(1),
+    );
+    // counted by the notifications, rated by the process callback
+    let xrun_count = Arc::new(AtomicUsize::new(0));
+    let mut xruns = args.flag_xrun_silence.map(XrunRate::new);
+    let notifications = Notifications {
+        xruns: xrun_count.clone(),
+        requests: request_tx.clone(),
+        grow_ports: args.flag_grow_ports,
+        connect_app: args.flag_connect_app.clone(),
+    };
+
+    let process_callback =
+        move |processor: &mut Processor, _: &jack::Client, ps: &jack::ProcessScope| {
+            let in_port_p = input.samples(ps);
+            if let Some(xruns) = &mut xruns {
+                xruns.add(xrun_count.swap(0, Ordering::Relaxed));
+                processor.switch_mut().set_stalled(xruns.stalled());
+            }
+
+            // process the buffer, writing the debug audio, dump and status from
+            // this thread when asked to: not real-time safe with these options
+            let db = process_buf(in_port_p, processor, &output, &mut dump);
+            if let Some(cv_out) = &mut cv_out {
+                cv_out.write(db, ps);
+            }
+
+            // Continue as normal
+            jack::Control::Continue
+        };
+    let buffer_callback =
+        |_: &mut Processor, _: &jack::Client, _: jack::Frames| jack::Control::Continue;
+
+    // The processor is kept as handler state to get the switch back on deactivation
+    let process = jack::contrib::ClosureProcessHandler::with_state(
+        Processor::from_parts(detector, switch),
+        process_callback,
+        buffer_callback,
+    );
+
+    // Activate the client, which starts the processing.
+    let active_client = client.activate_async(notifications, process).unwrap();
+
+    // Wait for a signal to quit, or user input when interactive
+    if args.flag_interactive {
+        thread::spawn(|| {
+            let mut user_input = String::new();
+            io::stdin().read_line(&mut user_input).ok();
+            shutdown::request();
+        });
+        println!("Press enter/return to quit...");
+    }
+    if args.flag_connect_app.is_some() {
+        request_tx.send(Request::ConnectApp).ok();
+    }
+    if args.flag_grow_ports || args.flag_connect_app.is_some() {
+        let client = active_client.as_client();
+        let channel = (!args.flag_grow_ports).then_some(args.flag_channel);
+        let mut port_count = args.flag_ports;
+        let mut warned_max = false;
+        while !shutdown::requested() {
+            match request_rx.recv_timeout(REQUEST_POLL) {
+                Ok(Request::GrowPorts) if all_connected(client) => {
+                    if port_count == max_ports {
+                        if !warned_max {
+                            eprintln!(
+                                "Warning: not registering more than {} input ports",
+                                max_ports
+                            );
+                            warned_max = true;
+                        }
+                        continue;
+                    }
+                    port_count += 1;
+                    let name = format!("in_{}", port_count);
+                    match client.register_port(&name, jack::AudioIn::default()) {
+                        Ok(port) => {
+                            println!("JACK: registered {} for the next source", name);
+                            port_tx.send(port).ok();
+                            // an output of the app may be waiting for it
+                            if let Some(app) = &args.flag_connect_app {
+                                connect_app(client, app, channel);
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Warning: unable to register {}, {}", name, e);
+                            port_count -= 1;
+                        }
+                    }
+                }
+                Ok(Request::ConnectApp) => {
+                    if let Some(app) = &args.flag_connect_app {
+                        connect_app(client, app, channel);
+                    }
+                }
+                _ => {}
+            }
+        }
+    } else {
+        shutdown::wait();
+    }
+
+    let (_, _, process) = active_client.deactivate().unwrap();
+    let detected = process.state.switch().detected();
+    process
+        .state
+        .into_switch()
+        .shutdown(worker, !switch_args.flag_keep_on_exit);
+    // finalize the debug audio and dumps of the callback before a possible exit
+    drop(process.callbacks);
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
+
+/// Detect on a buffer, returning the last level in dB if any was detected.
+fn process_buf(
+    rec_buf: &[f32],
+    processor: &mut Processor,
+    output: &Output,
+    dump: &mut Option<TransitionDump>,
+) -> Option<f32> {
+    let mut last_db = None;
+    processor.process_with(rec_buf, |last, switch| {
+        last_db = Some(common::to_db(last));
+        if switch.finished() {
+            shutdown::request();
+        }
+
+        output.print_status(last, switch);
+    });
+    if let Some(dump) = dump {
+        dump.process(rec_buf, processor.is_on());
+    }
+    last_db
+}
+
+/// Full names of the input ports of `client`.
+fn input_ports(client: &jack::Client) -> Vec<String> {
+    let prefix = format!("{}:", client.name());
+    client
+        .ports(None, None, jack::PortFlags::IS_INPUT)
+        .into_iter()
+        .filter(|name| name.starts_with(&prefix))
+        .collect()
+}
+
+/// Connect the audio outputs of the `app` client not connected to us yet, all
+/// to our `channel` port, or else each to one of our ports without a source,
+/// the others waiting for --grow-ports to register more.
+fn connect_app(client: &jack::Client, app: &str, channel: Option<usize>) {
+    let prefix = format!("{}:", app);
+    let ours = format!("{}:", client.name());
+    let sources: Vec<String> = client
+        .ports(None, Some("audio"), jack::PortFlags::IS_OUTPUT)
+        .into_iter()
+        .filter(|name| name.starts_with(&prefix))
+        .collect();
+    if sources.is_empty() {
+        println!("JACK: waiting for {} to register audio outputs", app);
+        return;
+    }
+
+    let mut free_ports = input_ports(client).into_iter().filter(|name| {
+        client
+            .port_by_name(name)
+            .is_some_and(|port| port.connected_count().unwrap_or(0) == 0)
+    });
+    for source in sources {
+        let connected = client.port_by_name(&source).is_some_and(|port| {
+            port.get_connections()
+                .iter()
+                .any(|name| name.starts_with(&ours))
+        });
+        if connected {
+            continue;
+        }
+        let destination = match channel {
+            Some(channel) => format!("{}in_{}", ours, channel),
+            None => match free_ports.next() {
+                Some(port) => port,
+                None => break,
+            },
+        };
+        if let Err(e) = client.connect_ports_by_name(&source, &destination) {
+            eprintln!(
+                "Warning: unable to connect {} to {}, {}",
+                source, destination, e
+            );
+        }
+    }
+}
+
+/// Whether every input port of `client` has a source.
+fn all_connected(client: &jack::Client) -> bool {
+    input_ports(client).iter().all(|name| {
+        client
+            .port_by_name(name)
+            .is_some_and(|port| port.connected_count().unwrap_or(0) > 0)
+    })
+}
+
+struct Notifications {
+    /// xruns since last taken by the process callback
+    xruns: Arc<AtomicUsize>,
+    requests: mpsc::Sender<Request>,
+    grow_ports: bool,
+    /// client whose outputs to connect once it registers them
+    connect_app: Option<String>,
+}
+
+impl Notifications {
+    /// Report a connection to one of our input ports with its sources, warning
+    /// when JACK sums several of them.
+    fn report_connection(&self, client: &jack::Client, a: jack::PortId, b: jack::PortId) {
+        let ours = input_ports(client);
+        let names = [a, b].map(|id| client.port_by_id(id).and_then(|port| port.name().ok()));
+        let Some(port) = names
+            .iter()
+            .flatten()
+            .find(|name| ours.contains(name))
+            .and_then(|name| client.port_by_name(name))
+        else {
+            return;
+        };
+        let name = port.name().unwrap_or_default();
+        let sources = port.get_connections();
+        if sources.is_empty() {
+            println!("JACK: {} has no source", name);
+        } else {
+            println!("JACK: {} from {}", name, sources.join(", "));
+        }
+        // the outputs of --connect-app are summed on purpose without --grow-ports
+        let app = self.connect_app.as_ref().map(|app| format!("{}:", app));
+        let from_app = app.is_some_and(|app| sources.iter().all(|name| name.starts_with(&app)));
+        if sources.len() > 1 && !from_app {
+            eprintln!(
+                "Warning: JACK sums the {} sources of {}, connect them to separate ports, \
+                 registered with --ports or --grow-ports",
+                sources.len(),
+                name
+            );
+        }
+        if self.grow_ports {
+            self.requests.send(Request::GrowPorts).ok();
+        }
+    }
+}
+
+impl jack::NotificationHandler for Notifications {
+    fn thread_init(&self, _: &jack::Client) {
+        println!("JACK: thread init");
+    }
+
+    unsafe fn shutdown(&mut self, status: jack::ClientStatus, reason: &str) {
+        println!(
+            "JACK: shutdown with status {:?} because \"{}\"",
+            status, reason
+        );
+    }
+
+    fn freewheel(&mut self, _: &jack::Client, is_enabled: bool) {
+        println!(
+            "JACK: freewheel mode is {}",
+            if is_enabled { "on" } else { "off" }
+        );
+    }
+
+    fn sample_rate(&mut self, _: &jack::Client, srate: jack::Frames) -> jack::Control {
+        println!("JACK: sample rate changed to {}", srate);
+        jack::Control::Continue
+    }
+
+    fn client_registration(&mut self, _: &jack::Client, name: &str, is_reg: bool) {
+        println!(
+            "JACK: {} client with name \"{}\"",
+            if is_reg { "registered" } else { "unregistered" },
+            name
+        );
+        if is_reg && self.connect_app.as_deref() == Some(name) {
+            self.requests.send(Request::ConnectApp).ok();
+        }
+    }
+
+    fn port_registration(&mut self, client: &jack::Client, port_id: jack::PortId, is_reg: bool) {
+        println!(
+            "JACK: {} port with id {}",
+            if is_reg { "registered" } else { "unregistered" },
+            port_id
+        );
+        // the ports of a client come after its registration
+        if let (true, Some(app)) = (is_reg, &self.connect_app) {
+            let prefix = format!("{}:", app);
+            let name = client.port_by_id(port_id).and_then(|port| port.name().ok());
+            if name.is_some_and(|name| name.starts_with(&prefix)) {
+                self.requests.send(Request::ConnectApp).ok();
+            }
+        }
+    }
+
+    fn port_rename(
+        &mut self,
+        _: &jack::Client,
+        port_id: jack::PortId,
+        old_name: &str,
+        new_name: &str,
+    ) -> jack::Control {
+        println!(
+            "JACK: port with id {} renamed from {} to {}",
+            port_id, old_name, new_name
+        );
+        jack::Control::Continue
+    }
+
+    fn ports_connected(
+        &mut self,
+        client: &jack::Client,
+        port_id_a: jack::PortId,
+        port_id_b: jack::PortId,
+        are_connected: bool,
+    ) {
+        println!(
+            "JACK: ports with id {} and {} are {}",
+            port_id_a,
+            port_id_b,
+            if are_connected {
+                "connected"
+            } else {
+                "disconnected"
+            }
+        );
+        self.report_connection(client, port_id_a, port_id_b);
+    }
+
+    fn graph_reorder(&mut self, _: &jack::Client) -> jack::Control {
+        println!("JACK: graph reordered");
+        jack::Control::Continue
+    }
+
+    fn xrun(&mut self, _: &jack::Client) -> jack::Control {
+        println!("JACK: xrun occurred");
+        self.xruns.fetch_add(1, Ordering::Relaxed);
+        jack::Control::Continue
+    }
+}
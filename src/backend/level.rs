@@ -0,0 +1,83 @@
+use std::io::{self, BufRead};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::shutdown;
+
+const USAGE: &str = concat!(
+    "
+Silent Command for levels read from stdin.
+
+Drives the switch with levels in dB, one per line, without any audio capture
+or detection: the detection options are ignored. Stops at the end of input.
+
+Usage:
+  silentcmd-level <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [options]
+  silentcmd-level --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --interval=<ms>            Wait this long after each level, to replay a captured level
+                             log in real time [default: 0].
+  --threshold=<db>           Minimal signal level to turn on [default: -60.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 30]
+",
+    switch_options!()
+);
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_interval: f32,
+}
+
+/// Switch on levels read from stdin.
+pub fn run(argv: Vec<String>) {
+    let argv = cli::parse(USAGE, "levels", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    switch_args.self_test("stdin");
+
+    if switch_args.flag_print_config {
+        cli::print_config(&argv);
+    }
+    let output = switch_args.output();
+    let (mut switch, worker) =
+        switch_args.start_switch("stdin", None, &switch_args.channel_settings(1));
+    let interval = Duration::from_secs_f32(args.flag_interval / 1000.0);
+
+    for line in io::stdin().lock().lines() {
+        if shutdown::requested() {
+            break;
+        }
+        let line = line.expect("Unable to read stdin");
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let db: f32 = match line.parse() {
+            Ok(db) => db,
+            Err(_) => {
+                eprintln!("Warning: ignoring invalid level: {:?}", line);
+                continue;
+            }
+        };
+
+        switch.update_level(db);
+        if switch.finished() {
+            shutdown::request();
+        }
+        output.print_status(common::from_db(db), &switch);
+
+        if !interval.is_zero() {
+            thread::sleep(interval);
+        }
+    }
+
+    let detected = switch.detected();
+    switch.shutdown(worker, !switch_args.flag_keep_on_exit);
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
@@ -0,0 +1,464 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::process;
+use std::ptr;
+use std::slice;
+use std::thread;
+
+use crate::action::StreamInfo;
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::detect::{self, Downmix};
+use crate::dump::TransitionDump;
+use crate::dylib::Library;
+use crate::output::Output;
+use crate::processor::Processor;
+use crate::shutdown;
+
+const USAGE: &str = concat!(
+    "
+Silent Command for PipeWire.
+
+Records from a PipeWire node through a native stream, without the JACK or
+PulseAudio layers. libpipewire is loaded when starting, building needs no
+PipeWire development files.
+
+Usage:
+  silentcmd-pipewire <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [options]
+  silentcmd-pipewire --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --target=<node>            Node to record from, by name or serial as listed by pw-cli ls
+                             Node, the default source if not given.
+  --capture-sink             Record what a sink plays, from its monitor: the --target sink,
+                             or the default one if not given.
+  --channels=<n>             Channels to record, remixed by PipeWire from those of the node
+                             [default: 1].
+  --downmix=<mode>           Mix the channels down by avg or sum, or keep the mid (L+R)/2
+                             or side (L-R)/2 of the first two channels [default: avg].
+  --sample-rate=<hz>         Sample rate to record at, resampled by PipeWire if the graph
+                             runs at another [default: 48000].
+  --window=<frames>          Frames per level, whatever the quantum of the graph, also asked
+                             of it as the latency of the stream [default: 1024].
+  --threshold=<db>           Minimal signal level to turn on [default: -60.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 30]
+",
+    switch_options!()
+);
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_target: Option<String>,
+    flag_capture_sink: bool,
+    flag_channels: usize,
+    flag_downmix: String,
+    flag_sample_rate: u32,
+    flag_window: usize,
+}
+
+/// Library of the native API, with the version of its ABI.
+const LIBRARY: &str = "libpipewire-0.3.so.0";
+
+/// `SPA_DIRECTION_INPUT`
+const DIRECTION_INPUT: c_int = 0;
+/// `PW_ID_ANY`, the target being given as a property
+const ID_ANY: u32 = 0xffff_ffff;
+/// `PW_STREAM_FLAG_AUTOCONNECT | PW_STREAM_FLAG_MAP_BUFFERS`, the process
+/// callback running on the main loop rather than the real-time thread
+const STREAM_FLAGS: c_int = 1 | 1 << 2;
+/// `PW_STREAM_STATE_ERROR`
+const STATE_ERROR: c_int = -1;
+
+type InitFn = unsafe extern "C" fn(argc: *mut c_int, argv: *mut *mut *mut c_char);
+type DeinitFn = unsafe extern "C" fn();
+type MainLoopNewFn = unsafe extern "C" fn(props: *const c_void) -> *mut c_void;
+type MainLoopFn = unsafe extern "C" fn(main_loop: *mut c_void) -> c_int;
+type MainLoopGetLoopFn = unsafe extern "C" fn(main_loop: *mut c_void) -> *mut c_void;
+type MainLoopDestroyFn = unsafe extern "C" fn(main_loop: *mut c_void);
+type PropertiesNewFn = unsafe extern "C" fn(key: *const c_char, ...) -> *mut c_void;
+type PropertiesSetFn =
+    unsafe extern "C" fn(props: *mut c_void, key: *const c_char, value: *const c_char) -> c_int;
+type StreamNewSimpleFn = unsafe extern "C" fn(
+    pw_loop: *mut c_void,
+    name: *const c_char,
+    props: *mut c_void,
+    events: *const StreamEvents,
+    data: *mut c_void,
+) -> *mut c_void;
+type StreamConnectFn = unsafe extern "C" fn(
+    stream: *mut c_void,
+    direction: c_int,
+    target_id: u32,
+    flags: c_int,
+    params: *mut *const c_void,
+    n_params: u32,
+) -> c_int;
+type DequeueBufferFn = unsafe extern "C" fn(stream: *mut c_void) -> *mut PwBuffer;
+type QueueBufferFn = unsafe extern "C" fn(stream: *mut c_void, buffer: *mut PwBuffer) -> c_int;
+type StreamDestroyFn = unsafe extern "C" fn(stream: *mut c_void);
+type StateAsStringFn = unsafe extern "C" fn(state: c_int) -> *const c_char;
+
+/// Functions of libpipewire used.
+struct Api {
+    init: InitFn,
+    deinit: DeinitFn,
+    main_loop_new: MainLoopNewFn,
+    main_loop_get_loop: MainLoopGetLoopFn,
+    main_loop_run: MainLoopFn,
+    main_loop_quit: MainLoopFn,
+    main_loop_destroy: MainLoopDestroyFn,
+    properties_new: PropertiesNewFn,
+    properties_set: PropertiesSetFn,
+    stream_new_simple: StreamNewSimpleFn,
+    stream_connect: StreamConnectFn,
+    dequeue_buffer: DequeueBufferFn,
+    queue_buffer: QueueBufferFn,
+    stream_destroy: StreamDestroyFn,
+    state_as_string: StateAsStringFn,
+}
+
+impl Api {
+    fn load() -> Result<Api, String> {
+        let library = Library::open(LIBRARY)?;
+        unsafe {
+            Ok(Api {
+                init: library.function("pw_init")?,
+                deinit: library.function("pw_deinit")?,
+                main_loop_new: library.function("pw_main_loop_new")?,
+                main_loop_get_loop: library.function("pw_main_loop_get_loop")?,
+                main_loop_run: library.function("pw_main_loop_run")?,
+                main_loop_quit: library.function("pw_main_loop_quit")?,
+                main_loop_destroy: library.function("pw_main_loop_destroy")?,
+                properties_new: library.function("pw_properties_new")?,
+                properties_set: library.function("pw_properties_set")?,
+                stream_new_simple: library.function("pw_stream_new_simple")?,
+                stream_connect: library.function("pw_stream_connect")?,
+                dequeue_buffer: library.function("pw_stream_dequeue_buffer")?,
+                queue_buffer: library.function("pw_stream_queue_buffer")?,
+                stream_destroy: library.function("pw_stream_destroy")?,
+                state_as_string: library.function("pw_stream_state_as_string")?,
+            })
+        }
+    }
+}
+
+/// `struct pw_stream_events` of version 0, up to `drained`.
+#[repr(C)]
+struct StreamEvents {
+    version: u32,
+    destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+    state_changed: Option<unsafe extern "C" fn(*mut c_void, c_int, c_int, *const c_char)>,
+    control_info: Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void)>,
+    io_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *mut c_void, u32)>,
+    param_changed: Option<unsafe extern "C" fn(*mut c_void, u32, *const c_void)>,
+    add_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+    remove_buffer: Option<unsafe extern "C" fn(*mut c_void, *mut PwBuffer)>,
+    process: Option<unsafe extern "C" fn(*mut c_void)>,
+    drained: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+static EVENTS: StreamEvents = StreamEvents {
+    version: 0,
+    destroy: None,
+    state_changed: Some(on_state_changed),
+    control_info: None,
+    io_changed: None,
+    param_changed: None,
+    add_buffer: None,
+    remove_buffer: None,
+    process: Some(on_process),
+    drained: None,
+};
+
+/// Start of `struct pw_buffer`, the only part read.
+#[repr(C)]
+struct PwBuffer {
+    buffer: *mut SpaBuffer,
+}
+
+/// `struct spa_buffer`
+#[repr(C)]
+struct SpaBuffer {
+    n_metas: u32,
+    n_datas: u32,
+    metas: *mut c_void,
+    datas: *mut SpaData,
+}
+
+/// `struct spa_data`
+#[repr(C)]
+struct SpaData {
+    data_type: u32,
+    flags: u32,
+    fd: i64,
+    mapoffset: u32,
+    maxsize: u32,
+    data: *mut c_void,
+    chunk: *mut SpaChunk,
+}
+
+/// `struct spa_chunk`
+#[repr(C)]
+struct SpaChunk {
+    offset: u32,
+    size: u32,
+    stride: i32,
+    flags: i32,
+}
+
+/// The `EnumFormat` parameter asking for interleaved 32-bit floats at `rate`
+/// with `channels`, as the SPA POD `spa_format_audio_raw_build` would build.
+fn format_param(rate: u32, channels: u32) -> Vec<u64> {
+    // object of type Format (0x40003), id EnumFormat (3), then each property
+    // as key, flags and a pod of type Id (3) or Int (4) padded to 8 bytes
+    #[rustfmt::skip]
+    let words: [u32; 34] = [
+        128, 15, 0x40003, 3,
+        // media type: audio
+        1, 0, 4, 3, 1, 0,
+        // media subtype: raw
+        2, 0, 4, 3, 1, 0,
+        // audio format: F32_LE
+        0x10001, 0, 4, 3, 0x11b, 0,
+        // rate and channels, as Int
+        0x10003, 0, 4, 4, rate, 0,
+        0x10004, 0, 4, 4, channels, 0,
+    ];
+    // pods are 8-byte aligned, hence the words paired in native order
+    words
+        .chunks(2)
+        .map(|pair| {
+            let mut bytes = [0; 8];
+            bytes[..4].copy_from_slice(&pair[0].to_ne_bytes());
+            bytes[4..].copy_from_slice(&pair[1].to_ne_bytes());
+            u64::from_ne_bytes(bytes)
+        })
+        .collect()
+}
+
+/// State of the stream callbacks, on the main loop thread.
+struct Capture {
+    api: Api,
+    stream: *mut c_void,
+    channels: usize,
+    mix_channels: Vec<usize>,
+    downmix: Downmix,
+    mono: Vec<f32>,
+    processor: Processor,
+    output: Output,
+    dump: Option<TransitionDump>,
+    /// the stream went into the error state
+    failed: bool,
+}
+
+impl Capture {
+    /// Detect on interleaved samples.
+    fn process(&mut self, samples: &[f32]) {
+        let frames = samples.len() / self.channels;
+        self.mono.resize(frames, 0.0);
+        detect::downmix(
+            &samples[..frames * self.channels],
+            self.channels,
+            &self.mix_channels,
+            self.downmix,
+            &mut self.mono,
+        );
+        let output = &self.output;
+        self.processor.process_with(&self.mono, |last, switch| {
+            if switch.finished() {
+                shutdown::request();
+            }
+            output.print_status(last, switch);
+        });
+        if let Some(dump) = &mut self.dump {
+            dump.process(&self.mono, self.processor.is_on());
+        }
+    }
+}
+
+unsafe extern "C" fn on_state_changed(
+    data: *mut c_void,
+    _old: c_int,
+    state: c_int,
+    error: *const c_char,
+) {
+    let capture = &mut *(data as *mut Capture);
+    let name = CStr::from_ptr((capture.api.state_as_string)(state));
+    println!("PipeWire: stream {}", name.to_string_lossy());
+    if state == STATE_ERROR {
+        let error = if error.is_null() {
+            "unknown error".into()
+        } else {
+            CStr::from_ptr(error).to_string_lossy()
+        };
+        eprintln!("Error: PipeWire stream failed, {}", error);
+        capture.failed = true;
+        shutdown::request();
+    }
+}
+
+unsafe extern "C" fn on_process(data: *mut c_void) {
+    let capture = &mut *(data as *mut Capture);
+    let buffer = (capture.api.dequeue_buffer)(capture.stream);
+    if buffer.is_null() {
+        return;
+    }
+    let spa_buffer = &*(*buffer).buffer;
+    if spa_buffer.n_datas > 0 {
+        let spa_data = &*spa_buffer.datas;
+        if !spa_data.data.is_null() && !spa_data.chunk.is_null() {
+            let chunk = &*spa_data.chunk;
+            let offset = chunk.offset.min(spa_data.maxsize);
+            let size = chunk.size.min(spa_data.maxsize - offset) as usize;
+            let samples = slice::from_raw_parts(
+                (spa_data.data as *const u8).add(offset as usize) as *const f32,
+                size / 4,
+            );
+            capture.process(samples);
+        }
+    }
+    (capture.api.queue_buffer)(capture.stream, buffer);
+}
+
+/// Record from a PipeWire node and switch on signal.
+pub fn run(argv: Vec<String>) {
+    let argv = cli::parse(USAGE, "PipeWire", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    let target = args.flag_target.as_deref().unwrap_or("default");
+    switch_args.self_test(target);
+    if args.flag_channels == 0 {
+        eprintln!("--channels needs at least one channel");
+        process::exit(1);
+    }
+
+    let api = Api::load().unwrap_or_else(|e| {
+        eprintln!("Unable to record from PipeWire: {}", e);
+        process::exit(1);
+    });
+    let rate = args.flag_sample_rate;
+    let downmix = cli::downmix(&args.flag_downmix, args.flag_channels);
+    let output = switch_args.output();
+    let dump = switch_args.transition_dump(rate);
+    let mut detector = switch_args.detector(args.flag_window, rate);
+    // levels come per window rather than per quantum
+    let frame_len = switch_args.flag_analysis_ms.map_or(args.flag_window, |ms| {
+        common::time_to_frames(ms, rate) as usize
+    });
+    detector.set_frame_len(frame_len);
+    if switch_args.flag_print_config {
+        cli::print_config(&argv);
+        cli::config_line("pipewire.target", target);
+        cli::config_line("pipewire.rate", &rate.to_string());
+        cli::config_line("pipewire.channels", &args.flag_channels.to_string());
+        cli::config_line("pipewire.frame", &frame_len.to_string());
+    }
+    let stream_info = StreamInfo {
+        rate,
+        format: "float".to_string(),
+        channels: args.flag_channels as u32,
+    };
+    let (switch, worker) =
+        switch_args.start_switch(target, Some(&stream_info), &switch_args.channel_settings(1));
+
+    let mut properties = vec![
+        ("media.type", "Audio".to_string()),
+        ("media.category", "Capture".to_string()),
+        ("node.latency", format!("{}/{}", args.flag_window, rate)),
+    ];
+    if let Some(target) = &args.flag_target {
+        properties.push(("target.object", target.clone()));
+    }
+    if args.flag_capture_sink {
+        properties.push(("stream.capture.sink", "true".to_string()));
+    }
+
+    let name = CString::new("silentcmd").unwrap();
+    let mut capture = Box::new(Capture {
+        api,
+        stream: ptr::null_mut(),
+        channels: args.flag_channels,
+        mix_channels: (0..args.flag_channels).collect(),
+        downmix,
+        mono: Vec::new(),
+        processor: Processor::from_parts(detector, switch),
+        output,
+        dump,
+        failed: false,
+    });
+    let api = &capture.api;
+    let (main_loop, connected) = unsafe {
+        (api.init)(ptr::null_mut(), ptr::null_mut());
+        let main_loop = (api.main_loop_new)(ptr::null());
+        let props = (api.properties_new)(ptr::null());
+        for (key, value) in &properties {
+            let key = CString::new(*key).unwrap();
+            let value = CString::new(value.as_str()).expect("Invalid PipeWire property");
+            (api.properties_set)(props, key.as_ptr(), value.as_ptr());
+        }
+        // the stream takes the properties
+        let stream = (api.stream_new_simple)(
+            (api.main_loop_get_loop)(main_loop),
+            name.as_ptr(),
+            props,
+            &EVENTS,
+            &mut *capture as *mut Capture as *mut c_void,
+        );
+        capture.stream = stream;
+        let format = format_param(rate, args.flag_channels as u32);
+        let mut params = [format.as_ptr() as *const c_void];
+        let connected = !stream.is_null()
+            && (capture.api.stream_connect)(
+                stream,
+                DIRECTION_INPUT,
+                ID_ANY,
+                STREAM_FLAGS,
+                params.as_mut_ptr(),
+                1,
+            ) == 0;
+        (main_loop, connected)
+    };
+    eprintln!("Recording from PipeWire node: {}, at {} Hz", target, rate);
+
+    if connected {
+        // only quit from here, the loop being gone once run returns
+        let quit = capture.api.main_loop_quit;
+        let main_loop_addr = main_loop as usize;
+        let waiter = thread::spawn(move || {
+            shutdown::wait();
+            unsafe { quit(main_loop_addr as *mut c_void) };
+        });
+        unsafe { (capture.api.main_loop_run)(main_loop) };
+        waiter.join().ok();
+    } else {
+        eprintln!("Error: unable to connect a PipeWire stream to {}", target);
+        capture.failed = true;
+    }
+
+    unsafe {
+        if !capture.stream.is_null() {
+            (capture.api.stream_destroy)(capture.stream);
+        }
+        (capture.api.main_loop_destroy)(main_loop);
+        (capture.api.deinit)();
+    }
+    let Capture {
+        processor,
+        dump,
+        failed,
+        ..
+    } = *capture;
+    let detected = processor.switch().detected();
+    processor
+        .into_switch()
+        .shutdown(worker, !switch_args.flag_keep_on_exit);
+    // finalize the dumps before a possible exit
+    drop(dump);
+    if failed {
+        process::exit(1);
+    }
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
@@ -0,0 +1,290 @@
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::process;
+use std::ptr;
+
+use crate::action::StreamInfo;
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::dump::TransitionDump;
+use crate::dylib::Library;
+use crate::output::Output;
+use crate::processor::Processor;
+use crate::shutdown;
+
+const USAGE: &str = concat!(
+    "
+Silent Command for PulseAudio.
+
+Records from a PulseAudio source, also served by PipeWire, as mono mixed down
+by the server. libpulse-simple is loaded when starting, building needs no
+PulseAudio development files.
+
+Usage:
+  silentcmd-pulse <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [options]
+  silentcmd-pulse --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --source=<name>            PulseAudio source to record from, as listed by pactl list short
+                             sources, the default one if not given. The monitor source of a
+                             sink records what it plays, e.g. @DEFAULT_MONITOR@ for the
+                             default sink.
+  --server=<name>            PulseAudio server to connect to, the default one if not given.
+  --sample-rate=<hz>         Sample rate to record at, resampled by PulseAudio if the source
+                             runs at another [default: 48000].
+  --buffer-size=<frames>     Frames read at a time, each giving a level [default: 1024].
+  --threshold=<db>           Minimal signal level to turn on [default: -60.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 30]
+",
+    switch_options!()
+);
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_source: Option<String>,
+    flag_server: Option<String>,
+    flag_sample_rate: u32,
+    flag_buffer_size: usize,
+}
+
+/// Library of the simple API, with the version of its ABI.
+const LIBRARY: &str = "libpulse-simple.so.0";
+
+/// `PA_SAMPLE_FLOAT32LE` of `pa_sample_format_t`.
+const SAMPLE_FLOAT32LE: c_int = 5;
+/// `PA_STREAM_RECORD` of `pa_stream_direction_t`.
+const STREAM_RECORD: c_int = 2;
+
+/// `pa_sample_spec`
+#[repr(C)]
+struct SampleSpec {
+    format: c_int,
+    rate: u32,
+    channels: u8,
+}
+
+/// `pa_buffer_attr`, -1 leaving a value to the server.
+#[repr(C)]
+struct BufferAttr {
+    maxlength: u32,
+    tlength: u32,
+    prebuf: u32,
+    minreq: u32,
+    fragsize: u32,
+}
+
+type NewFn = unsafe extern "C" fn(
+    server: *const c_char,
+    name: *const c_char,
+    dir: c_int,
+    dev: *const c_char,
+    stream_name: *const c_char,
+    ss: *const SampleSpec,
+    map: *const c_void,
+    attr: *const BufferAttr,
+    error: *mut c_int,
+) -> *mut c_void;
+type ReadFn = unsafe extern "C" fn(
+    s: *mut c_void,
+    data: *mut c_void,
+    bytes: usize,
+    error: *mut c_int,
+) -> c_int;
+type FreeFn = unsafe extern "C" fn(s: *mut c_void);
+type StrerrorFn = unsafe extern "C" fn(error: c_int) -> *const c_char;
+
+/// Record stream of the PulseAudio simple API, as mono 32-bit floats.
+struct Simple {
+    stream: *mut c_void,
+    read: ReadFn,
+    free: FreeFn,
+    strerror: StrerrorFn,
+}
+
+impl Simple {
+    /// Connect to `server` and record from `source`, the defaults if None,
+    /// with fragments of `frames` to read them as they come.
+    fn open(
+        server: Option<&str>,
+        source: Option<&str>,
+        rate: u32,
+        frames: usize,
+    ) -> Result<Simple, Box<dyn Error>> {
+        let library = Library::open(LIBRARY)?;
+        // pa_strerror comes from libpulse, which libpulse-simple links to
+        let (new, read, free, strerror) = unsafe {
+            (
+                library.function::<NewFn>("pa_simple_new")?,
+                library.function::<ReadFn>("pa_simple_read")?,
+                library.function::<FreeFn>("pa_simple_free")?,
+                library.function::<StrerrorFn>("pa_strerror")?,
+            )
+        };
+
+        let server = server.map(CString::new).transpose()?;
+        let source = source.map(CString::new).transpose()?;
+        let name = CString::new("silentcmd")?;
+        let stream_name = CString::new("detection")?;
+        let spec = SampleSpec {
+            format: SAMPLE_FLOAT32LE,
+            rate,
+            channels: 1,
+        };
+        // without a fragment size, the server would deliver about 2 s at a time
+        let attr = BufferAttr {
+            maxlength: u32::MAX,
+            tlength: u32::MAX,
+            prebuf: u32::MAX,
+            minreq: u32::MAX,
+            fragsize: (frames * 4) as u32,
+        };
+        let mut error = 0;
+        let stream = unsafe {
+            new(
+                server
+                    .as_ref()
+                    .map_or(ptr::null(), |server| server.as_ptr()),
+                name.as_ptr(),
+                STREAM_RECORD,
+                source
+                    .as_ref()
+                    .map_or(ptr::null(), |source| source.as_ptr()),
+                stream_name.as_ptr(),
+                &spec,
+                ptr::null(),
+                &attr,
+                &mut error,
+            )
+        };
+        let simple = Simple {
+            stream,
+            read,
+            free,
+            strerror,
+        };
+        if stream.is_null() {
+            return Err(simple.error(error).into());
+        }
+        Ok(simple)
+    }
+
+    /// Fill `buf`, blocking until read.
+    fn read(&mut self, buf: &mut [f32]) -> Result<(), String> {
+        let mut error = 0;
+        let result = unsafe {
+            (self.read)(
+                self.stream,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len() * 4,
+                &mut error,
+            )
+        };
+        if result < 0 {
+            return Err(self.error(error));
+        }
+        Ok(())
+    }
+
+    fn error(&self, error: c_int) -> String {
+        let message = unsafe { CStr::from_ptr((self.strerror)(error)) };
+        message.to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for Simple {
+    fn drop(&mut self) {
+        if !self.stream.is_null() {
+            unsafe { (self.free)(self.stream) };
+        }
+    }
+}
+
+/// Record from a PulseAudio source and switch on signal.
+pub fn run(argv: Vec<String>) {
+    let argv = cli::parse(USAGE, "PulseAudio", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    let source = args.flag_source.as_deref().unwrap_or("default");
+    switch_args.self_test(source);
+
+    let mut simple = Simple::open(
+        args.flag_server.as_deref(),
+        args.flag_source.as_deref(),
+        args.flag_sample_rate,
+        args.flag_buffer_size,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Unable to record from PulseAudio source {}: {}", source, e);
+        process::exit(1);
+    });
+    eprintln!(
+        "Recording from PulseAudio source: {}, at {} Hz",
+        source, args.flag_sample_rate
+    );
+
+    let rate = args.flag_sample_rate;
+    let output = switch_args.output();
+    let mut dump = switch_args.transition_dump(rate);
+    let mut detector = switch_args.detector(args.flag_buffer_size, rate);
+    if let Some(ms) = switch_args.flag_analysis_ms {
+        detector.set_frame_len(common::time_to_frames(ms, rate) as usize);
+    }
+    if switch_args.flag_print_config {
+        cli::print_config(&argv);
+        cli::config_line("pulse.source", source);
+        cli::config_line("pulse.rate", &rate.to_string());
+        cli::config_line("pulse.buffer-size", &args.flag_buffer_size.to_string());
+    }
+    let stream = StreamInfo {
+        rate,
+        format: "float".to_string(),
+        channels: 1,
+    };
+    let (switch, worker) =
+        switch_args.start_switch(source, Some(&stream), &switch_args.channel_settings(1));
+    let mut processor = Processor::from_parts(detector, switch);
+
+    let mut buf = vec![0.0f32; args.flag_buffer_size];
+    let mut failure = None;
+    while !shutdown::requested() {
+        if let Err(e) = simple.read(&mut buf) {
+            failure = Some(e);
+            break;
+        }
+        process_buf(&buf, &mut processor, &output, &mut dump);
+    }
+
+    let detected = processor.switch().detected();
+    processor
+        .into_switch()
+        .shutdown(worker, !switch_args.flag_keep_on_exit);
+    // finalize the dumps before a possible exit
+    drop(dump);
+    if let Some(e) = failure {
+        eprintln!("Error: PulseAudio source {} stopped, {}", source, e);
+        process::exit(1);
+    }
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
+
+/// Detect on a buffer and print the status of each level.
+fn process_buf(
+    buf: &[f32],
+    processor: &mut Processor,
+    output: &Output,
+    dump: &mut Option<TransitionDump>,
+) {
+    processor.process_with(buf, |last, switch| {
+        if switch.finished() {
+            shutdown::request();
+        }
+        output.print_status(last, switch);
+    });
+    if let Some(dump) = dump {
+        dump.process(buf, processor.is_on());
+    }
+}
@@ -0,0 +1,751 @@
+use std::os::raw::c_void;
+use std::process;
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::action::StreamInfo;
+use crate::cli::{self, SwitchArgs};
+use crate::common;
+use crate::detect::{self, Downmix};
+use crate::dump::TransitionDump;
+use crate::output::Output;
+use crate::processor::Processor;
+use crate::shutdown;
+
+const USAGE: &str = concat!(
+    "
+Silent Command for WASAPI.
+
+Records what a render endpoint of Windows plays, through WASAPI loopback
+capture, to switch on playback activity. Endpoints deliver nothing while
+nothing plays, that time being detected as silence.
+
+Usage:
+  silentcmd-wasapi <cmd-on> [<cmd-off>] [--cmd-env=<var>]... [options]
+  silentcmd-wasapi --version
+
+Options:
+  -h --help                  Show this screen.
+  --version                  Show version.
+  --endpoint=<name>          Render endpoint to record, by its name in the Sound settings,
+                             the default one if not given.
+  --downmix=<mode>           Mix the channels of the endpoint down by avg or sum, or keep the
+                             mid (L+R)/2 or side (L-R)/2 of the first two [default: avg].
+  --window=<frames>          Frames per level, whatever the packets of the endpoint
+                             [default: 1024].
+  --threshold=<db>           Minimal signal level to turn on [default: -60.0]
+  --timeout=<s>              Amount of time without signal before off switch [default: 30]
+",
+    switch_options!()
+);
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_endpoint: Option<String>,
+    flag_downmix: String,
+    flag_window: usize,
+}
+
+/// How often the capture client is read.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Buffer of the stream, in 100 ns units: 200 ms.
+const BUFFER_DURATION: i64 = 2_000_000;
+
+type HResult = i32;
+
+/// `GUID`
+#[repr(C)]
+struct Guid {
+    data1: u32,
+    data2: u16,
+    data3: u16,
+    data4: [u8; 8],
+}
+
+impl Guid {
+    const fn new(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Guid {
+        Guid {
+            data1,
+            data2,
+            data3,
+            data4,
+        }
+    }
+
+    fn eq(&self, other: &Guid) -> bool {
+        (self.data1, self.data2, self.data3, self.data4)
+            == (other.data1, other.data2, other.data3, other.data4)
+    }
+}
+
+const CLSID_MM_DEVICE_ENUMERATOR: Guid = Guid::new(
+    0xbcde0395,
+    0xe52f,
+    0x467c,
+    [0x8e, 0x3d, 0xc4, 0x57, 0x92, 0x91, 0x69, 0x2e],
+);
+const IID_IMM_DEVICE_ENUMERATOR: Guid = Guid::new(
+    0xa95664d2,
+    0x9614,
+    0x4f35,
+    [0xa7, 0x46, 0xde, 0x8d, 0xb6, 0x36, 0x17, 0xe6],
+);
+const IID_IAUDIO_CLIENT: Guid = Guid::new(
+    0x1cb9ad4c,
+    0xdbfa,
+    0x4c32,
+    [0xb1, 0x78, 0xc2, 0xf5, 0x68, 0xa7, 0x03, 0xb2],
+);
+const IID_IAUDIO_CAPTURE_CLIENT: Guid = Guid::new(
+    0xc8adbd64,
+    0xe71e,
+    0x48a0,
+    [0xa4, 0xde, 0x18, 0x5c, 0x39, 0x5c, 0xd3, 0x17],
+);
+/// `KSDATAFORMAT_SUBTYPE_IEEE_FLOAT`
+const SUBTYPE_FLOAT: Guid = Guid::new(
+    0x0000_0003,
+    0x0000,
+    0x0010,
+    [0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71],
+);
+/// `KSDATAFORMAT_SUBTYPE_PCM`
+const SUBTYPE_PCM: Guid = Guid::new(
+    0x0000_0001,
+    0x0000,
+    0x0010,
+    [0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71],
+);
+
+/// `PROPERTYKEY`
+#[repr(C)]
+struct PropertyKey {
+    fmtid: Guid,
+    pid: u32,
+}
+
+/// `PKEY_Device_FriendlyName`
+const DEVICE_FRIENDLY_NAME: PropertyKey = PropertyKey {
+    fmtid: Guid::new(
+        0xa45c254e,
+        0xdf1c,
+        0x4efd,
+        [0x80, 0x20, 0x67, 0xd1, 0x46, 0xa8, 0x50, 0xe0],
+    ),
+    pid: 14,
+};
+
+const COINIT_MULTITHREADED: u32 = 0;
+const CLSCTX_ALL: u32 = 0x17;
+/// `eRender`
+const DATA_FLOW_RENDER: i32 = 0;
+/// `eConsole`
+const ROLE_CONSOLE: i32 = 0;
+const DEVICE_STATE_ACTIVE: u32 = 1;
+const STGM_READ: u32 = 0;
+const VT_LPWSTR: u16 = 31;
+const AUDCLNT_SHAREMODE_SHARED: i32 = 0;
+const AUDCLNT_STREAMFLAGS_LOOPBACK: u32 = 0x0002_0000;
+const AUDCLNT_BUFFERFLAGS_SILENT: u32 = 2;
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// `PROPVARIANT`, its union being 16 bytes on 64-bit Windows and 8 on 32-bit.
+#[repr(C)]
+struct PropVariant {
+    vt: u16,
+    reserved: [u16; 3],
+    data: [usize; 2],
+}
+
+/// `WAVEFORMATEX`, packed as in mmreg.h.
+#[repr(C, packed)]
+struct WaveFormat {
+    format_tag: u16,
+    channels: u16,
+    samples_per_sec: u32,
+    avg_bytes_per_sec: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+    extra_size: u16,
+}
+
+/// `WAVEFORMATEXTENSIBLE`, packed as in mmreg.h.
+#[repr(C, packed)]
+struct WaveFormatExtensible {
+    format: WaveFormat,
+    valid_bits_per_sample: u16,
+    channel_mask: u32,
+    sub_format: Guid,
+}
+
+#[link(name = "ole32")]
+extern "system" {
+    fn CoInitializeEx(reserved: *mut c_void, flags: u32) -> HResult;
+    fn CoUninitialize();
+    fn CoCreateInstance(
+        clsid: *const Guid,
+        outer: *mut c_void,
+        context: u32,
+        iid: *const Guid,
+        object: *mut *mut c_void,
+    ) -> HResult;
+    fn CoTaskMemFree(pointer: *mut c_void);
+    fn PropVariantClear(value: *mut PropVariant) -> HResult;
+}
+
+/// Methods of `IUnknown`, starting every vtable.
+#[repr(C)]
+struct UnknownVtbl {
+    query_interface: usize,
+    add_ref: usize,
+    release: unsafe extern "system" fn(this: *mut c_void) -> u32,
+}
+
+#[repr(C)]
+struct DeviceEnumeratorVtbl {
+    unknown: UnknownVtbl,
+    enum_audio_endpoints: unsafe extern "system" fn(
+        this: *mut c_void,
+        data_flow: i32,
+        state_mask: u32,
+        devices: *mut *mut c_void,
+    ) -> HResult,
+    get_default_audio_endpoint: unsafe extern "system" fn(
+        this: *mut c_void,
+        data_flow: i32,
+        role: i32,
+        device: *mut *mut c_void,
+    ) -> HResult,
+}
+
+#[repr(C)]
+struct DeviceCollectionVtbl {
+    unknown: UnknownVtbl,
+    get_count: unsafe extern "system" fn(this: *mut c_void, count: *mut u32) -> HResult,
+    item: unsafe extern "system" fn(
+        this: *mut c_void,
+        index: u32,
+        device: *mut *mut c_void,
+    ) -> HResult,
+}
+
+#[repr(C)]
+struct DeviceVtbl {
+    unknown: UnknownVtbl,
+    activate: unsafe extern "system" fn(
+        this: *mut c_void,
+        iid: *const Guid,
+        context: u32,
+        params: *mut PropVariant,
+        interface: *mut *mut c_void,
+    ) -> HResult,
+    open_property_store: unsafe extern "system" fn(
+        this: *mut c_void,
+        access: u32,
+        store: *mut *mut c_void,
+    ) -> HResult,
+}
+
+#[repr(C)]
+struct PropertyStoreVtbl {
+    unknown: UnknownVtbl,
+    get_count: usize,
+    get_at: usize,
+    get_value: unsafe extern "system" fn(
+        this: *mut c_void,
+        key: *const PropertyKey,
+        value: *mut PropVariant,
+    ) -> HResult,
+}
+
+#[repr(C)]
+struct AudioClientVtbl {
+    unknown: UnknownVtbl,
+    initialize: unsafe extern "system" fn(
+        this: *mut c_void,
+        share_mode: i32,
+        stream_flags: u32,
+        buffer_duration: i64,
+        periodicity: i64,
+        format: *const WaveFormat,
+        session: *const Guid,
+    ) -> HResult,
+    get_buffer_size: usize,
+    get_stream_latency: usize,
+    get_current_padding: usize,
+    is_format_supported: usize,
+    get_mix_format:
+        unsafe extern "system" fn(this: *mut c_void, format: *mut *mut WaveFormat) -> HResult,
+    get_device_period: usize,
+    start: unsafe extern "system" fn(this: *mut c_void) -> HResult,
+    stop: unsafe extern "system" fn(this: *mut c_void) -> HResult,
+    reset: usize,
+    set_event_handle: usize,
+    get_service: unsafe extern "system" fn(
+        this: *mut c_void,
+        iid: *const Guid,
+        service: *mut *mut c_void,
+    ) -> HResult,
+}
+
+#[repr(C)]
+struct CaptureClientVtbl {
+    unknown: UnknownVtbl,
+    get_buffer: unsafe extern "system" fn(
+        this: *mut c_void,
+        data: *mut *mut u8,
+        frames: *mut u32,
+        flags: *mut u32,
+        device_position: *mut u64,
+        counter_position: *mut u64,
+    ) -> HResult,
+    release_buffer: unsafe extern "system" fn(this: *mut c_void, frames: u32) -> HResult,
+    get_next_packet_size: unsafe extern "system" fn(this: *mut c_void, frames: *mut u32) -> HResult,
+}
+
+/// COM interface with the vtable `V`, released when dropped.
+struct Interface<V> {
+    object: *mut *const V,
+}
+
+impl<V> Interface<V> {
+    /// Take the interface returned by a call of `HRESULT`.
+    unsafe fn take(hr: HResult, object: *mut c_void, call: &str) -> Result<Interface<V>, String> {
+        check(hr, call)?;
+        Ok(Interface {
+            object: object as *mut *const V,
+        })
+    }
+
+    fn this(&self) -> *mut c_void {
+        self.object as *mut c_void
+    }
+
+    fn vtbl(&self) -> &V {
+        unsafe { &**self.object }
+    }
+}
+
+impl<V> Drop for Interface<V> {
+    fn drop(&mut self) {
+        // every vtable starts with those of IUnknown
+        unsafe {
+            let unknown = &*(*self.object as *const UnknownVtbl);
+            (unknown.release)(self.this());
+        }
+    }
+}
+
+/// Error of a call returning `hr`.
+fn check(hr: HResult, call: &str) -> Result<(), String> {
+    if hr >= 0 {
+        Ok(())
+    } else {
+        Err(format!("{} failed, 0x{:08x}", call, hr as u32))
+    }
+}
+
+/// Friendly name of `device`, as shown in the Sound settings.
+fn device_name(device: &Interface<DeviceVtbl>) -> Result<String, String> {
+    unsafe {
+        let mut store = ptr::null_mut();
+        let hr = (device.vtbl().open_property_store)(device.this(), STGM_READ, &mut store);
+        let store: Interface<PropertyStoreVtbl> = Interface::take(hr, store, "OpenPropertyStore")?;
+        let mut value = PropVariant {
+            vt: 0,
+            reserved: [0; 3],
+            data: [0; 2],
+        };
+        let hr = (store.vtbl().get_value)(store.this(), &DEVICE_FRIENDLY_NAME, &mut value);
+        check(hr, "GetValue")?;
+        let mut name = String::new();
+        if value.vt == VT_LPWSTR && value.data[0] != 0 {
+            let wide = value.data[0] as *const u16;
+            let len = (0..).take_while(|&i| *wide.add(i) != 0).count();
+            name = String::from_utf16_lossy(slice::from_raw_parts(wide, len));
+        }
+        PropVariantClear(&mut value);
+        Ok(name)
+    }
+}
+
+/// Render endpoint named `name`, or the default one.
+fn find_endpoint(
+    enumerator: &Interface<DeviceEnumeratorVtbl>,
+    name: Option<&str>,
+) -> Result<Interface<DeviceVtbl>, String> {
+    unsafe {
+        let Some(name) = name else {
+            let mut device = ptr::null_mut();
+            let hr = (enumerator.vtbl().get_default_audio_endpoint)(
+                enumerator.this(),
+                DATA_FLOW_RENDER,
+                ROLE_CONSOLE,
+                &mut device,
+            );
+            return Interface::take(hr, device, "GetDefaultAudioEndpoint");
+        };
+        let mut devices = ptr::null_mut();
+        let hr = (enumerator.vtbl().enum_audio_endpoints)(
+            enumerator.this(),
+            DATA_FLOW_RENDER,
+            DEVICE_STATE_ACTIVE,
+            &mut devices,
+        );
+        let devices: Interface<DeviceCollectionVtbl> =
+            Interface::take(hr, devices, "EnumAudioEndpoints")?;
+        let mut count = 0;
+        check(
+            (devices.vtbl().get_count)(devices.this(), &mut count),
+            "GetCount",
+        )?;
+        let mut names = Vec::new();
+        for index in 0..count {
+            let mut device = ptr::null_mut();
+            let hr = (devices.vtbl().item)(devices.this(), index, &mut device);
+            let device: Interface<DeviceVtbl> = Interface::take(hr, device, "Item")?;
+            let device_name = device_name(&device)?;
+            if device_name == name {
+                return Ok(device);
+            }
+            names.push(device_name);
+        }
+        Err(format!(
+            "no render endpoint named {}, the endpoints are: {}",
+            name,
+            names.join(", ")
+        ))
+    }
+}
+
+/// Sample type of a mix format.
+#[derive(Clone, Copy)]
+enum SampleType {
+    F32,
+    S16,
+}
+
+impl SampleType {
+    /// Type of `format`, None if not handled.
+    unsafe fn of(format: *const WaveFormat) -> Option<SampleType> {
+        let WaveFormat {
+            format_tag,
+            bits_per_sample,
+            extra_size,
+            ..
+        } = ptr::read_unaligned(format);
+        let float = match format_tag {
+            WAVE_FORMAT_IEEE_FLOAT => true,
+            WAVE_FORMAT_PCM => false,
+            WAVE_FORMAT_EXTENSIBLE if extra_size >= 22 => {
+                let sub_format =
+                    ptr::read_unaligned(format as *const WaveFormatExtensible).sub_format;
+                if sub_format.eq(&SUBTYPE_FLOAT) {
+                    true
+                } else if sub_format.eq(&SUBTYPE_PCM) {
+                    false
+                } else {
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+        match (float, bits_per_sample) {
+            (true, 32) => Some(SampleType::F32),
+            (false, 16) => Some(SampleType::S16),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SampleType::F32 => "float",
+            SampleType::S16 => "s16",
+        }
+    }
+}
+
+/// Mix format of the endpoint, and the stream set up with it.
+struct Stream {
+    client: Interface<AudioClientVtbl>,
+    capture: Interface<CaptureClientVtbl>,
+    rate: u32,
+    channels: usize,
+    sample_type: SampleType,
+}
+
+/// Set up loopback capture of `device` in its mix format.
+unsafe fn open_stream(device: &Interface<DeviceVtbl>) -> Result<Stream, String> {
+    let mut client = ptr::null_mut();
+    let hr = (device.vtbl().activate)(
+        device.this(),
+        &IID_IAUDIO_CLIENT,
+        CLSCTX_ALL,
+        ptr::null_mut(),
+        &mut client,
+    );
+    let client: Interface<AudioClientVtbl> = Interface::take(hr, client, "Activate")?;
+    let mut format = ptr::null_mut();
+    check(
+        (client.vtbl().get_mix_format)(client.this(), &mut format),
+        "GetMixFormat",
+    )?;
+    let WaveFormat {
+        samples_per_sec,
+        channels,
+        ..
+    } = ptr::read_unaligned(format);
+    let sample_type = SampleType::of(format);
+    let initialized = match sample_type {
+        Some(_) => check(
+            (client.vtbl().initialize)(
+                client.this(),
+                AUDCLNT_SHAREMODE_SHARED,
+                AUDCLNT_STREAMFLAGS_LOOPBACK,
+                BUFFER_DURATION,
+                0,
+                format,
+                ptr::null(),
+            ),
+            "Initialize",
+        ),
+        None => Err("unsupported mix format".to_string()),
+    };
+    CoTaskMemFree(format as *mut c_void);
+    initialized?;
+    let mut capture = ptr::null_mut();
+    let hr = (client.vtbl().get_service)(client.this(), &IID_IAUDIO_CAPTURE_CLIENT, &mut capture);
+    let capture = Interface::take(hr, capture, "GetService")?;
+    Ok(Stream {
+        client,
+        capture,
+        rate: samples_per_sec,
+        channels: usize::from(channels),
+        sample_type: sample_type.unwrap(),
+    })
+}
+
+/// Detection on the frames of the stream.
+struct Capture {
+    channels: usize,
+    mix_channels: Vec<usize>,
+    downmix: Downmix,
+    /// samples of the last packet, as floats
+    samples: Vec<f32>,
+    mono: Vec<f32>,
+    processor: Processor,
+    output: Output,
+    dump: Option<TransitionDump>,
+}
+
+impl Capture {
+    /// Detect on the interleaved `samples`.
+    fn process(&mut self) {
+        let frames = self.samples.len() / self.channels;
+        self.mono.resize(frames, 0.0);
+        detect::downmix(
+            &self.samples[..frames * self.channels],
+            self.channels,
+            &self.mix_channels,
+            self.downmix,
+            &mut self.mono,
+        );
+        let output = &self.output;
+        self.processor.process_with(&self.mono, |last, switch| {
+            if switch.finished() {
+                shutdown::request();
+            }
+            output.print_status(last, switch);
+        });
+        if let Some(dump) = &mut self.dump {
+            dump.process(&self.mono, self.processor.is_on());
+        }
+    }
+
+    /// Detect on `frames` of silence.
+    fn process_silence(&mut self, frames: usize) {
+        self.samples.clear();
+        self.samples.resize(frames * self.channels, 0.0);
+        self.process();
+    }
+}
+
+/// Read the packets of `stream` until a shutdown is requested.
+unsafe fn capture_loop(stream: &Stream, capture: &mut Capture) -> Result<(), String> {
+    let client = &stream.client;
+    check((client.vtbl().start)(client.this()), "Start")?;
+    let reader = &stream.capture;
+    // frames not delivered while nothing plays are made up as silence
+    let mut last_packet = Instant::now();
+    while !shutdown::requested() {
+        thread::sleep(POLL_INTERVAL);
+        let mut received = false;
+        loop {
+            let mut packet = 0;
+            check(
+                (reader.vtbl().get_next_packet_size)(reader.this(), &mut packet),
+                "GetNextPacketSize",
+            )?;
+            if packet == 0 {
+                break;
+            }
+            let (mut data, mut frames, mut flags) = (ptr::null_mut(), 0, 0);
+            check(
+                (reader.vtbl().get_buffer)(
+                    reader.this(),
+                    &mut data,
+                    &mut frames,
+                    &mut flags,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                ),
+                "GetBuffer",
+            )?;
+            let len = frames as usize * stream.channels;
+            capture.samples.clear();
+            if flags & AUDCLNT_BUFFERFLAGS_SILENT != 0 || data.is_null() {
+                capture.samples.resize(len, 0.0);
+            } else {
+                match stream.sample_type {
+                    SampleType::F32 => capture
+                        .samples
+                        .extend_from_slice(slice::from_raw_parts(data as *const f32, len)),
+                    SampleType::S16 => capture.samples.extend(
+                        slice::from_raw_parts(data as *const i16, len)
+                            .iter()
+                            .map(|&sample| f32::from(sample) / 32768.0),
+                    ),
+                }
+            }
+            check(
+                (reader.vtbl().release_buffer)(reader.this(), frames),
+                "ReleaseBuffer",
+            )?;
+            capture.process();
+            received = true;
+        }
+        let now = Instant::now();
+        if received {
+            last_packet = now;
+        } else if now - last_packet >= POLL_INTERVAL * 2 {
+            let silent = now - last_packet;
+            capture.process_silence(
+                common::time_to_frames(silent.as_millis() as f32, stream.rate) as usize,
+            );
+            last_packet = now;
+        }
+    }
+    (client.vtbl().stop)(client.this());
+    Ok(())
+}
+
+/// Record what a render endpoint plays and switch on signal.
+pub fn run(argv: Vec<String>) {
+    let argv = cli::parse(USAGE, "WASAPI", argv);
+    let args: Args = cli::args(&argv);
+    let switch_args: SwitchArgs = cli::args(&argv);
+    shutdown::install(switch_args.max_runtime());
+    let endpoint_name = args.flag_endpoint.as_deref().unwrap_or("default");
+    switch_args.self_test(endpoint_name);
+
+    unsafe {
+        check(
+            CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED),
+            "CoInitializeEx",
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Unable to record from WASAPI: {}", e);
+            process::exit(1);
+        });
+    }
+    let opened = unsafe {
+        let mut enumerator = ptr::null_mut();
+        let hr = CoCreateInstance(
+            &CLSID_MM_DEVICE_ENUMERATOR,
+            ptr::null_mut(),
+            CLSCTX_ALL,
+            &IID_IMM_DEVICE_ENUMERATOR,
+            &mut enumerator,
+        );
+        Interface::take(hr, enumerator, "CoCreateInstance")
+            .and_then(|enumerator| find_endpoint(&enumerator, args.flag_endpoint.as_deref()))
+            .and_then(|device| open_stream(&device))
+    };
+    let stream = opened.unwrap_or_else(|e| {
+        eprintln!(
+            "Unable to record from WASAPI endpoint {}: {}",
+            endpoint_name, e
+        );
+        process::exit(1);
+    });
+
+    let rate = stream.rate;
+    let channels = stream.channels;
+    let downmix = cli::downmix(&args.flag_downmix, channels);
+    let output = switch_args.output();
+    let dump = switch_args.transition_dump(rate);
+    let mut detector = switch_args.detector(args.flag_window, rate);
+    // levels come per window rather than per packet
+    let frame_len = switch_args.flag_analysis_ms.map_or(args.flag_window, |ms| {
+        common::time_to_frames(ms, rate) as usize
+    });
+    detector.set_frame_len(frame_len);
+    if switch_args.flag_print_config {
+        cli::print_config(&argv);
+        cli::config_line("wasapi.endpoint", endpoint_name);
+        cli::config_line("wasapi.rate", &rate.to_string());
+        cli::config_line("wasapi.format", stream.sample_type.name());
+        cli::config_line("wasapi.channels", &channels.to_string());
+        cli::config_line("wasapi.frame", &frame_len.to_string());
+    }
+    let stream_info = StreamInfo {
+        rate,
+        format: stream.sample_type.name().to_string(),
+        channels: channels as u32,
+    };
+    let (switch, worker) = switch_args.start_switch(
+        endpoint_name,
+        Some(&stream_info),
+        &switch_args.channel_settings(1),
+    );
+
+    let mut capture = Capture {
+        channels,
+        mix_channels: (0..channels).collect(),
+        downmix,
+        samples: Vec::new(),
+        mono: Vec::new(),
+        processor: Processor::from_parts(detector, switch),
+        output,
+        dump,
+    };
+    eprintln!(
+        "Recording from WASAPI endpoint: {}, at {} Hz, {} channel(s)",
+        endpoint_name, rate, channels
+    );
+    let result = unsafe { capture_loop(&stream, &mut capture) };
+    drop(stream);
+    unsafe { CoUninitialize() };
+
+    let Capture {
+        processor, dump, ..
+    } = capture;
+    let detected = processor.switch().detected();
+    processor
+        .into_switch()
+        .shutdown(worker, !switch_args.flag_keep_on_exit);
+    // finalize the dumps before a possible exit
+    drop(dump);
+    if let Err(e) = result {
+        eprintln!(
+            "Error: unable to record from WASAPI endpoint {}, {}",
+            endpoint_name, e
+        );
+        process::exit(1);
+    }
+    cli::detect_exit(switch_args.flag_detect_exit_code, detected);
+}
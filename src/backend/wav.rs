@@ -0,0 +1,437 @@
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::process;
+
+use crate::cli;
+use crate::common;
+use crate::detect::{self, Detector};
+use crate::filter::Tilt;
+use crate::spectrogram::Spectrogram;
+use crate::wav_file::WavFile;
+
+const USAGE: &str = "
+Silent Command for WAV file.
+
+Usage:
+  silentcmd-wav <file>... [options]
+  silentcmd-wav --version
+
+Options:
+  -h --help             Show this screen.
+  --version             Show version.
+  <file>                WAV input files, read in order as one timeline. They need the same
+                        sample rate and channels, sample formats may differ.
+  --window=<samples>    Window size in samples [default: 1024].
+  --attack=<ms>         Envelope attack time in milliseconds.
+  --release=<ms>        Envelope release time in milliseconds.
+  --detector=<kind>     Level measured over the window: rms, mav for the mean absolute
+                        value, or peak [default: rms].
+  --band-pass=<hz:hz>   Keep only this band before detection, e.g. 300:3400, with 12 dB per
+                        octave slopes. Applies before --tilt.
+  --speech              Preset for voice detection, the same as --band-pass=300:3400 with
+                        an attack of 10 ms and a release of 200 ms, any of them given
+                        explicitly taking precedence.
+  --tilt=<db>           Tilt the spectrum by this many dB per octave before detection,
+                        positive to emphasize highs, negative for lows.
+  --decimate=<n>        Detect on one sample out of <n>, each averaging the <n> samples it
+                        replaces [default: 1].
+  --gate-floor=<db>     Zero the samples below this level before detection, after tilt and
+                        decimation, so that steady hiss does not add to the level.
+  --debug-audio=<file>  Write the mono stream detection sees to this WAV file.
+  --integration=<s>     Print a running RMS integrating the window levels with this time constant.
+  --level-unit=<unit>   Printed level: db, linear envelope value or both [default: db].
+  --output=<mode>       Levels on stdout as text, or raw-f32 for the linear envelope value
+                        of each window as a little-endian 32-bit float, for piping into
+                        another tool without parsing. Raw output ignores --level-unit and
+                        leaves out the peak-hold and clip ratio [default: text].
+  --output-interval=<s>
+                        Print the level of at most one window per interval of the file,
+                        the first one of each, text or raw, to keep long files readable.
+                        The average and the other outputs still take every window
+                        [default: 0].
+  --peak-hold=<s>       Print the maximum level over this hold time after each level,
+                        decaying by 20 dB/s afterward.
+  --clip-ratio          Print the fraction of samples at full scale after each level.
+  --threshold=<db>      Level counting as detected for --detect-exit-code [default: -60.0].
+  --threshold-rel-peak=<db>
+                        Threshold relative to the sample peak of the file instead, e.g. -20.
+                        The peak is tracked while reading, as the threshold only applies
+                        once the whole file is read: this costs no second pass.
+  --labels=<file>       Write the segments of windows reaching the threshold to this
+                        file, as Audacity labels: start, end (s) and label, tab separated.
+  --detect-exit-code    Exit with status 2 if no window reaches the threshold, 0 otherwise.
+  --spectrogram=<png>   Draw the spectrogram of the mono signal before filtering to this PNG
+                        file, a column per window, with the windows reaching the threshold
+                        marked green below it and the band-pass edges as dotted lines.
+  --downmix=<mode>      Mix the channels down by avg or sum, or keep the mid (L+R)/2 or
+                        side (L-R)/2 of the first two channels [default: avg].
+  --analyze-channel=<n>
+                        Detect on this channel only, from 1, without any mixdown.
+  --per-channel         Also report average, min and max level of each channel.
+  --alsa-args           Print the silentcmd-alsa options capturing in the format of the file,
+                        then exit.
+  --pause               Wait for enter/return before exiting, when run from a terminal.
+
+Environment:
+  Options not given on the command line can be set as SILENTCMD_<OPTION> variables,
+  e.g. SILENTCMD_WINDOW=2048 for the window, or SILENTCMD_SPEECH=1 to turn on a flag.
+  The command line takes precedence, then the environment, then the defaults.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_file: Vec<String>,
+    flag_window: usize,
+    flag_attack: Option<f32>,
+    flag_release: Option<f32>,
+    flag_detector: String,
+    flag_band_pass: Option<String>,
+    flag_speech: bool,
+    flag_tilt: Option<f32>,
+    flag_decimate: usize,
+    flag_gate_floor: Option<f32>,
+    flag_debug_audio: Option<String>,
+    flag_integration: Option<f32>,
+    flag_level_unit: String,
+    flag_output: String,
+    flag_output_interval: f32,
+    flag_peak_hold: Option<f32>,
+    flag_clip_ratio: bool,
+    flag_threshold: f32,
+    flag_threshold_rel_peak: Option<f32>,
+    flag_labels: Option<String>,
+    flag_detect_exit_code: bool,
+    flag_spectrogram: Option<String>,
+    flag_downmix: String,
+    flag_analyze_channel: Option<usize>,
+    flag_per_channel: bool,
+    flag_alsa_args: bool,
+    flag_pause: bool,
+}
+
+/// Level statistics over the windows of one channel.
+struct ChannelStats {
+    sum: f32,
+    min: f32,
+    max: f32,
+    count: usize,
+}
+
+impl ChannelStats {
+    fn new() -> ChannelStats {
+        ChannelStats {
+            sum: 0.0,
+            min: f32::INFINITY,
+            max: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, level: f32) {
+        self.sum += level;
+        self.min = self.min.min(level);
+        self.max = self.max.max(level);
+        self.count += 1;
+    }
+}
+
+/// `silentcmd-alsa` options recording in the sample rate, depth and channels of `spec`.
+fn alsa_args(spec: hound::WavSpec) -> String {
+    // float samples are captured as 32-bit integers
+    let bits = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 16) => 16,
+        (hound::SampleFormat::Int, 24) => 24,
+        _ => 32,
+    };
+    let channels = (1..=spec.channels)
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "--sample-rate={} --bits={} --channels={}",
+        spec.sample_rate, bits, channels
+    )
+}
+
+/// Runs of consecutive windows of `window` frames reaching `threshold_db`,
+/// as start and end frames, the last window ending at `frames`.
+fn segments(
+    levels: &[f32],
+    threshold_db: f32,
+    window: usize,
+    frames: usize,
+) -> Vec<(usize, usize)> {
+    let mut segments: Vec<(usize, usize)> = Vec::new();
+    for (i, &level) in levels.iter().enumerate() {
+        if common::to_db(level) < threshold_db {
+            continue;
+        }
+        let (start, end) = (i * window, ((i + 1) * window).min(frames));
+        match segments.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => segments.push((start, end)),
+        }
+    }
+    segments
+}
+
+/// Write segments as an Audacity label track.
+fn write_labels(path: &str, segments: &[(usize, usize)], sample_rate: u32) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let rate = f64::from(sample_rate);
+    for &(start, end) in segments {
+        writeln!(
+            file,
+            "{:.6}\t{:.6}\tsignal",
+            start as f64 / rate,
+            end as f64 / rate
+        )?;
+    }
+    file.flush()
+}
+
+/// Open a WAV file, exiting if unsupported.
+fn open(path: &str) -> WavFile {
+    WavFile::open(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path, e);
+        process::exit(1);
+    })
+}
+
+/// Open the WAV files, exiting unless they share the sample rate and channels of the first.
+fn open_readers(paths: &[String]) -> Vec<WavFile> {
+    let readers: Vec<_> = paths.iter().map(|path| open(path)).collect();
+    let first = readers[0].spec();
+    for (path, reader) in paths.iter().zip(&readers).skip(1) {
+        let spec = reader.spec();
+        if spec.sample_rate != first.sample_rate || spec.channels != first.channels {
+            eprintln!(
+                "{}: {} Hz, {} channel(s), expected {} Hz, {} channel(s) as {}",
+                path, spec.sample_rate, spec.channels, first.sample_rate, first.channels, paths[0]
+            );
+            process::exit(1);
+        }
+    }
+    readers
+}
+
+/// Print the level of each window of WAV files, then the average.
+pub fn run(argv: Vec<String>) {
+    let args: Args = cli::args(&cli::parse(USAGE, "WAV", argv));
+    let unit = cli::level_unit(&args.flag_level_unit);
+    let raw = match args.flag_output.as_str() {
+        "text" => false,
+        "raw-f32" => true,
+        mode => {
+            eprintln!("Unknown output mode: {}", mode);
+            process::exit(1);
+        }
+    };
+
+    if args.flag_alsa_args {
+        println!("{}", alsa_args(open(&args.arg_file[0]).spec()));
+        return;
+    }
+
+    for path in &args.arg_file {
+        eprintln!("Detecting signal from file: {}", path);
+    }
+    eprintln!("Window size: {} samples", args.flag_window);
+
+    let mut readers = open_readers(&args.arg_file);
+    for reader in &readers {
+        eprintln!("Spec: {:?}", reader.spec());
+    }
+    let spec = readers[0].spec();
+
+    let channels = spec.channels as usize;
+    let downmix = cli::downmix(&args.flag_downmix, channels);
+    let mix_channels: Vec<usize> = (0..channels).collect();
+    if let Some(channel) = args.flag_analyze_channel {
+        if channel == 0 || channel > channels {
+            eprintln!("Channel {} out of the {} of the file(s)", channel, channels);
+            process::exit(1);
+        }
+    }
+    let (attack, release) =
+        cli::speech_envelope(args.flag_speech, args.flag_attack, args.flag_release);
+    let envelope = common::envelope_frames(attack, release, spec.sample_rate);
+
+    let mut detector = Detector::new(args.flag_window, envelope);
+    detector.set_measure(cli::measure(&args.flag_detector));
+    if let Some(band_pass) =
+        cli::band_pass(args.flag_speech, &args.flag_band_pass, spec.sample_rate)
+    {
+        detector.set_band_pass(band_pass);
+    }
+    if let Some(tilt) = args.flag_tilt {
+        detector.set_tilt(Tilt::new(tilt, spec.sample_rate));
+    }
+    if args.flag_decimate > 1 {
+        detector.set_decimation(args.flag_decimate);
+    }
+    if let Some(db) = args.flag_gate_floor {
+        detector.set_gate_floor(db);
+    }
+    if let Some(path) = &args.flag_debug_audio {
+        detector
+            .set_debug_audio(path, spec.sample_rate)
+            .expect("Unable to create debug audio file");
+    }
+    if let Some(s) = args.flag_integration {
+        detector.set_integration(common::time_to_frames(s * 1000.0, spec.sample_rate));
+    }
+    if let Some(hold) = args.flag_peak_hold {
+        detector.set_peak_hold(hold, spec.sample_rate, false);
+    }
+    let mut spectrogram = args
+        .flag_spectrogram
+        .as_ref()
+        .map(|_| Spectrogram::new(spec.sample_rate));
+    let mut channel_detectors = vec![detector.clone(); channels];
+    let mut channel_buf = vec![0.0f32; args.flag_window];
+    let mut channel_stats: Vec<ChannelStats> = (0..channels).map(|_| ChannelStats::new()).collect();
+
+    // raw levels are flushed after each block, for a reader following them
+    let mut raw_out = raw.then(|| io::stdout().lock());
+    let mut total = Vec::new();
+    let mut frames = 0;
+    let mut peak = 0.0f32;
+    let mut current = 0;
+    let interval_frames = (args.flag_output_interval * spec.sample_rate as f32) as usize;
+    let mut next_output = 0;
+    loop {
+        // windows span file boundaries, as if the files were concatenated
+        let mut buf = Vec::with_capacity(args.flag_window * channels);
+        while current < readers.len() && buf.len() < args.flag_window * channels {
+            let count = args.flag_window * channels - buf.len();
+            let samples = readers[current].read(count);
+            let done = samples.len() < count;
+            buf.extend(samples);
+            if done {
+                current += 1;
+                if let Some(path) = args.arg_file.get(current) {
+                    let start = (frames + buf.len() / channels) as f64;
+                    eprintln!(
+                        "File {} starts at {:.3} s: {}",
+                        current + 1,
+                        start / f64::from(spec.sample_rate),
+                        path
+                    );
+                }
+            }
+        }
+
+        if buf.is_empty() {
+            break;
+        }
+        peak = buf
+            .iter()
+            .map(|s| s.abs())
+            .filter(|s| s.is_finite())
+            .fold(peak, f32::max);
+        let mut mono = vec![0.0; buf.len() / channels];
+        match args.flag_analyze_channel {
+            Some(channel) => detect::extract_channel(&buf, channels, channel - 1, &mut mono),
+            None => detect::downmix(&buf, channels, &mix_channels, downmix, &mut mono),
+        }
+        let last = detector.level(&mono);
+        let last = detector.integrate(last, mono.len());
+        if let Some(spectrogram) = &mut spectrogram {
+            spectrogram.add(&mono, last);
+        }
+
+        if args.flag_per_channel {
+            let frames = mono.len();
+            for (c, (stats, detector)) in channel_stats
+                .iter_mut()
+                .zip(channel_detectors.iter_mut())
+                .enumerate()
+            {
+                detect::extract_channel(&buf, channels, c, &mut channel_buf[..frames]);
+                stats.add(detector.level(&channel_buf[..frames]));
+            }
+        }
+
+        let due = frames >= next_output;
+        if due && interval_frames > 0 {
+            next_output = (frames / interval_frames + 1) * interval_frames;
+        }
+        // only the printing is decimated
+        if due {
+            if let Some(out) = &mut raw_out {
+                out.write_all(&last.to_le_bytes())
+                    .and_then(|()| out.flush())
+                    .expect("Unable to write the levels");
+            } else {
+                let mut line = unit.format(last);
+                if let Some(peak_hold) = detector.peak_hold() {
+                    line = format!("{}\t{}", line, unit.format(peak_hold));
+                }
+                if args.flag_clip_ratio {
+                    line = format!("{}\t{:.4}", line, detector.clip_ratio());
+                }
+                println!("{}", line);
+            }
+        }
+        total.push(last);
+        frames += mono.len();
+    }
+
+    drop(raw_out);
+
+    let avg = total.iter().sum::<f32>() / total.len() as f32;
+
+    eprintln!("Average: {}, {} dB", avg, common::to_db(avg));
+
+    if args.flag_per_channel {
+        for (c, stats) in channel_stats.iter().enumerate() {
+            eprintln!(
+                "Channel {}: average {:.1} dB, min {:.1} dB, max {:.1} dB",
+                c + 1,
+                common::to_db(stats.sum / stats.count as f32),
+                common::to_db(stats.min),
+                common::to_db(stats.max)
+            );
+        }
+    }
+
+    let threshold = match args.flag_threshold_rel_peak {
+        Some(relative) => {
+            let threshold = common::to_db(peak) + relative;
+            eprintln!(
+                "Peak: {:.1} dB, threshold: {:.1} dB",
+                common::to_db(peak),
+                threshold
+            );
+            threshold
+        }
+        None => args.flag_threshold,
+    };
+    let detected = total.iter().any(|&level| common::to_db(level) >= threshold);
+    if let Some(path) = &args.flag_labels {
+        let segments = segments(&total, threshold, args.flag_window, frames);
+        write_labels(path, &segments, spec.sample_rate).expect("Unable to write labels");
+        eprintln!("Wrote {} label(s) to {}", segments.len(), path);
+    }
+    if let (Some(path), Some(spectrogram)) = (&args.flag_spectrogram, &spectrogram) {
+        let band = cli::band(args.flag_speech, &args.flag_band_pass, spec.sample_rate);
+        spectrogram
+            .write(path, threshold, band)
+            .expect("Unable to write the spectrogram");
+        eprintln!(
+            "Wrote the spectrogram to {}, {} window(s) per column",
+            path,
+            spectrogram.windows_per_column()
+        );
+    }
+    // finalize the debug audio before a possible exit
+    drop(detector);
+    // once everything is written and reported
+    if args.flag_pause && io::stdin().is_terminal() {
+        eprintln!("Press enter/return to quit...");
+        let mut user_input = String::new();
+        io::stdin().read_line(&mut user_input).ok();
+    }
+    cli::detect_exit(args.flag_detect_exit_code, detected);
+}
@@ -0,0 +1,907 @@
+use docopt::{ArgvMap, Docopt, Value};
+use serde::de::DeserializeOwned;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::action::{CommandAction, DurationRule, LogAction, LogFormat, StreamInfo, SwitchAction};
+use crate::common;
+use crate::control::{self, ControlSocket};
+use crate::detect::{Detector, Downmix, Measure};
+use crate::dump::TransitionDump;
+use crate::fifo::FifoAction;
+use crate::filter::{BandPass, Tilt};
+use crate::http_status;
+use crate::level_history::LevelHistory;
+use crate::midi::MidiAction;
+use crate::output::{LevelUnit, Output};
+use crate::quiet_hours::QuietHours;
+use crate::statsd::StatsdAction;
+use crate::status_file;
+use crate::switch::SwitchStatus;
+
+/// Prefix of the environment variables setting options.
+const ENV_PREFIX: &str = "SILENTCMD_";
+
+/// Band, attack and release (ms) of the `--speech` preset.
+const SPEECH_BAND: &str = "300:3400";
+const SPEECH_ATTACK_MS: f32 = 10.0;
+const SPEECH_RELEASE_MS: f32 = 200.0;
+
+/// Usage text of the options shared by the live backends, see `SwitchArgs`.
+#[macro_export]
+macro_rules! switch_options {
+    () => {
+        "  --cmd-on-after=<rules>     Run another command on switching on after being off for long,
+                             from <s>:<cmd> rules separated by ';'. The longest duration
+                             reached wins, cmd-on runs below all of them.
+  --cmd-off-after=<rules>    Same for switching off after being on for long. Without
+                             cmd-off, or with - as cmd-off, only these run on switching off.
+  --first-on-cmd=<cmd>       Run this command before cmd-on on the first switch on only.
+  --self-test                Run cmd-on, then cmd-off, as on transitions but without any
+                             audio, report whether they succeeded and exit.
+  --self-test-interval=<s>   Time between cmd-on and cmd-off in --self-test [default: 1].
+  --prime-off                Run cmd-off once at startup, before detecting, for the target to
+                             match the initial off state. Skipped when the --status-file of
+                             a previous run shows off.
+  --allow-cmd=<prefixes>     Only run commands whose executable, resolved from PATH, is one
+                             of these comma-separated paths or under one of these
+                             directories. Others are refused.
+  --cmd-env=<var>            Set this KEY=VALUE variable in the environment of commands,
+                             repeat for several. Added to the inherited environment unless
+                             with --cmd-clear-env.
+  --cmd-clear-env            Run commands in an empty environment, but for the --cmd-env
+                             variables and SILENTCMD_*. Bare command names are still found
+                             from the PATH of silentcmd.
+  --cmd-cwd=<dir>            Run commands from this working directory, for relative paths
+                             not to depend on how silentcmd was started.
+  --cmd-nice=<n>             Run commands with this niceness, from -20 to 19, so that a heavy
+                             cmd-on does not starve the capture, e.g. 10 on a single-core
+                             board. Inherited by default. Going below the niceness of
+                             silentcmd needs root or CAP_SYS_NICE, the commands failing to
+                             start otherwise. Linux and other Unix systems only.
+  --analysis-ms=<ms>         Run detection on frames of this duration, whatever the buffer size.
+  --attack=<ms>              Envelope attack time in milliseconds.
+  --release=<ms>             Envelope release time in milliseconds.
+  --snr-threshold=<db>       Switch on this many dB above the noise floor tracked from the
+                             level, whatever the input gain, the threshold then following the
+                             floor: --threshold no longer applies, and --threshold-file cannot
+                             be used. The floor follows the level down right away, and up by
+                             the --noise-floor-rise while off only, not to take a long signal
+                             for noise. With --verbose, the SNR is printed after the level.
+  --noise-floor-rise=<db>    Rise of the noise floor for --snr-threshold, in dB per second
+                             [default: 0.5].
+  --threshold-off=<db>       Once on, count the level as silence only below this level in dB,
+                             lower than the threshold, for hysteresis. A threshold change from
+                             the control or file keeps the gap between both.
+  --hysteresis=<db>          Once on, count the level as silence only this many dB below the
+                             threshold, e.g. 6, instead of giving --threshold-off.
+  --post-cmd-mute=<s>        Ignore the level for this time after each command fires, so
+                             the noise it makes cannot switch again [default: 0].
+  --off-deadtime=<s>         Do not switch on again for this time after switching off,
+                             whatever the level, for sources making noise as they shut
+                             down [default: 0].
+  --quiet-hours=<window>     Keep the switch off during this daily window of local time, as
+                             HH:MM-HH:MM, e.g. 22:00-07:00, wrapping past midnight when the
+                             end comes first. Entering it switches off, running cmd-off even
+                             with --ack-required, and levels and forced on are then ignored.
+  --defer-cmd-to-quiet=<s>   Hold the commands of each switch until a detection window is below
+                             the threshold, so that the noise they make is not detected. They
+                             run anyway once held for <s> seconds.
+  --detector=<kind>          Level measured over the window: rms, mav for the mean absolute
+                             value, cheaper and more responsive, or peak [default: rms].
+  --band-pass=<hz:hz>        Keep only this band before detection, e.g. 300:3400, with 12 dB
+                             per octave slopes. Applies before --tilt.
+  --speech                   Preset for voice detection, ignoring rumble and hiss: the same
+                             as --band-pass=300:3400 --attack=10 --release=200, any of them
+                             given explicitly taking precedence.
+  --tilt=<db>                Tilt the spectrum by this many dB per octave before detection,
+                             positive to emphasize highs, negative for lows.
+  --decimate=<n>             Detect on one sample out of <n>, each averaging the <n> samples
+                             it replaces, to save CPU. Content above the decimated Nyquist
+                             frequency is attenuated by the averaging [default: 1].
+  --gate-floor=<db>          Zero the samples below this level before detection, after tilt
+                             and decimation, so that steady hiss does not add to the level.
+                             Unlike the threshold, it does not decide switching.
+  --debug-audio=<file>       Write the mono stream detection sees, after mixdown, filters,
+                             decimation and gate, to this WAV file.
+  --integration=<s>          Switch on a running RMS integrating the window levels with this
+                             time constant, smoothing out gaps in the signal.
+  --min-silence=<ms>         Classify each window as silent when its sub-windows stay below
+                             the threshold for this long in total, instead of by its level.
+                             Short spikes in a silent window no longer switch on, but sound
+                             has to fill more of the window to be detected.
+  --silence-window=<ms>      Sub-window duration for --min-silence [default: 10].
+  --tone-min-duration=<s>    Switch on only once the level stayed above the threshold for
+                             this long without interruption, as for an alarm tone, with
+                             a tilt favoring its band. Any window below the threshold
+                             restarts the count: a longer release or integration smooths
+                             out gaps, at the cost of a later switch on.
+  --require-onset            Switch on only on sound with an onset, the level rising by the
+                             onset rise within 200ms, so that background noise slowly
+                             ramping up past the threshold does not switch on. A sound
+                             starting without one, such as a fade in, waits for a later one.
+                             Switching off still only depends on the level and timeout.
+  --onset-rise=<db>          Rise of the level making an onset [default: 10].
+  --vote-window=<n>          Count sound only when most of the last n windows were above the
+                             threshold, a tie counting as silence, to ignore brief flips
+                             either way. This delays switching on and the start of the
+                             timeout by up to n/2 + 1 windows.
+  --transition-dump=<dir>    Write the input from 2s before to 1s after each transition to
+                             a WAV file in this directory, named after time and state.
+  --transition-dump-max=<n>  Stop dumping transitions after this many files [default: 20].
+  --wait-silence             Start as on and exit after switching off, once silent for the
+                             timeout, even if silent from the start. cmd-on is not run.
+  --heartbeat=<s>            Log the state and level at this interval in seconds.
+  --duty-window=<s>          Log the percentage of time on over this rolling window once per
+                             window, to characterize intermittent sources. Also given as
+                             duty in the JSON status, over the time since start at first.
+  --max-runtime=<s>          Shut down after this time, as on SIGTERM.
+  --detect-exit-code         Exit with status 2 if the switch never turned on, 0 otherwise.
+  --keep-on-exit             Do not run cmd-off when exiting while on.
+  --verbose                  Print level and status on stdout, and the latency from each
+                             switch to its commands being spawned.
+  --log-format=<fmt>         Transition log on stderr: plain, json or logfmt, or journal for
+                             plain lines to the systemd journal with STATE, LEVEL, DEVICE
+                             and PREVIOUS_DURATION fields to filter on, falling back to
+                             stderr without a journal [default: plain].
+  --level-unit=<unit>        Printed level: db, linear envelope value or both [default: db].
+  --peak-hold=<s>            Print the maximum level over this hold time after the level,
+                             decaying by 20 dB/s afterward.
+  --peak-hold-trigger        Switch on the peak-hold value instead of the level.
+  --clip-ratio               Print the fraction of samples at full scale after the level.
+  --status-ramp=<s>          Ramp the printed status over this time after each transition
+                             instead of a hard step [default: 0].
+  --knee=<db>                Raise the printed status softly with the level over this range
+                             below the threshold while off, switching is unaffected.
+  --status-smoothing=<n>     Print the status as the average of its last <n> values, for
+                             cleaner plots, switching is unaffected [default: 1].
+  --output-interval=<s>      Print at most one level and status line per interval, with the
+                             latest values, however fast the levels come. Detection is
+                             unaffected [default: 0].
+  --control=<path>           Listen for commands on this Unix socket, one per line:
+                             ack, or status for the status as JSON. Unix only.
+  --control-jsonrpc          Take JSON-RPC 2.0 requests on --control instead, one per line,
+                             for getStatus, setThreshold, forceOn, forceOff, ack and reload
+                             of the --threshold-file.
+  --ack-required             Once on, stay on until acknowledged with ack on --control,
+                             whatever the level.
+  --ack-cooldown=<s>         After an ack switched off, do not switch on again for this time
+                             whatever the level, so that a lingering signal cannot undo the
+                             reset right away. Needs --ack-required [default: 0].
+  --threshold-file=<path>    Read the threshold in dB from this file, checked every second,
+                             instead of --threshold once read. Applies to all channels.
+  --suppress-reload-transition=<s>
+                             After each threshold change from --control or --threshold-file,
+                             take the first transition within this time as an artifact of
+                             the change: the state follows, but no command runs.
+  --print-config             Print the options in effect and the settings negotiated with
+                             the device on stderr, as key=value lines.
+  --http-status=<addr:port>  Serve the switch status as JSON on GET /.
+  --status-file=<path>       Keep this file up to date with the state and level, as
+                             `on -23.4`, rewritten atomically at most once per second.
+  --level-history=<path>     Record every level update and the state to this fixed-size
+                             ring file, overwriting the oldest, to look back at the levels
+                             before a false trigger. Print it with `silentcmd history`.
+  --level-history-size=<n>   Records kept in the level history, 16 bytes each. At one
+                             update per 512 frames at 48 kHz, the default keeps about an
+                             hour [default: 360000].
+  --event-fifo=<path>        Write transitions as JSON lines to this named pipe, created if
+                             missing. Events are dropped while no reader is connected.
+                             Unix only.
+  --midi-port=<name>         Also send MIDI on transitions, to the first output port
+                             whose name contains <name>.
+  --midi-channel=<n>         MIDI channel, 1 to 16 [default: 1].
+  --midi-note=<n>            Note on when switching on, note off when switching off [default: 60].
+  --midi-cc=<n>              Send this control change instead of a note, 127 on and 0 off.
+  --statsd=<addr:port>       Send metrics to this StatsD server over UDP: transition counters
+                             and the level and state as gauges.
+  --statsd-prefix=<name>     Prefix of the StatsD metric names [default: silentcmd].
+  --statsd-interval=<s>      Interval between StatsD gauges [default: 10].
+
+Environment:
+  Options not given on the command line can be set as SILENTCMD_<OPTION> variables,
+  e.g. SILENTCMD_THRESHOLD=-50 for the threshold, or SILENTCMD_VERBOSE=1 to turn on a
+  flag. The command line takes precedence, then the environment, then the defaults.
+
+  Commands get SILENTCMD_STATE (on or off), SILENTCMD_LEVEL (dB) and
+  SILENTCMD_PREVIOUS_DURATION (s the previous state lasted), the input in
+  SILENTCMD_DEVICE, and for audio inputs SILENTCMD_RATE (Hz), SILENTCMD_FORMAT and
+  SILENTCMD_CHANNELS as negotiated with the device: the format is named as for
+  the ALSA --format, or float for JACK, whose channels are the registered ports.
+"
+    };
+}
+
+/// Commands, threshold, timeout and the `switch_options!` of a live backend.
+///
+/// Threshold and timeout are listed by each backend with its own defaults,
+/// they can be comma separated lists in per-channel mode, see `channel_settings`.
+#[derive(Debug, Deserialize)]
+pub struct SwitchArgs {
+    /// set by the command action on failure, for `self_test`
+    #[serde(skip)]
+    command_failed: Arc<AtomicBool>,
+    /// name the device in the plain transition log, for several devices
+    #[serde(skip)]
+    device_in_log: bool,
+    pub arg_cmd_on: String,
+    pub arg_cmd_off: Option<String>,
+    pub flag_cmd_on_after: Option<String>,
+    pub flag_cmd_off_after: Option<String>,
+    pub flag_first_on_cmd: Option<String>,
+    pub flag_prime_off: bool,
+    pub flag_self_test: bool,
+    pub flag_self_test_interval: f32,
+    pub flag_allow_cmd: Option<String>,
+    pub flag_cmd_env: Vec<String>,
+    pub flag_cmd_clear_env: bool,
+    pub flag_cmd_cwd: Option<String>,
+    pub flag_cmd_nice: Option<i32>,
+    pub flag_threshold: String,
+    pub flag_timeout: String,
+    pub flag_snr_threshold: Option<f32>,
+    pub flag_noise_floor_rise: f32,
+    pub flag_threshold_off: Option<f32>,
+    pub flag_hysteresis: Option<f32>,
+    pub flag_analysis_ms: Option<f32>,
+    pub flag_attack: Option<f32>,
+    pub flag_release: Option<f32>,
+    pub flag_detector: String,
+    pub flag_band_pass: Option<String>,
+    pub flag_speech: bool,
+    pub flag_tilt: Option<f32>,
+    pub flag_decimate: usize,
+    pub flag_gate_floor: Option<f32>,
+    pub flag_debug_audio: Option<String>,
+    pub flag_integration: Option<f32>,
+    pub flag_min_silence: Option<f32>,
+    pub flag_silence_window: f32,
+    pub flag_tone_min_duration: Option<f32>,
+    pub flag_require_onset: bool,
+    pub flag_onset_rise: f32,
+    pub flag_vote_window: Option<usize>,
+    pub flag_duty_window: Option<f32>,
+    pub flag_transition_dump: Option<String>,
+    pub flag_transition_dump_max: usize,
+    pub flag_post_cmd_mute: f32,
+    pub flag_off_deadtime: f32,
+    pub flag_quiet_hours: Option<String>,
+    pub flag_defer_cmd_to_quiet: Option<f32>,
+    pub flag_wait_silence: bool,
+    pub flag_heartbeat: Option<f32>,
+    pub flag_max_runtime: Option<f32>,
+    pub flag_detect_exit_code: bool,
+    pub flag_keep_on_exit: bool,
+    pub flag_verbose: bool,
+    pub flag_log_format: String,
+    pub flag_level_unit: String,
+    pub flag_peak_hold: Option<f32>,
+    pub flag_peak_hold_trigger: bool,
+    pub flag_clip_ratio: bool,
+    pub flag_status_ramp: f32,
+    pub flag_knee: Option<f32>,
+    pub flag_status_smoothing: usize,
+    pub flag_output_interval: f32,
+    pub flag_control: Option<String>,
+    pub flag_control_jsonrpc: bool,
+    pub flag_threshold_file: Option<String>,
+    pub flag_suppress_reload_transition: Option<f32>,
+    pub flag_ack_required: bool,
+    pub flag_ack_cooldown: f32,
+    pub flag_print_config: bool,
+    pub flag_http_status: Option<String>,
+    pub flag_status_file: Option<String>,
+    pub flag_level_history: Option<String>,
+    pub flag_level_history_size: u32,
+    pub flag_event_fifo: Option<String>,
+    pub flag_midi_port: Option<String>,
+    pub flag_midi_channel: u8,
+    pub flag_midi_note: u8,
+    pub flag_midi_cc: Option<u8>,
+    pub flag_statsd: Option<String>,
+    pub flag_statsd_prefix: String,
+    pub flag_statsd_interval: f32,
+}
+
+impl SwitchArgs {
+    /// With `--self-test`, run cmd-on then cmd-off through the switch without
+    /// any audio, and exit with status 1 if a command failed, 0 otherwise.
+    pub fn self_test(&self, device: &str) {
+        if !self.flag_self_test {
+            return;
+        }
+
+        eprintln!(
+            "Self-test: running cmd-on, then cmd-off after {}s",
+            self.flag_self_test_interval
+        );
+        let (mut switch, worker) = self.start_switch(device, None, &self.channel_settings(1));
+        switch.force(true);
+        thread::sleep(Duration::from_secs_f32(self.flag_self_test_interval));
+        switch.force(false);
+        switch.shutdown(worker, false);
+
+        if self.command_failed.load(Ordering::Relaxed) {
+            eprintln!("Self-test failed");
+            process::exit(1);
+        }
+        eprintln!("Self-test passed");
+        process::exit(0);
+    }
+
+    /// Start the plain transition log lines with the device, to tell several apart.
+    pub fn set_device_in_log(&mut self) {
+        self.device_in_log = true;
+    }
+
+    /// Maximum runtime before shutting down.
+    pub fn max_runtime(&self) -> Option<Duration> {
+        self.flag_max_runtime.map(Duration::from_secs_f32)
+    }
+
+    /// Envelope attack and release in frames.
+    pub fn envelope(&self, sample_rate: u32) -> (f32, f32) {
+        let (attack, release) =
+            speech_envelope(self.flag_speech, self.flag_attack, self.flag_release);
+        common::envelope_frames(attack, release, sample_rate)
+    }
+
+    /// Detector over `window` samples, with the envelope, filter, decimation, gate, debug audio
+    /// and integration options.
+    pub fn detector(&self, window: usize, sample_rate: u32) -> Detector {
+        let mut detector = Detector::new(window, self.envelope(sample_rate));
+        detector.set_measure(measure(&self.flag_detector));
+        if let Some(band_pass) = band_pass(self.flag_speech, &self.flag_band_pass, sample_rate) {
+            detector.set_band_pass(band_pass);
+        }
+        if let Some(tilt) = self.flag_tilt {
+            detector.set_tilt(Tilt::new(tilt, sample_rate));
+        }
+        if self.flag_decimate > 1 {
+            detector.set_decimation(self.flag_decimate);
+        }
+        if let Some(db) = self.flag_gate_floor {
+            detector.set_gate_floor(db);
+        }
+        if let Some(path) = &self.flag_debug_audio {
+            detector
+                .set_debug_audio(path, sample_rate)
+                .expect("Unable to create debug audio file");
+        }
+        if let Some(s) = self.flag_integration {
+            detector.set_integration(common::time_to_frames(s * 1000.0, sample_rate));
+        }
+        if let Some(hold) = self.flag_peak_hold {
+            detector.set_peak_hold(hold, sample_rate, self.flag_peak_hold_trigger);
+        } else if self.flag_peak_hold_trigger {
+            eprintln!("--peak-hold-trigger needs --peak-hold");
+            process::exit(1);
+        }
+        if let Some(ms) = self.flag_min_silence {
+            detector.set_min_silence(
+                common::time_to_frames(self.flag_silence_window, sample_rate) as usize,
+                common::time_to_frames(ms, sample_rate) as usize,
+            );
+        }
+        detector
+    }
+
+    /// Dump of the input around transitions, if enabled.
+    pub fn transition_dump(&self, sample_rate: u32) -> Option<TransitionDump> {
+        self.flag_transition_dump.as_ref().map(|dir| {
+            TransitionDump::new(dir, sample_rate, self.flag_transition_dump_max)
+                .expect("Unable to create the transition dump directory")
+        })
+    }
+
+    pub fn output(&self) -> Output {
+        let mut output = Output::new(
+            self.flag_verbose,
+            self.flag_status_ramp,
+            level_unit(&self.flag_level_unit),
+        );
+        if let Some(knee) = self.flag_knee {
+            output.set_knee(knee);
+        }
+        if self.flag_clip_ratio {
+            output.set_clip_ratio();
+        }
+        output.set_status_smoothing(self.flag_status_smoothing);
+        output.set_interval(Duration::from_secs_f32(self.flag_output_interval));
+        output
+    }
+
+    /// Threshold (dB) and timeout (s) of each of `count` channels, a single value
+    /// applying to all of them. Exits if a list does not match the channels.
+    pub fn channel_settings(&self, count: usize) -> Vec<(f32, u64)> {
+        let thresholds: Vec<f32> = channel_list("threshold", &self.flag_threshold, count);
+        let timeouts: Vec<u64> = channel_list("timeout", &self.flag_timeout, count);
+        thresholds.into_iter().zip(timeouts).collect()
+    }
+
+    /// Gap below the threshold(s) of `settings` for the level to count as silence
+    /// once on, from --hysteresis or --threshold-off, exiting if invalid.
+    fn hysteresis(&self, settings: &[(f32, u64)]) -> Option<f32> {
+        let gap = match (self.flag_hysteresis, self.flag_threshold_off) {
+            (Some(_), Some(_)) => {
+                eprintln!("--hysteresis and --threshold-off are exclusive");
+                process::exit(1);
+            }
+            (Some(gap), None) => gap,
+            (None, Some(threshold_off)) => {
+                let threshold = settings[0].0;
+                if settings.iter().any(|&(other, _)| other != threshold) {
+                    eprintln!("--threshold-off needs a single threshold, use --hysteresis");
+                    process::exit(1);
+                }
+                threshold - threshold_off
+            }
+            (None, None) => return None,
+        };
+        if gap < 0.0 {
+            eprintln!("The off threshold has to be below the threshold");
+            process::exit(1);
+        }
+        Some(gap)
+    }
+
+    /// Create the switch with its actions, returned with the thread running them.
+    ///
+    /// `device` names the input in the transition log, and with `stream` in the
+    /// environment of commands. With several `settings`
+    /// from `channel_settings`, levels are given per channel.
+    pub fn start_switch(
+        &self,
+        device: &str,
+        stream: Option<&StreamInfo>,
+        settings: &[(f32, u64)],
+    ) -> (SwitchStatus, JoinHandle<()>) {
+        // read before the status file gets rewritten
+        let previous_state = self
+            .flag_status_file
+            .as_deref()
+            .and_then(status_file::read_state);
+        let (tx, rx) = mpsc::channel();
+        let (threshold, timeout) = settings[0];
+        let mut switch = SwitchStatus::new(threshold, timeout, tx);
+        if settings.len() > 1 {
+            switch.set_channels(settings);
+        }
+        if let Some(snr_db) = self.flag_snr_threshold {
+            if self.flag_threshold_file.is_some() {
+                eprintln!("--snr-threshold and --threshold-file are exclusive");
+                process::exit(1);
+            }
+            switch.set_snr_threshold(snr_db, self.flag_noise_floor_rise);
+        }
+        if let Some(gap) = self.hysteresis(settings) {
+            switch.set_hysteresis(gap);
+        }
+        switch.set_post_cmd_mute(Duration::from_secs_f32(self.flag_post_cmd_mute));
+        switch.set_off_deadtime(Duration::from_secs_f32(self.flag_off_deadtime));
+        if let Some(window) = &self.flag_quiet_hours {
+            let quiet_hours = QuietHours::parse(window).unwrap_or_else(|| {
+                eprintln!("Invalid quiet hours {}, expected HH:MM-HH:MM", window);
+                process::exit(1);
+            });
+            switch.set_quiet_hours(quiet_hours);
+        }
+        if let Some(s) = self.flag_defer_cmd_to_quiet {
+            switch.set_defer_to_quiet(Duration::from_secs_f32(s));
+        }
+        if let Some(s) = self.flag_tone_min_duration {
+            switch.set_min_sound(Duration::from_secs_f32(s));
+        }
+        if self.flag_require_onset {
+            switch.set_require_onset(self.flag_onset_rise);
+        }
+        if let Some(n) = self.flag_vote_window {
+            switch.set_vote_window(n);
+        }
+        if let Some(window) = self.flag_duty_window {
+            switch.set_duty_window(Duration::from_secs_f32(window));
+        }
+        if let Some(interval) = self.flag_heartbeat {
+            switch.set_heartbeat(Duration::from_secs_f32(interval));
+        }
+        if self.flag_wait_silence {
+            let timeout = settings.iter().map(|&(_, timeout)| timeout).max().unwrap();
+            eprintln!("Waiting for {}s of silence", timeout);
+            switch.wait_silence();
+        }
+        if self.flag_ack_required {
+            if self.flag_control.is_none() {
+                eprintln!("--ack-required needs --control to acknowledge");
+                process::exit(1);
+            }
+            switch.set_ack_required();
+            switch.set_ack_cooldown(Duration::from_secs_f32(self.flag_ack_cooldown));
+        } else if self.flag_ack_cooldown > 0.0 {
+            eprintln!("--ack-cooldown needs --ack-required");
+            process::exit(1);
+        }
+        if self.flag_control.is_some() || self.flag_threshold_file.is_some() {
+            let (control_tx, control_rx) = mpsc::channel();
+            if let Some(path) = &self.flag_control {
+                let mut socket = ControlSocket::new(control_tx.clone(), switch.share_status());
+                if self.flag_control_jsonrpc {
+                    socket.set_jsonrpc();
+                }
+                if let Some(file) = &self.flag_threshold_file {
+                    socket.set_threshold_file(file.clone());
+                }
+                socket
+                    .serve(path)
+                    .expect("Unable to listen on the control socket");
+            }
+            if let Some(path) = &self.flag_threshold_file {
+                control::watch_threshold(path.clone(), control_tx);
+            }
+            switch.set_control(control_rx);
+            if let Some(s) = self.flag_suppress_reload_transition {
+                switch.set_reload_suppression(Duration::from_secs_f32(s));
+            }
+        }
+        if let Some(addr) = &self.flag_http_status {
+            http_status::serve(addr, switch.share_status()).expect("Unable to serve HTTP status");
+        }
+        if let Some(path) = &self.flag_status_file {
+            status_file::start(path, switch.share_status())
+                .expect("Unable to write the status file");
+        }
+        if let Some(path) = &self.flag_level_history {
+            if self.flag_level_history_size == 0 {
+                eprintln!("--level-history-size needs at least one record");
+                process::exit(1);
+            }
+            switch.set_level_history(
+                LevelHistory::create(path, self.flag_level_history_size)
+                    .expect("Unable to create the level history"),
+            );
+        }
+
+        // "-" as well as no cmd-off only tracks the off state
+        let cmd_off = self.arg_cmd_off.clone().filter(|cmd| cmd != "-");
+        let mut command = CommandAction::new(self.arg_cmd_on.clone(), cmd_off);
+        command.set_rules(
+            duration_rules(&self.flag_cmd_on_after),
+            duration_rules(&self.flag_cmd_off_after),
+        );
+        if let Some(cmd) = &self.flag_first_on_cmd {
+            command.set_first_on(cmd.clone());
+        }
+        if let Some(prefixes) = &self.flag_allow_cmd {
+            command.set_allowed(prefixes.split(',').map(str::to_string).collect());
+        }
+        command.set_env(self.flag_cmd_clear_env, env_vars(&self.flag_cmd_env));
+        if let Some(dir) = &self.flag_cmd_cwd {
+            if !Path::new(dir).is_dir() {
+                eprintln!("Command working directory {} is not a directory", dir);
+                process::exit(1);
+            }
+            command.set_cwd(PathBuf::from(dir));
+        }
+        if let Some(nice) = self.flag_cmd_nice {
+            if !(-20..=19).contains(&nice) {
+                eprintln!("Invalid niceness {}, expected -20 to 19", nice);
+                process::exit(1);
+            }
+            #[cfg(unix)]
+            command.set_nice(nice);
+            #[cfg(not(unix))]
+            {
+                eprintln!("--cmd-nice is Unix only, unable to set niceness {}", nice);
+                process::exit(1);
+            }
+        }
+        if self.flag_verbose {
+            command.set_report_latency();
+        }
+        command.set_failure_flag(self.command_failed.clone());
+        command.set_device(device);
+        if let Some(stream) = stream {
+            command.set_stream(stream.clone());
+        }
+        if self.flag_prime_off {
+            if previous_state == Some(false) {
+                eprintln!("Priming skipped, the status file shows off");
+            } else if command.has_cmd_off() {
+                eprintln!("Priming: running cmd-off");
+                command.prime_off();
+            } else {
+                eprintln!("--prime-off needs a cmd-off");
+                process::exit(1);
+            }
+        }
+        let log_format = LogFormat::parse(&self.flag_log_format).unwrap_or_else(|| {
+            eprintln!("Unknown log format: {}", self.flag_log_format);
+            process::exit(1);
+        });
+        let mut log = LogAction::new(log_format, device);
+        if self.device_in_log {
+            log.set_plain_device();
+        }
+        let mut actions: Vec<Box<dyn SwitchAction>> = vec![Box::new(log), Box::new(command)];
+        if let Some(path) = &self.flag_event_fifo {
+            actions.push(Box::new(
+                FifoAction::create(path).expect("Unable to create event FIFO"),
+            ));
+        }
+        if let Some(port) = &self.flag_midi_port {
+            match MidiAction::connect(
+                port,
+                self.flag_midi_channel,
+                self.flag_midi_note,
+                self.flag_midi_cc,
+            ) {
+                Ok(midi) => actions.push(Box::new(midi)),
+                Err(e) => eprintln!("Warning: MIDI output disabled, {}", e),
+            }
+        }
+        if let Some(addr) = &self.flag_statsd {
+            let statsd = StatsdAction::connect(addr, &self.flag_statsd_prefix)
+                .expect("Unable to connect to StatsD");
+            statsd
+                .start_gauges(
+                    Duration::from_secs_f32(self.flag_statsd_interval),
+                    switch.share_status(),
+                )
+                .expect("Unable to start StatsD gauges");
+            actions.push(Box::new(statsd));
+        }
+        let worker = SwitchStatus::start(actions, rx);
+        (switch, worker)
+    }
+}
+
+/// Exit with `common::EXIT_NOT_DETECTED` if asked to and nothing was `detected`.
+pub fn detect_exit(enabled: bool, detected: bool) {
+    if enabled && !detected {
+        process::exit(common::EXIT_NOT_DETECTED);
+    }
+}
+
+/// Values of a comma separated list option, one per channel or a single one repeated.
+fn channel_list<T: FromStr + Clone>(name: &str, list: &str, count: usize) -> Vec<T> {
+    let values = list
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<Vec<T>, _>>()
+        .unwrap_or_else(|_| {
+            eprintln!("Invalid --{} value: {}", name, list);
+            process::exit(1);
+        });
+    match values.len() {
+        1 => vec![values[0].clone(); count],
+        n if n == count => values,
+        n => {
+            eprintln!(
+                "--{} lists {} values for {} channel(s), give one per channel in per-channel mode or a single one",
+                name, n, count
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Command rules from an optional option value, exiting if invalid.
+fn duration_rules(rules: &Option<String>) -> Vec<DurationRule> {
+    match rules {
+        Some(rules) => DurationRule::parse_list(rules).unwrap_or_else(|e| {
+            eprintln!("Invalid command rules: {}", e);
+            process::exit(1);
+        }),
+        None => Vec::new(),
+    }
+}
+
+/// `KEY=VALUE` variables as pairs, exiting if one is invalid.
+fn env_vars(vars: &[String]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|var| match var.split_once('=') {
+            Some((key, value)) if !key.is_empty() && !var.contains('\0') => {
+                (key.to_string(), value.to_string())
+            }
+            _ => {
+                eprintln!(
+                    "Invalid command environment variable {:?}, expected KEY=VALUE",
+                    var
+                );
+                process::exit(1);
+            }
+        })
+        .collect()
+}
+
+/// Band-pass of `--band-pass`, or of the `--speech` preset, exiting if invalid.
+pub fn band_pass(speech: bool, band: &Option<String>, sample_rate: u32) -> Option<BandPass> {
+    self::band(speech, band, sample_rate).map(|(low, high)| BandPass::new(low, high, sample_rate))
+}
+
+/// Low and high frequencies (Hz) of `--band-pass`, or of the `--speech` preset,
+/// exiting if invalid.
+pub fn band(speech: bool, band: &Option<String>, sample_rate: u32) -> Option<(f32, f32)> {
+    let band = band.as_deref().or(speech.then_some(SPEECH_BAND))?;
+    let nyquist = sample_rate as f32 / 2.0;
+    match band
+        .split_once(':')
+        .map(|(low, high)| (low.trim().parse::<f32>(), high.trim().parse::<f32>()))
+    {
+        Some((Ok(low), Ok(high))) if 0.0 < low && low < high && high < nyquist => Some((low, high)),
+        _ => {
+            eprintln!(
+                "Invalid band {}, expected low:high in Hz, below {} Hz",
+                band, nyquist
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Attack and release (ms) given, or of the `--speech` preset.
+pub fn speech_envelope(
+    speech: bool,
+    attack: Option<f32>,
+    release: Option<f32>,
+) -> (Option<f32>, Option<f32>) {
+    if speech {
+        (
+            attack.or(Some(SPEECH_ATTACK_MS)),
+            release.or(Some(SPEECH_RELEASE_MS)),
+        )
+    } else {
+        (attack, release)
+    }
+}
+
+/// Detector measure from its option value, exiting if unknown.
+pub fn measure(name: &str) -> Measure {
+    Measure::parse(name).unwrap_or_else(|| {
+        eprintln!("Unknown detector: {}", name);
+        process::exit(1);
+    })
+}
+
+/// Downmix from its option value, exiting if unknown or if `channels` are too few.
+pub fn downmix(name: &str, channels: usize) -> Downmix {
+    let downmix = Downmix::parse(name).unwrap_or_else(|| {
+        eprintln!("Unknown downmix: {}", name);
+        process::exit(1);
+    });
+    if channels < downmix.min_channels() {
+        eprintln!("Downmix {} needs {} channels", name, downmix.min_channels());
+        process::exit(1);
+    }
+    downmix
+}
+
+/// Level unit from its option value, exiting if unknown.
+pub fn level_unit(name: &str) -> LevelUnit {
+    LevelUnit::parse(name).unwrap_or_else(|| {
+        eprintln!("Unknown level unit: {}", name);
+        process::exit(1);
+    })
+}
+
+/// Parse a full command line against `usage`, exiting on error, help or version.
+///
+/// Options not given on the command line are taken from the environment, see
+/// `env_options`.
+pub fn parse(usage: &str, backend: &str, argv: Vec<String>) -> ArgvMap {
+    let argv = env_options(usage, argv);
+    Docopt::new(usage)
+        .and_then(|d| d.argv(argv).version(Some(common::version(backend))).parse())
+        .unwrap_or_else(|e| e.exit())
+}
+
+/// `argv` with the long options of `usage` it lacks added from `SILENTCMD_<OPTION>`
+/// environment variables, e.g. `SILENTCMD_THRESHOLD` for `--threshold`, exiting
+/// if a flag has a value other than 1/true/yes or 0/false/no.
+///
+/// The command line takes precedence over the environment, itself taking
+/// precedence over the defaults.
+fn env_options(usage: &str, mut argv: Vec<String>) -> Vec<String> {
+    // options are added before any "--" ending them
+    let mut end = argv
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(argv.len());
+    for (name, takes_value) in usage_options(usage) {
+        let var = format!("{}{}", ENV_PREFIX, name.to_uppercase().replace('-', "_"));
+        let Ok(value) = env::var(&var) else {
+            continue;
+        };
+        let option = format!("--{}", name);
+        let given = argv[..end]
+            .iter()
+            .any(|arg| arg == &option || arg.starts_with(&format!("{}=", option)));
+        if given {
+            continue;
+        }
+
+        let arg = if takes_value {
+            format!("{}={}", option, value)
+        } else {
+            match value.as_str() {
+                "1" | "true" | "yes" => option,
+                "0" | "false" | "no" => continue,
+                _ => {
+                    eprintln!(
+                        "Invalid {}={}, expected 1/true/yes or 0/false/no for {}",
+                        var, value, option
+                    );
+                    process::exit(1);
+                }
+            }
+        };
+        argv.insert(end, arg);
+        end += 1;
+    }
+    argv
+}
+
+/// Long option names in the option descriptions of `usage`, with whether they take a value.
+fn usage_options(usage: &str) -> Vec<(&str, bool)> {
+    usage
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| line.starts_with('-'))
+        .filter_map(|line| {
+            // the option and its aliases end at two spaces
+            let names = line.split("  ").next()?;
+            let long = names
+                .split([' ', ','])
+                .find(|name| name.starts_with("--"))?;
+            let long = long.trim_start_matches('-');
+            Some(match long.split_once('=') {
+                Some((name, _)) => (name, true),
+                None => (long, false),
+            })
+        })
+        .filter(|&(name, _)| name != "help" && name != "version")
+        .collect()
+}
+
+/// Print the parsed options in effect as `key=value` lines on stderr, defaults
+/// included and options not given left out.
+pub fn print_config(argv: &ArgvMap) {
+    let mut settings: Vec<(&str, String)> = argv
+        .map
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                Value::Switch(on) => on.to_string(),
+                Value::Counted(count) => count.to_string(),
+                Value::Plain(Some(value)) => value.clone(),
+                Value::List(values) => values.join(","),
+                Value::Plain(None) => return None,
+            };
+            let key = key
+                .trim_start_matches('-')
+                .trim_matches(|c| c == '<' || c == '>');
+            Some((key, value))
+        })
+        .filter(|&(key, _)| key != "help" && key != "version")
+        .collect();
+    settings.sort();
+    for (key, value) in settings {
+        config_line(key, &value);
+    }
+}
+
+/// Print a setting in the format of `print_config`.
+pub fn config_line(key: &str, value: &str) {
+    eprintln!("{}={}", key, common::kv_value(value));
+}
+
+/// Deserialize parsed arguments, one struct per group of options.
+pub fn args<T: DeserializeOwned>(argv: &ArgvMap) -> T {
+    argv.clone().deserialize().unwrap_or_else(|e| e.exit())
+}
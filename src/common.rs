@@ -1,4 +1,94 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Envelope attack and release, in frames, when not given in milliseconds.
 pub const ATTACK: f32 = 1.0;
 pub const RELEASE: f32 = 1.0;
 
+/// Exit code with `--detect-exit-code` when no signal was detected, 1 being for errors.
+pub const EXIT_NOT_DETECTED: i32 = 2;
+
+/// Version string reported by `--version`.
+pub fn version(backend: &str) -> String {
+    format!("silentcmd {} ({})", env!("CARGO_PKG_VERSION"), backend)
+}
+
 pub fn to_db(val: f32) -> f32 { 20.0 * val.log10() }
+
+/// Linear amplitude of a dB level, the inverse of `to_db`.
+pub fn from_db(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Convert an attack/release time in milliseconds to frames at the given sample rate.
+///
+/// `envelope::Detector` takes its time constants in frames and derives the
+/// per-sample coefficient as `exp(-1 / frames)`, so the same time gives the
+/// same response whatever the rate.
+pub fn time_to_frames(ms: f32, sample_rate: u32) -> f32 {
+    ms * sample_rate as f32 / 1000.0
+}
+
+/// Attack and release in frames from optional millisecond values.
+pub fn envelope_frames(
+    attack_ms: Option<f32>,
+    release_ms: Option<f32>,
+    sample_rate: u32,
+) -> (f32, f32) {
+    (
+        attack_ms.map_or(ATTACK, |ms| time_to_frames(ms, sample_rate)),
+        release_ms.map_or(RELEASE, |ms| time_to_frames(ms, sample_rate)),
+    )
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Value of a `key=value` (logfmt) pair, quoted if needed.
+pub fn kv_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '=' || c == '"') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// JSON has no representation for infinite dB values (digital silence).
+pub fn json_number(val: f32, decimals: usize) -> String {
+    if val.is_finite() {
+        format!("{:.*}", decimals, val)
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_to_frames_at_common_rates() {
+        assert_eq!(time_to_frames(50.0, 48000), 2400.0);
+        assert_eq!(time_to_frames(50.0, 44100), 2205.0);
+        assert_eq!(time_to_frames(0.0, 96000), 0.0);
+    }
+
+    #[test]
+    fn envelope_coefficient_is_rate_independent() {
+        // the per-sample coefficient exp(-1 / frames) decays as much over a second
+        let decay = |rate: u32| (-1.0 / time_to_frames(50.0, rate)).exp().powi(rate as i32);
+        assert!((decay(48000) - decay(44100)).abs() < 1e-6);
+        assert!((decay(48000) - (-20.0f32).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn envelope_frames_defaults() {
+        assert_eq!(envelope_frames(None, None, 48000), (ATTACK, RELEASE));
+        assert_eq!(envelope_frames(Some(50.0), None, 48000), (2400.0, RELEASE));
+        assert_eq!(envelope_frames(None, Some(50.0), 44100), (ATTACK, 2205.0));
+        assert_eq!(envelope_frames(Some(10.0), Some(50.0), 48000), (480.0, 2400.0));
+    }
+}
@@ -0,0 +1,256 @@
+// without Unix sockets, only the threshold file is watched on Windows
+#![cfg_attr(not(unix), allow(dead_code))]
+
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::json::{self, Json};
+use crate::switch::Status;
+
+/// Command for the switch received on the control socket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    /// Clear an on state waiting for acknowledgment.
+    Ack,
+    /// Set the threshold in dB, of all channels.
+    Threshold(f32),
+    /// Switch on right away, staying on for the timeout without signal.
+    ForceOn,
+    /// Switch off right away, until the level switches on again.
+    ForceOff,
+}
+
+/// How often `watch_threshold` reads its file.
+const THRESHOLD_FILE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_ERROR: i32 = -32000;
+
+/// Unix socket taking commands for the switch, one per line, each answered with a line.
+///
+/// By default, `ack` is forwarded to the switch and `status` returns the status
+/// as JSON. With JSON-RPC 2.0, each line is a request instead, for the methods
+/// `getStatus`, `setThreshold` (`{"db": -40}` or `[-40]`), `forceOn`, `forceOff`,
+/// `ack` and `reload`, re-reading the threshold file.
+#[derive(Clone)]
+pub struct ControlSocket {
+    tx: mpsc::Sender<Control>,
+    status: Arc<Mutex<Status>>,
+    jsonrpc: bool,
+    threshold_file: Option<String>,
+}
+
+impl ControlSocket {
+    pub fn new(tx: mpsc::Sender<Control>, status: Arc<Mutex<Status>>) -> ControlSocket {
+        ControlSocket {
+            tx,
+            status,
+            jsonrpc: false,
+            threshold_file: None,
+        }
+    }
+
+    /// Take JSON-RPC 2.0 requests rather than line commands.
+    pub fn set_jsonrpc(&mut self) {
+        self.jsonrpc = true;
+    }
+
+    /// File read again by the `reload` method.
+    pub fn set_threshold_file(&mut self, path: String) {
+        self.threshold_file = Some(path);
+    }
+
+    /// Listen on `path`, from a thread.
+    #[cfg(unix)]
+    pub fn serve(self, path: &str) -> io::Result<()> {
+        // replace a socket left over by a previous run, but nothing else
+        if let Ok(meta) = fs::metadata(path) {
+            if !meta.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        eprintln!("Control socket on {}", path);
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let socket = self.clone();
+                thread::spawn(move || {
+                    if let Err(e) = socket.handle(stream) {
+                        eprintln!("Control socket error: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn serve(self, _path: &str) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "control sockets are Unix only",
+        ))
+    }
+
+    #[cfg(unix)]
+    fn handle(&self, stream: UnixStream) -> io::Result<()> {
+        let mut writer = &stream;
+        for line in BufReader::new(&stream).lines() {
+            let line = line?;
+            let reply = if self.jsonrpc {
+                self.jsonrpc_request(line.trim())
+            } else {
+                Some(self.command(line.trim()))
+            };
+            if let Some(reply) = reply {
+                writeln!(writer, "{}", reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&self, command: &str) -> String {
+        match command {
+            "ack" => match self.send(Control::Ack) {
+                Ok(()) => "ok".to_string(),
+                Err(e) => format!("error: {}", e),
+            },
+            "status" => self.status.lock().unwrap().to_json(),
+            command => format!("error: unknown command {:?}", command),
+        }
+    }
+
+    /// Reply to a JSON-RPC request, none for a notification.
+    fn jsonrpc_request(&self, line: &str) -> Option<String> {
+        let request = match Json::parse(line) {
+            Ok(request) => request,
+            Err(e) => return Some(jsonrpc_error(&Json::Null, PARSE_ERROR, &e)),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Json::as_str) else {
+            let id = id.unwrap_or(Json::Null);
+            return Some(jsonrpc_error(&id, INVALID_REQUEST, "missing method"));
+        };
+        let result = self.call(method, request.get("params"));
+        id.map(|id| match result {
+            Ok(result) => format!(
+                "{{\"jsonrpc\":\"2.0\",\"result\":{},\"id\":{}}}",
+                result, id
+            ),
+            Err((code, message)) => jsonrpc_error(&id, code, &message),
+        })
+    }
+
+    /// Result of a JSON-RPC method as JSON, or error code and message.
+    fn call(&self, method: &str, params: Option<&Json>) -> Result<String, (i32, String)> {
+        let server_error = |e| (SERVER_ERROR, e);
+        match method {
+            "getStatus" => Ok(self.status.lock().unwrap().to_json()),
+            "setThreshold" => {
+                let db = params
+                    .and_then(|params| match params {
+                        Json::Array(values) => values.first(),
+                        params => params.get("db"),
+                    })
+                    .and_then(Json::as_f64)
+                    .ok_or((INVALID_PARAMS, "expected a threshold in dB".to_string()))?;
+                self.send(Control::Threshold(db as f32))
+                    .map_err(server_error)?;
+                Ok("true".to_string())
+            }
+            "forceOn" => self
+                .send(Control::ForceOn)
+                .map_err(server_error)
+                .map(|_| "true".to_string()),
+            "forceOff" => self
+                .send(Control::ForceOff)
+                .map_err(server_error)
+                .map(|_| "true".to_string()),
+            "ack" => self
+                .send(Control::Ack)
+                .map_err(server_error)
+                .map(|_| "true".to_string()),
+            "reload" => {
+                let path = self
+                    .threshold_file
+                    .as_ref()
+                    .ok_or((SERVER_ERROR, "no threshold file to reload".to_string()))?;
+                let db = read_threshold(path).map_err(server_error)?;
+                self.send(Control::Threshold(db)).map_err(server_error)?;
+                Ok(format!("{{\"threshold\":{}}}", db))
+            }
+            method => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    fn send(&self, control: Control) -> Result<(), String> {
+        self.tx
+            .send(control)
+            .map_err(|_| "switch stopped".to_string())
+    }
+}
+
+fn jsonrpc_error(id: &Json, code: i32, message: &str) -> String {
+    format!(
+        "{{\"jsonrpc\":\"2.0\",\"error\":{{\"code\":{},\"message\":{}}},\"id\":{}}}",
+        code,
+        json::string(message),
+        id
+    )
+}
+
+/// Threshold in dB from the file at `path`.
+fn read_threshold(path: &str) -> Result<f32, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    text.trim()
+        .parse::<f32>()
+        .map_err(|_| format!("invalid threshold {:?}", text.trim()))
+}
+
+/// Read a threshold in dB from the file at `path` every second, sending it
+/// to the switch on startup and each time it changes. A missing or invalid
+/// file keeps the last good value, logging the error once.
+pub fn watch_threshold(path: String, tx: mpsc::Sender<Control>) {
+    thread::spawn(move || {
+        let mut threshold = None;
+        let mut error = None;
+        loop {
+            match read_threshold(&path) {
+                Ok(db) => {
+                    error = None;
+                    if threshold != Some(db) {
+                        threshold = Some(db);
+                        if tx.send(Control::Threshold(db)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    if error.as_ref() != Some(&e) {
+                        eprintln!("Warning: threshold file {}: {}", path, e);
+                        error = Some(e);
+                    }
+                }
+            }
+            thread::sleep(THRESHOLD_FILE_INTERVAL);
+        }
+    });
+}
@@ -0,0 +1,15 @@
+extern crate silentcmd;
+
+use std::env;
+
+#[cfg(target_os = "macos")]
+fn main() {
+    silentcmd::backend::coreaudio::run(env::args().collect());
+}
+
+#[cfg(not(target_os = "macos"))]
+fn main() {
+    let _ = env::args();
+    eprintln!("silentcmd-coreaudio records on macOS only");
+    std::process::exit(1);
+}
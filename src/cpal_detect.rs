@@ -0,0 +1,7 @@
+extern crate silentcmd;
+
+use std::env;
+
+fn main() {
+    silentcmd::backend::cpal::run(env::args().collect());
+}
@@ -0,0 +1,826 @@
+use dasp::sample::ToSample;
+use dasp::{envelope, ring_buffer, Sample};
+use dasp::{signal, signal::envelope::SignalEnvelope, Signal};
+use std::fs::File;
+use std::io::BufWriter;
+use std::mem;
+use std::ops::Range;
+
+use crate::common;
+use crate::filter::{BandPass, Tilt};
+
+/// Sample magnitude counted as clipped, about 0.01 dB below full scale.
+const CLIP_LEVEL: f32 = 0.999;
+
+/// Decay of the peak-hold value once the hold time is over, in dB per second.
+const PEAK_HOLD_DECAY: f32 = 20.0;
+
+/// Maximum level over a hold time, decaying afterward.
+#[derive(Clone)]
+struct PeakHold {
+    /// hold time in frames
+    hold: usize,
+    /// decay factor per frame after the hold time
+    decay: f32,
+    value: f32,
+    /// frames left before decaying
+    left: usize,
+    /// report the value as the level
+    trigger: bool,
+}
+
+impl PeakHold {
+    /// Feed the level of the last `frames` frames, returning the held value.
+    fn update(&mut self, level: f32, frames: usize) -> f32 {
+        if level >= self.value {
+            self.value = level;
+            self.left = self.hold;
+        } else if self.left >= frames {
+            self.left -= frames;
+        } else {
+            let decaying = frames - self.left;
+            self.left = 0;
+            self.value = (self.value * self.decay.powi(decaying as i32)).max(level);
+        }
+        self.value
+    }
+}
+
+/// Level measured over the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Measure {
+    Rms,
+    /// Mean absolute value: cheaper and more responsive than RMS, reading
+    /// about 0.9 dB lower on a sine and more on peaky signals.
+    Mav,
+    /// Peak envelope, only shaped by attack and release.
+    Peak,
+}
+
+impl Measure {
+    pub fn parse(name: &str) -> Option<Measure> {
+        match name {
+            "rms" => Some(Measure::Rms),
+            "mav" => Some(Measure::Mav),
+            "peak" => Some(Measure::Peak),
+            _ => None,
+        }
+    }
+
+    /// Level detector of this measure, `window` in samples, attack and release in frames.
+    pub fn level_detector(
+        self,
+        window: usize,
+        (attack, release): (f32, f32),
+    ) -> Box<dyn LevelDetector> {
+        match self {
+            Measure::Rms => Box::new(RmsLevel::new(window, (attack, release))),
+            Measure::Mav => Box::new(MavLevel::new(window, (attack, release))),
+            Measure::Peak => Box::new(PeakLevel::new((attack, release))),
+        }
+    }
+}
+
+/// Level of the stream detection sees, after filters and decimation.
+///
+/// `Detector` runs the measures built in, library users can plug in their own
+/// with `Detector::set_level_detector`.
+pub trait LevelDetector: LevelDetectorClone + Send {
+    /// Linear level at the end of a non-empty buffer of mono samples.
+    fn process(&mut self, buf: &[f32]) -> f32;
+}
+
+/// Cloning of boxed level detectors, implemented for all those that are `Clone`.
+pub trait LevelDetectorClone {
+    fn clone_box(&self) -> Box<dyn LevelDetector>;
+}
+
+impl<T: LevelDetector + Clone + 'static> LevelDetectorClone for T {
+    fn clone_box(&self) -> Box<dyn LevelDetector> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn LevelDetector> {
+    fn clone(&self) -> Box<dyn LevelDetector> {
+        self.clone_box()
+    }
+}
+
+/// Last value of an envelope over a buffer.
+fn envelope_level<D>(buf: &[f32], detector: envelope::Detector<[f32; 1], D>) -> f32
+where
+    D: envelope::Detect<[f32; 1], Output = [f32; 1]>,
+{
+    signal::from_iter(buf.iter().map(|&sample| [sample]))
+        .detect_envelope(detector)
+        .until_exhausted()
+        .last()
+        .map_or(0.0, |level| level[0])
+}
+
+/// RMS envelope over a window, each buffer starting from silence.
+#[derive(Clone)]
+pub struct RmsLevel {
+    /// squares of the window, cleared and reused for each buffer
+    window: Vec<[f32; 1]>,
+    attack: f32,
+    release: f32,
+}
+
+impl RmsLevel {
+    /// `window` in samples, attack and release in frames.
+    pub fn new(window: usize, (attack, release): (f32, f32)) -> RmsLevel {
+        RmsLevel {
+            window: vec![[0.0]; window],
+            attack,
+            release,
+        }
+    }
+}
+
+impl LevelDetector for RmsLevel {
+    fn process(&mut self, buf: &[f32]) -> f32 {
+        self.window.fill([0.0]);
+        let ring_buffer = ring_buffer::Fixed::from(&mut self.window[..]);
+        let detector = envelope::Detector::rms(ring_buffer, self.attack, self.release);
+        envelope_level(buf, detector)
+    }
+}
+
+/// Mean absolute value envelope over a window, each buffer starting from silence.
+#[derive(Clone)]
+pub struct MavLevel {
+    /// absolute values of the window, cleared and reused for each buffer
+    window: Vec<f32>,
+    attack: f32,
+    release: f32,
+}
+
+impl MavLevel {
+    /// `window` in samples, attack and release in frames.
+    pub fn new(window: usize, (attack, release): (f32, f32)) -> MavLevel {
+        MavLevel {
+            window: vec![0.0; window],
+            attack,
+            release,
+        }
+    }
+}
+
+impl LevelDetector for MavLevel {
+    fn process(&mut self, buf: &[f32]) -> f32 {
+        self.window.fill(0.0);
+        let mav = Mav {
+            window: ring_buffer::Fixed::from(&mut self.window[..]),
+            sum: 0.0,
+        };
+        let detector = envelope::Detector::new(mav, self.attack, self.release);
+        envelope_level(buf, detector)
+    }
+}
+
+/// Full-wave peak envelope, each buffer starting from silence.
+#[derive(Clone)]
+pub struct PeakLevel {
+    attack: f32,
+    release: f32,
+}
+
+impl PeakLevel {
+    /// Attack and release in frames.
+    pub fn new((attack, release): (f32, f32)) -> PeakLevel {
+        PeakLevel { attack, release }
+    }
+}
+
+impl LevelDetector for PeakLevel {
+    fn process(&mut self, buf: &[f32]) -> f32 {
+        envelope_level(
+            buf,
+            envelope::Detector::new(FullWave, self.attack, self.release),
+        )
+    }
+}
+
+/// Mean absolute value over a window, for `envelope::Detector`.
+struct Mav<'a> {
+    window: ring_buffer::Fixed<&'a mut [f32]>,
+    sum: f32,
+}
+
+impl envelope::Detect<[f32; 1]> for Mav<'_> {
+    type Output = [f32; 1];
+
+    fn detect(&mut self, frame: [f32; 1]) -> [f32; 1] {
+        let abs = frame[0].abs();
+        self.sum += abs - self.window.push(abs);
+        // rounding can leave the running sum slightly negative
+        [self.sum.max(0.0) / self.window.len() as f32]
+    }
+}
+
+/// Full-wave rectifier, for a peak `envelope::Detector`.
+#[derive(Clone)]
+struct FullWave;
+
+impl envelope::Detect<[f32; 1]> for FullWave {
+    type Output = [f32; 1];
+
+    fn detect(&mut self, frame: [f32; 1]) -> [f32; 1] {
+        [frame[0].abs()]
+    }
+}
+
+type DebugWriter = hound::WavWriter<BufWriter<File>>;
+
+/// Writer of the stream detection sees, left out of clones.
+#[derive(Default)]
+struct DebugAudio(Option<DebugWriter>);
+
+impl Clone for DebugAudio {
+    fn clone(&self) -> DebugAudio {
+        DebugAudio(None)
+    }
+}
+
+/// RMS, or other `Measure`, envelope detection over a window.
+///
+/// Filters keep their state from one buffer to the next, use one detector per channel.
+#[derive(Clone)]
+pub struct Detector {
+    window: usize,
+    /// None with a level detector set by the user
+    measure: Option<Measure>,
+    attack: f32,
+    release: f32,
+    level_detector: Box<dyn LevelDetector>,
+    /// stream detected on in the last buffer, after filters and decimation
+    stream: Vec<f32>,
+    /// samples accumulated for the current analysis frame
+    frame: Vec<f32>,
+    frame_len: usize,
+    /// running RMS time constant in frames, 0 when disabled
+    integration: f32,
+    mean_square: f32,
+    band_pass: Option<BandPass>,
+    tilt: Option<Tilt>,
+    /// keep one sample out of `decimation`, averaging them
+    decimation: usize,
+    decimation_sum: f32,
+    decimation_count: usize,
+    /// linear level below which samples are zeroed, 0 when disabled
+    gate_floor: f32,
+    debug_audio: DebugAudio,
+    /// fraction of clipped samples in the last buffer
+    clip_ratio: f32,
+    peak_hold: Option<PeakHold>,
+    /// sub-window length for the silence classification, 0 when disabled
+    silence_window: usize,
+    /// sub-windows below the threshold making a buffer silent
+    min_silent_windows: usize,
+    silence_sum: f32,
+    silence_count: usize,
+    /// mean square of each sub-window completed in the last buffer
+    silence_levels: Vec<f32>,
+}
+
+impl Detector {
+    /// `window` in samples, attack and release in frames.
+    pub fn new(window: usize, (attack, release): (f32, f32)) -> Detector {
+        Detector {
+            window,
+            measure: Some(Measure::Rms),
+            attack,
+            release,
+            level_detector: Measure::Rms.level_detector(window, (attack, release)),
+            stream: Vec::new(),
+            frame: Vec::new(),
+            frame_len: 0,
+            integration: 0.0,
+            mean_square: 0.0,
+            band_pass: None,
+            tilt: None,
+            decimation: 1,
+            decimation_sum: 0.0,
+            decimation_count: 0,
+            gate_floor: 0.0,
+            debug_audio: DebugAudio::default(),
+            clip_ratio: 0.0,
+            peak_hold: None,
+            silence_window: 0,
+            min_silent_windows: 0,
+            silence_sum: 0.0,
+            silence_count: 0,
+            silence_levels: Vec::new(),
+        }
+    }
+
+    /// Detect over fixed-size analysis frames rather than once per buffer,
+    /// the window becoming the frame length.
+    pub fn set_frame_len(&mut self, frame_len: usize) {
+        self.window = (frame_len / self.decimation).max(1);
+        self.frame = Vec::with_capacity(frame_len);
+        self.frame_len = frame_len;
+        self.update_level_detector();
+    }
+
+    pub fn set_measure(&mut self, measure: Measure) {
+        self.measure = Some(measure);
+        self.update_level_detector();
+    }
+
+    /// Measure the level with this detector instead of a built-in `Measure`,
+    /// window, attack and release being up to it.
+    pub fn set_level_detector(&mut self, level_detector: Box<dyn LevelDetector>) {
+        self.measure = None;
+        self.level_detector = level_detector;
+    }
+
+    /// Rebuild the level detector of the measure with the current settings.
+    fn update_level_detector(&mut self) {
+        if let Some(measure) = self.measure {
+            self.level_detector = measure.level_detector(self.window, (self.attack, self.release));
+        }
+    }
+
+    /// Filter the input with a band-pass before detection, and before the tilt.
+    pub fn set_band_pass(&mut self, band_pass: BandPass) {
+        self.band_pass = Some(band_pass);
+    }
+
+    /// Filter the input with a tilt before detection.
+    pub fn set_tilt(&mut self, tilt: Tilt) {
+        self.tilt = Some(tilt);
+    }
+
+    /// Detect on one sample out of `factor`, each the average of the `factor` input
+    /// samples it replaces. The average is a cheap anti-alias filter: rather than
+    /// aliasing, content above the decimated Nyquist frequency is attenuated and
+    /// reads lower. Window, attack and release keep their duration.
+    pub fn set_decimation(&mut self, factor: usize) {
+        self.window = (self.window / factor).max(1);
+        self.attack /= factor as f32;
+        self.release /= factor as f32;
+        self.decimation = factor;
+        self.update_level_detector();
+    }
+
+    /// Zero the samples below `floor_db` after filters and decimation, so that
+    /// steady hiss does not add to the level. Unlike the threshold, the gate only
+    /// cleans the measured signal: silence sub-windows, debug audio and the level
+    /// detector all see the gated stream, while the clip ratio is on the input.
+    pub fn set_gate_floor(&mut self, floor_db: f32) {
+        self.gate_floor = common::from_db(floor_db);
+    }
+
+    /// Also write the mono stream detection sees, after filters, decimation and gate,
+    /// to a 32-bit float WAV file. `sample_rate` is the input rate.
+    ///
+    /// The file is finalized when the detector is dropped.
+    pub fn set_debug_audio(&mut self, path: &str, sample_rate: u32) -> hound::Result<()> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: sample_rate / self.decimation as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        self.debug_audio = DebugAudio(Some(hound::WavWriter::create(path, spec)?));
+        Ok(())
+    }
+
+    /// Classify buffers as silent when their sub-windows of `window` samples stay
+    /// below the threshold for at least `min_silence` samples, both counted at
+    /// the input rate. See `silent`.
+    pub fn set_min_silence(&mut self, window: usize, min_silence: usize) {
+        self.silence_window = (window / self.decimation).max(1);
+        self.min_silent_windows = min_silence.div_ceil(window.max(1)).max(1);
+    }
+
+    /// Whether the last buffer was silent, from the RMS level of its sub-windows
+    /// against `threshold_db`, or None without classification or when the buffer
+    /// completed no sub-window. A buffer shorter than the silence required must
+    /// be silent all along.
+    pub fn silent(&self, threshold_db: f32) -> Option<bool> {
+        if self.silence_window == 0 || self.silence_levels.is_empty() {
+            return None;
+        }
+
+        let threshold = common::from_db(threshold_db);
+        let silent_windows = self
+            .silence_levels
+            .iter()
+            .filter(|&&mean_square| mean_square.sqrt() < threshold)
+            .count();
+        Some(silent_windows >= self.min_silent_windows.min(self.silence_levels.len()))
+    }
+
+    /// Report an exponentially weighted running RMS of the levels, with this
+    /// time constant in frames, instead of the level of each window.
+    pub fn set_integration(&mut self, time_constant: f32) {
+        self.integration = time_constant;
+    }
+
+    /// Feed the level of the last `frames` frames to the running RMS and return it,
+    /// or return `level` unchanged when integration is disabled.
+    pub fn integrate(&mut self, level: f32, frames: usize) -> f32 {
+        if self.integration <= 0.0 {
+            return level;
+        }
+
+        let alpha = 1.0 - (-(frames as f32) / self.integration).exp();
+        self.mean_square += alpha * (level * level - self.mean_square);
+        self.mean_square.sqrt()
+    }
+
+    /// Track the maximum level over `hold` seconds, decaying afterward, and
+    /// report it as the level if `trigger` is set.
+    pub fn set_peak_hold(&mut self, hold: f32, sample_rate: u32, trigger: bool) {
+        self.peak_hold = Some(PeakHold {
+            hold: common::time_to_frames(hold * 1000.0, sample_rate) as usize,
+            decay: common::from_db(-PEAK_HOLD_DECAY / sample_rate as f32),
+            value: 0.0,
+            left: 0,
+            trigger,
+        });
+    }
+
+    /// Peak-hold value after the last buffer, if enabled.
+    pub fn peak_hold(&self) -> Option<f32> {
+        self.peak_hold.as_ref().map(|peak_hold| peak_hold.value)
+    }
+
+    /// Fraction of the samples of the last buffer at or near full scale.
+    pub fn clip_ratio(&self) -> f32 {
+        self.clip_ratio
+    }
+
+    /// Call `on_level` with the level of each analysis frame completed by this
+    /// buffer, or once for the buffer when not using analysis frames, along with
+    /// the detector for the other results on the frame.
+    pub fn process<S, F>(&mut self, buf: &[S], mut on_level: F)
+    where
+        S: Sample + ToSample<f32>,
+        F: FnMut(f32, &Detector),
+    {
+        if self.frame_len == 0 {
+            let level = self.level(buf);
+            on_level(self.integrate(level, buf.len()), self);
+            return;
+        }
+
+        for &sample in buf {
+            self.frame.push(sample.to_sample());
+            if self.frame.len() == self.frame_len {
+                let mut frame = mem::take(&mut self.frame);
+                let level = self.level(&frame);
+                on_level(self.integrate(level, self.frame_len), self);
+                frame.clear();
+                self.frame = frame;
+            }
+        }
+    }
+
+    /// Linear envelope value at the end of a mono buffer.
+    ///
+    /// NaN, infinite and denormal samples count as silence, so that a bad
+    /// sample from a driver cannot poison the level.
+    pub fn level<S>(&mut self, buf: &[S]) -> f32
+    where
+        S: Sample + ToSample<f32>,
+    {
+        let mut stream = mem::take(&mut self.stream);
+        stream.clear();
+        self.silence_levels.clear();
+        let mut clipped = 0;
+        for &s in buf {
+            let sample = sanitize(s.to_sample());
+            if sample.abs() >= CLIP_LEVEL {
+                clipped += 1;
+            }
+            let sample = self
+                .band_pass
+                .as_mut()
+                .map_or(sample, |b| b.process(sample));
+            let sample = self.tilt.as_mut().map_or(sample, |t| t.process(sample));
+            let sample = if self.decimation == 1 {
+                sample
+            } else {
+                self.decimation_sum += sample;
+                self.decimation_count += 1;
+                if self.decimation_count < self.decimation {
+                    continue;
+                }
+                let mean = self.decimation_sum / self.decimation as f32;
+                (self.decimation_sum, self.decimation_count) = (0.0, 0);
+                mean
+            };
+            let sample = if sample.abs() < self.gate_floor {
+                0.0
+            } else {
+                sample
+            };
+
+            if self.silence_window > 0 {
+                self.silence_sum += sample * sample;
+                self.silence_count += 1;
+                if self.silence_count == self.silence_window {
+                    self.silence_levels
+                        .push(self.silence_sum / self.silence_window as f32);
+                    (self.silence_sum, self.silence_count) = (0.0, 0);
+                }
+            }
+
+            if let Some(writer) = &mut self.debug_audio.0 {
+                if let Err(e) = writer.write_sample(sample) {
+                    eprintln!("Warning: debug audio disabled, {}", e);
+                    self.debug_audio.0 = None;
+                }
+            }
+            stream.push(sample);
+        }
+        self.clip_ratio = clipped as f32 / buf.len().max(1) as f32;
+
+        // a buffer shorter than the decimation can leave nothing to detect
+        let level = if stream.is_empty() {
+            0.0
+        } else {
+            self.level_detector.process(&stream)
+        };
+        self.stream = stream;
+        match &mut self.peak_hold {
+            Some(peak_hold) => {
+                let held = peak_hold.update(level, buf.len());
+                if peak_hold.trigger {
+                    held
+                } else {
+                    level
+                }
+            }
+            None => level,
+        }
+    }
+}
+
+/// How channels are mixed down to the mono stream detected on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Downmix {
+    Avg,
+    Sum,
+    /// (L + R) / 2 of the first two channels
+    Mid,
+    /// (L - R) / 2 of the first two channels, catching panned content
+    /// that cancels out in the other modes
+    Side,
+}
+
+impl Downmix {
+    pub fn parse(name: &str) -> Option<Downmix> {
+        match name {
+            "avg" => Some(Downmix::Avg),
+            "sum" => Some(Downmix::Sum),
+            "mid" => Some(Downmix::Mid),
+            "side" => Some(Downmix::Side),
+            _ => None,
+        }
+    }
+
+    /// Channels needed, at least.
+    pub fn min_channels(self) -> usize {
+        match self {
+            Downmix::Avg | Downmix::Sum => 1,
+            Downmix::Mid | Downmix::Side => 2,
+        }
+    }
+}
+
+/// Mix the `channels` (0-based) of interleaved frames down to a mono buffer.
+///
+/// Samples are converted to float before being combined, so that full-scale
+/// content on all channels cannot overflow, whatever the sample type.
+pub fn downmix<S>(
+    interleaved: &[S],
+    channel_count: usize,
+    channels: &[usize],
+    mode: Downmix,
+    mono: &mut [f32],
+) where
+    S: Sample + ToSample<f32>,
+{
+    if let (Downmix::Avg | Downmix::Sum, Some(range)) = (mode, contiguous(channels)) {
+        downmix_range(interleaved, channel_count, range, mode, mono);
+    } else {
+        downmix_indexed(interleaved, channel_count, channels, mode, mono);
+    }
+}
+
+/// `downmix` indexing each sample through the channel list, for any list and mode.
+fn downmix_indexed<S>(
+    interleaved: &[S],
+    channel_count: usize,
+    channels: &[usize],
+    mode: Downmix,
+    mono: &mut [f32],
+) where
+    S: Sample + ToSample<f32>,
+{
+    for (sample, frame) in mono.iter_mut().zip(interleaved.chunks_exact(channel_count)) {
+        let value = |c: usize| frame[channels[c]].to_sample::<f32>();
+        *sample = match mode {
+            Downmix::Avg => {
+                channels
+                    .iter()
+                    .map(|&c| frame[c].to_sample::<f32>())
+                    .sum::<f32>()
+                    / channels.len() as f32
+            }
+            Downmix::Sum => channels.iter().map(|&c| frame[c].to_sample::<f32>()).sum(),
+            Downmix::Mid => (value(0) + value(1)) / 2.0,
+            Downmix::Side => (value(0) - value(1)) / 2.0,
+        };
+    }
+}
+
+/// Fast path of `downmix` averaging or summing a contiguous range of channels,
+/// all of them in particular, from a slice of each frame rather than indexing
+/// through the channel list. Samples are added in the same order, for the
+/// same result.
+fn downmix_range<S>(
+    interleaved: &[S],
+    channel_count: usize,
+    range: Range<usize>,
+    mode: Downmix,
+    mono: &mut [f32],
+) where
+    S: Sample + ToSample<f32>,
+{
+    let divisor = match mode {
+        Downmix::Avg => range.len() as f32,
+        _ => 1.0,
+    };
+    for (sample, frame) in mono.iter_mut().zip(interleaved.chunks_exact(channel_count)) {
+        *sample = frame[range.clone()]
+            .iter()
+            .map(|s| s.to_sample::<f32>())
+            .sum::<f32>()
+            / divisor;
+    }
+}
+
+/// Range of `channels` if they follow each other in increasing order.
+fn contiguous(channels: &[usize]) -> Option<Range<usize>> {
+    let first = *channels.first()?;
+    channels
+        .iter()
+        .enumerate()
+        .all(|(i, &c)| c == first + i)
+        .then_some(first..first + channels.len())
+}
+
+/// Replace NaN, infinite and denormal values with 0.
+fn sanitize(sample: f32) -> f32 {
+    if sample.is_normal() {
+        sample
+    } else {
+        0.0
+    }
+}
+
+/// Copy one channel (0-based) of interleaved frames to a mono buffer.
+pub fn extract_channel<S, T>(
+    interleaved: &[S],
+    channel_count: usize,
+    channel: usize,
+    mono: &mut [T],
+) where
+    S: Sample + ToSample<T>,
+    T: Sample,
+{
+    for (sample, frame) in mono.iter_mut().zip(interleaved.chunks_exact(channel_count)) {
+        *sample = frame[channel].to_sample();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNELS: usize = 8;
+    const FRAMES: usize = 256;
+
+    /// Deterministic full-range values, frame after frame.
+    fn noise(len: usize) -> Vec<i32> {
+        let mut state = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state as i32
+            })
+            .collect()
+    }
+
+    fn assert_range_matches_indexed<S>(interleaved: &[S])
+    where
+        S: Sample + ToSample<f32>,
+    {
+        for range in [0..CHANNELS, 0..1, 2..5, 7..8] {
+            let channels: Vec<usize> = range.clone().collect();
+            for mode in [Downmix::Avg, Downmix::Sum] {
+                let mut fast = vec![0.0; FRAMES];
+                let mut indexed = vec![0.0; FRAMES];
+                downmix_range(interleaved, CHANNELS, range.clone(), mode, &mut fast);
+                downmix_indexed(interleaved, CHANNELS, &channels, mode, &mut indexed);
+                assert_eq!(fast, indexed, "{:?} {:?}", range, mode);
+            }
+        }
+    }
+
+    fn assert_scattered_uses_indexed<S>(interleaved: &[S])
+    where
+        S: Sample + ToSample<f32>,
+    {
+        for channels in [vec![1, 0], vec![0, 2, 4], vec![3, 7, 5, 6]] {
+            assert_eq!(contiguous(&channels), None);
+            for mode in [Downmix::Avg, Downmix::Sum, Downmix::Mid, Downmix::Side] {
+                let mut mixed = vec![0.0; FRAMES];
+                let mut indexed = vec![0.0; FRAMES];
+                downmix(interleaved, CHANNELS, &channels, mode, &mut mixed);
+                downmix_indexed(interleaved, CHANNELS, &channels, mode, &mut indexed);
+                assert_eq!(mixed, indexed, "{:?} {:?}", channels, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn downmix_range_matches_indexed() {
+        let values = noise(FRAMES * CHANNELS);
+        let i16s: Vec<i16> = values.iter().map(|&v| (v >> 16) as i16).collect();
+        let f32s: Vec<f32> = values.iter().map(|&v| v.to_sample::<f32>()).collect();
+        assert_range_matches_indexed(&i16s);
+        assert_range_matches_indexed(&values);
+        assert_range_matches_indexed(&f32s);
+        assert_scattered_uses_indexed(&i16s);
+        assert_scattered_uses_indexed(&values);
+        assert_scattered_uses_indexed(&f32s);
+    }
+
+    /// 1 kHz sine at 48 kHz of `amplitude`.
+    fn sine(amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * i as f32 / 48.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn level_ignores_non_finite_samples() {
+        let mut poisoned = vec![0.0f32; 1024];
+        poisoned[10] = f32::NAN;
+        poisoned[20] = f32::INFINITY;
+        poisoned[30] = f32::NEG_INFINITY;
+        poisoned[40] = f32::MIN_POSITIVE / 2.0;
+        assert!(poisoned[40].is_subnormal());
+
+        let mut detector = Detector::new(1024, (1.0, 1.0));
+        let mut reference = Detector::new(1024, (1.0, 1.0));
+        let level = detector.level(&poisoned);
+        assert!(level.is_finite());
+        assert_eq!(level, reference.level(&[0.0f32; 1024]));
+
+        // the envelope is not left poisoned for the next buffers
+        let tone = sine(0.5, 1024);
+        let level = detector.level(&tone);
+        assert!(level.is_finite() && level > 0.0);
+        assert_eq!(level, reference.level(&tone));
+    }
+
+    /// Frames alternating between `high` and `low` on every channel.
+    fn full_scale<S: Copy>(high: S, low: S) -> Vec<S> {
+        (0..FRAMES)
+            .flat_map(|i| [if i % 2 == 0 { high } else { low }; CHANNELS])
+            .collect()
+    }
+
+    fn assert_full_scale_mixdown<S>(interleaved: &[S])
+    where
+        S: Sample + ToSample<f32>,
+    {
+        let channels: Vec<usize> = (0..CHANNELS).collect();
+        let mut mono = vec![0.0; FRAMES];
+        downmix(interleaved, CHANNELS, &channels, Downmix::Sum, &mut mono);
+        for (i, sample) in mono.iter().enumerate() {
+            let expected = CHANNELS as f32 * if i % 2 == 0 { 1.0 } else { -1.0 };
+            assert!((sample - expected).abs() < 1e-3, "{} at {}", sample, i);
+        }
+
+        downmix(interleaved, CHANNELS, &channels, Downmix::Avg, &mut mono);
+        let mut detector = Detector::new(FRAMES, (1.0, 1.0));
+        let db = common::to_db(detector.level(&mono));
+        assert!(db.abs() < 0.1, "{} dB", db);
+    }
+
+    #[test]
+    fn downmix_full_scale_does_not_overflow() {
+        assert_full_scale_mixdown(&full_scale(i16::MAX, i16::MIN));
+        assert_full_scale_mixdown(&full_scale(i32::MAX, i32::MIN));
+    }
+}
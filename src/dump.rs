@@ -0,0 +1,132 @@
+use dasp::sample::ToSample;
+use dasp::Sample;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::common;
+
+/// Seconds of input kept before each transition.
+const PRE_ROLL: f32 = 2.0;
+/// Seconds of input written after each transition.
+const POST_ROLL: f32 = 1.0;
+
+type DumpWriter = hound::WavWriter<BufWriter<File>>;
+
+/// Write the mono input around each transition to a WAV file, from a
+/// pre-roll kept in memory and a post-roll following the transition.
+///
+/// Files are named after the time and new state, e.g. `1700000000.123-on.wav`,
+/// and stop after `max` of them so that false triggers cannot fill the disk.
+pub struct TransitionDump {
+    dir: PathBuf,
+    sample_rate: u32,
+    pre_roll: VecDeque<f32>,
+    pre_roll_len: usize,
+    post_roll_len: usize,
+    /// dump in progress with the samples it still needs
+    writer: Option<(DumpWriter, usize)>,
+    is_on: bool,
+    count: usize,
+    max: usize,
+}
+
+impl TransitionDump {
+    /// Dump to `dir`, created if missing.
+    pub fn new(dir: &str, sample_rate: u32, max: usize) -> io::Result<TransitionDump> {
+        fs::create_dir_all(dir)?;
+        let pre_roll_len = common::time_to_frames(PRE_ROLL * 1000.0, sample_rate) as usize;
+        Ok(TransitionDump {
+            dir: PathBuf::from(dir),
+            sample_rate,
+            pre_roll: VecDeque::with_capacity(pre_roll_len),
+            pre_roll_len,
+            post_roll_len: common::time_to_frames(POST_ROLL * 1000.0, sample_rate) as usize,
+            writer: None,
+            is_on: false,
+            count: 0,
+            max,
+        })
+    }
+
+    /// Feed the mono buffer the switch state `is_on` was updated from.
+    pub fn process<S>(&mut self, buf: &[S], is_on: bool)
+    where
+        S: Sample + ToSample<f32>,
+    {
+        if is_on != self.is_on {
+            self.is_on = is_on;
+            self.start();
+        }
+
+        for &sample in buf {
+            let sample = sample.to_sample();
+            if let Some((writer, left)) = &mut self.writer {
+                if let Err(e) = writer.write_sample(sample) {
+                    eprintln!("Warning: transition dump stopped, {}", e);
+                    self.writer = None;
+                } else {
+                    *left -= 1;
+                    if *left == 0 {
+                        self.finish();
+                    }
+                }
+            }
+
+            if self.pre_roll.len() == self.pre_roll_len {
+                self.pre_roll.pop_front();
+            }
+            self.pre_roll.push_back(sample);
+        }
+    }
+
+    /// Start a dump with the pre-roll, finishing the one in progress if any.
+    fn start(&mut self) {
+        self.finish();
+        if self.count == self.max {
+            return;
+        }
+        self.count += 1;
+        if self.count == self.max {
+            eprintln!("Warning: last transition dump, {} written", self.max);
+        }
+
+        let name = format!(
+            "{:.3}-{}.wav",
+            common::unix_time(SystemTime::now()),
+            if self.is_on { "on" } else { "off" }
+        );
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let writer = hound::WavWriter::create(self.dir.join(name), spec).and_then(|mut writer| {
+            for &sample in &self.pre_roll {
+                writer.write_sample(sample)?;
+            }
+            Ok(writer)
+        });
+        match writer {
+            Ok(writer) => self.writer = Some((writer, self.post_roll_len.max(1))),
+            Err(e) => eprintln!("Warning: unable to dump transition, {}", e),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some((writer, _)) = self.writer.take() {
+            if let Err(e) = writer.finalize() {
+                eprintln!("Warning: unable to finalize transition dump, {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for TransitionDump {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Fraction of time the switch is on over a rolling window.
+pub struct DutyCycle {
+    window: Duration,
+    started: Instant,
+    /// on periods overlapping the window, oldest first, the last one open while on
+    periods: VecDeque<(Instant, Option<Instant>)>,
+    last_report: Instant,
+}
+
+impl DutyCycle {
+    /// Over the last `window`, starting `on` or off.
+    pub fn new(window: Duration, on: bool) -> DutyCycle {
+        let now = Instant::now();
+        let mut duty = DutyCycle {
+            window,
+            started: now,
+            periods: VecDeque::new(),
+            last_report: now,
+        };
+        duty.set(on);
+        duty
+    }
+
+    /// Note the switch being `on` or off from now.
+    pub fn set(&mut self, on: bool) {
+        let now = Instant::now();
+        match self.periods.back_mut() {
+            Some((_, end @ None)) if !on => *end = Some(now),
+            Some((_, None)) => {}
+            _ if on => self.periods.push_back((now, None)),
+            _ => {}
+        }
+    }
+
+    /// Fraction of the window spent on, of the time since started until a
+    /// full window passed.
+    pub fn ratio(&self) -> f32 {
+        let now = Instant::now();
+        let start = self.window_start(now);
+        let span = now.duration_since(start);
+        if span.is_zero() {
+            return 0.0;
+        }
+        let on: Duration = self
+            .periods
+            .iter()
+            .map(|&(from, to)| to.unwrap_or(now).saturating_duration_since(from.max(start)))
+            .sum();
+        on.as_secs_f32() / span.as_secs_f32()
+    }
+
+    /// The ratio once per window, dropping the periods ended before it.
+    pub fn report(&mut self) -> Option<f32> {
+        if self.last_report.elapsed() < self.window {
+            return None;
+        }
+        let now = Instant::now();
+        self.last_report = now;
+        let start = self.window_start(now);
+        while self
+            .periods
+            .front()
+            .is_some_and(|&(_, end)| end.is_some_and(|end| end <= start))
+        {
+            self.periods.pop_front();
+        }
+        Some(self.ratio())
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    fn window_start(&self, now: Instant) -> Instant {
+        now.checked_sub(self.window)
+            .map_or(self.started, |start| start.max(self.started))
+    }
+}
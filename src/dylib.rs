@@ -0,0 +1,66 @@
+#[cfg(unix)]
+use std::ffi::CStr;
+use std::ffi::CString;
+#[cfg(windows)]
+use std::io;
+use std::mem;
+#[cfg(windows)]
+use std::os::raw::c_char;
+use std::os::raw::c_void;
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn LoadLibraryA(name: *const c_char) -> *mut c_void;
+    fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+}
+
+/// Shared library loaded at runtime, for backends built without it.
+pub struct Library {
+    name: String,
+    handle: *mut c_void,
+}
+
+impl Library {
+    /// Load `name`, which then stays loaded until exit.
+    pub fn open(name: &str) -> Result<Library, String> {
+        let c_name = CString::new(name).map_err(|e| e.to_string())?;
+        #[cfg(unix)]
+        let handle = unsafe { libc::dlopen(c_name.as_ptr(), libc::RTLD_NOW) };
+        #[cfg(windows)]
+        let handle = unsafe { LoadLibraryA(c_name.as_ptr()) };
+        if handle.is_null() {
+            // naming the library and why it could not be loaded
+            #[cfg(unix)]
+            let reason = unsafe { CStr::from_ptr(libc::dlerror()) }
+                .to_string_lossy()
+                .into_owned();
+            #[cfg(windows)]
+            let reason = format!("{}: {}", name, io::Error::last_os_error());
+            return Err(reason);
+        }
+        Ok(Library {
+            name: name.to_string(),
+            handle,
+        })
+    }
+
+    /// Function `symbol` of the library or its dependencies, as `F`.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be an `extern "C"` function pointer of the C signature of
+    /// `symbol`.
+    pub unsafe fn function<F: Copy>(&self, symbol: &str) -> Result<F, String> {
+        assert_eq!(mem::size_of::<F>(), mem::size_of::<*mut c_void>());
+        let c_symbol = CString::new(symbol).map_err(|e| e.to_string())?;
+        #[cfg(unix)]
+        let pointer = libc::dlsym(self.handle, c_symbol.as_ptr());
+        #[cfg(windows)]
+        let pointer = GetProcAddress(self.handle, c_symbol.as_ptr());
+        if pointer.is_null() {
+            return Err(format!("{} lacks {}", self.name, symbol));
+        }
+        Ok(mem::transmute_copy(&pointer))
+    }
+}
@@ -0,0 +1,92 @@
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::PathBuf;
+
+use crate::action::SwitchAction;
+use crate::switch::Transition;
+
+/// Write each transition as a JSON line to a named pipe.
+///
+/// The pipe is opened and written without blocking: events are dropped while
+/// no reader is connected or when a stalled reader lets the pipe fill up, and
+/// the pipe is reopened after the reader goes away.
+pub struct FifoAction {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl FifoAction {
+    pub fn create(path: &str) -> io::Result<FifoAction> {
+        let fifo = FifoAction {
+            path: PathBuf::from(path),
+            file: None,
+        };
+        fifo.make_fifo()?;
+        Ok(fifo)
+    }
+
+    /// Create the pipe if missing, refusing to replace anything else.
+    #[cfg(unix)]
+    fn make_fifo(&self) -> io::Result<()> {
+        match fs::metadata(&self.path) {
+            Ok(meta) if meta.file_type().is_fifo() => Ok(()),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a named pipe", self.path.display()),
+            )),
+            Err(_) => {
+                let path = CString::new(self.path.as_os_str().as_bytes())?;
+                if unsafe { libc::mkfifo(path.as_ptr(), 0o644) } == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn make_fifo(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "named pipes are Unix only",
+        ))
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.file.is_none() {
+            self.make_fifo()?;
+            let mut options = OpenOptions::new();
+            options.write(true);
+            // fails with ENXIO while there is no reader
+            #[cfg(unix)]
+            options.custom_flags(libc::O_NONBLOCK);
+            self.file = Some(options.open(&self.path)?);
+        }
+
+        let result = self.file.as_mut().unwrap().write_all(line.as_bytes());
+        if result.is_err() {
+            // reader disconnected or stalled: reopen on next event
+            self.file = None;
+        }
+        result
+    }
+}
+
+impl SwitchAction for FifoAction {
+    fn run(&mut self, transition: &Transition) {
+        let line = transition.to_json() + "\n";
+        // a new reader may be waiting after the previous one left
+        if let Err(e) = self.write(&line).or_else(|_| self.write(&line)) {
+            eprintln!("Event FIFO {}: event dropped, {}", self.path.display(), e);
+        }
+    }
+}
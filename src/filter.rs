@@ -0,0 +1,113 @@
+use std::f32::consts::PI;
+
+/// Frequency the tilt pivots around, unchanged by the filter.
+const TILT_PIVOT_HZ: f32 = 1000.0;
+/// Distance from the pivot, in octaves, where the tilt levels off.
+const TILT_OCTAVES: f32 = 5.0;
+
+/// First-order shelf approximating a constant dB per octave tilt around
+/// `TILT_PIVOT_HZ`, emphasizing highs when positive and lows when negative.
+///
+/// The shelf is `g (s + w0 / g) / (s + w0 g)`: 1/g at DC, 1 at the pivot and g
+/// at high frequencies, with g reached `TILT_OCTAVES` away from the pivot on average.
+#[derive(Clone, Debug)]
+pub struct Tilt {
+    b0: f32,
+    b1: f32,
+    a1: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl Tilt {
+    pub fn new(db_per_octave: f32, sample_rate: u32) -> Tilt {
+        let g = 10f32.powf(db_per_octave * TILT_OCTAVES / 20.0);
+        let w0 = 2.0 * PI * TILT_PIVOT_HZ;
+        // bilinear transform, prewarped at the pivot
+        let k = w0 / (w0 / (2.0 * sample_rate as f32)).tan();
+        let (zero, pole) = (w0 / g, w0 * g);
+        Tilt {
+            b0: g * (k + zero) / (k + pole),
+            b1: g * (zero - k) / (k + pole),
+            a1: (pole - k) / (k + pole),
+            x1: 0.0,
+            y1: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let out = self.b0 * sample + self.b1 * self.x1 - self.a1 * self.y1;
+        self.x1 = sample;
+        self.y1 = out;
+        out
+    }
+}
+
+/// Band-pass as a second-order high-pass then a second-order low-pass,
+/// Butterworth, for 12 dB per octave slopes on each side.
+#[derive(Clone, Debug)]
+pub struct BandPass {
+    high_pass: Biquad,
+    low_pass: Biquad,
+}
+
+impl BandPass {
+    pub fn new(low_hz: f32, high_hz: f32, sample_rate: u32) -> BandPass {
+        BandPass {
+            high_pass: Biquad::new(low_hz, sample_rate, true),
+            low_pass: Biquad::new(high_hz, sample_rate, false),
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.low_pass.process(self.high_pass.process(sample))
+    }
+}
+
+/// Butterworth high-pass or low-pass biquad, from the Audio EQ Cookbook.
+#[derive(Clone, Debug)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn new(cutoff_hz: f32, sample_rate: u32, high_pass: bool) -> Biquad {
+        let w0 = 2.0 * PI * cutoff_hz / sample_rate as f32;
+        let alpha = w0.sin() / 2.0f32.sqrt();
+        let a0 = 1.0 + alpha;
+        let cos = w0.cos();
+        let (b0, b1) = if high_pass {
+            ((1.0 + cos) / 2.0, -(1.0 + cos))
+        } else {
+            ((1.0 - cos) / 2.0, 1.0 - cos)
+        };
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let out = self.b0 * sample + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        (self.x2, self.x1) = (self.x1, sample);
+        (self.y2, self.y1) = (self.y1, out);
+        out
+    }
+}
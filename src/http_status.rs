@@ -0,0 +1,51 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::switch::Status;
+
+/// Serve the switch status as JSON on GET `/`, one connection at a time.
+pub fn serve(addr: &str, status: Arc<Mutex<Status>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("HTTP status on http://{}/", listener.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &status) {
+                eprintln!("HTTP status error: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(stream: TcpStream, status: &Mutex<Status>) -> io::Result<()> {
+    // a stalled client must not hold the only serving thread forever
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip headers, up to the empty line
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (code, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/")) => ("200 OK", status.lock().unwrap().to_json()),
+        _ => ("404 Not Found", "{\"error\":\"not found\"}".to_string()),
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        body.len(),
+        body
+    )
+}
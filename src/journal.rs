@@ -0,0 +1,52 @@
+use std::io;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+/// Socket of the native journal protocol.
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Connection to the systemd journal, sending entries with structured fields
+/// through its native protocol, without libsystemd.
+pub struct Journal {
+    #[cfg(unix)]
+    socket: UnixDatagram,
+}
+
+impl Journal {
+    /// Connect to the journal, failing when not running under systemd.
+    #[cfg(unix)]
+    pub fn connect() -> io::Result<Journal> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(SOCKET)?;
+        Ok(Journal { socket })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect() -> io::Result<Journal> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} is Unix only", SOCKET),
+        ))
+    }
+
+    /// Send an entry of `fields`, upper case names and their values.
+    pub fn send(&self, fields: &[(&str, &str)]) -> io::Result<()> {
+        let mut entry = Vec::new();
+        for (name, value) in fields {
+            entry.extend_from_slice(name.as_bytes());
+            if value.contains('\n') {
+                // the value is then given with its length, in binary
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                entry.push(b'=');
+            }
+            entry.extend_from_slice(value.as_bytes());
+            entry.push(b'\n');
+        }
+        #[cfg(unix)]
+        return self.socket.send(&entry).map(|_| ());
+        #[cfg(not(unix))]
+        unreachable!("no journal to send {} bytes to", entry.len());
+    }
+}
@@ -0,0 +1,287 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::SystemTime;
+
+use docopt::Docopt;
+
+use crate::common;
+
+const USAGE: &str = "
+Print a level history ring file written with --level-history.
+
+Prints one record per line, oldest first, as the Unix time, the level in dB and
+the state, e.g. `1700000000.123 -42.5 off`. The levels alone can be replayed
+with: cut -d' ' -f2 | silentcmd level <cmd-on> [<cmd-off>]
+
+Usage:
+  silentcmd history <file>
+  silentcmd history --help
+
+Options:
+  -h --help  Show this screen.
+";
+
+/// Identifies the file and its format version.
+const MAGIC: &[u8; 8] = b"SCLEVEL1";
+const HEADER_LEN: u64 = 32;
+const RECORD_LEN: u64 = 16;
+/// Offset of the total count of records written in the header.
+const WRITTEN_OFFSET: u64 = 24;
+
+/// One level update.
+#[derive(Clone, Copy, Debug)]
+pub struct Record {
+    /// Unix time in seconds
+    pub time: f64,
+    pub level_db: f32,
+    pub is_on: bool,
+}
+
+/// Level updates written to a fixed-size ring file, the newest overwriting the
+/// oldest, to look at the levels leading to a problem after the fact.
+///
+/// The file, all little-endian, starts with a 32 bytes header:
+///
+/// - magic `SCLEVEL1`
+/// - record size, u32, 16
+/// - capacity in records, u32
+/// - reserved, 8 bytes
+/// - total count of records written, u64
+///
+/// followed by `capacity` records of:
+///
+/// - Unix time in seconds, f64
+/// - level in dB, f32
+/// - flags, u32, bit 0 set while on
+///
+/// Record `n` of the total count is in slot `n % capacity`, the oldest one
+/// kept being `written - min(written, capacity)`. An existing file of the same
+/// capacity is continued, keeping the history of previous runs.
+///
+/// Records are written by a thread, never blocking the level updates.
+pub struct LevelHistory {
+    tx: mpsc::Sender<Record>,
+}
+
+impl LevelHistory {
+    /// Write to the file at `path`, created or resized to hold `capacity` records.
+    pub fn create(path: &str, capacity: u32) -> io::Result<LevelHistory> {
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the level history needs room for at least one record",
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let written = match read_header(&file) {
+            Ok((file_capacity, written)) if file_capacity == capacity => written,
+            _ => {
+                file.set_len(0)?;
+                write_header(&file, capacity)?;
+                file.set_len(HEADER_LEN + u64::from(capacity) * RECORD_LEN)?;
+                0
+            }
+        };
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut ring = Ring {
+                file,
+                capacity: u64::from(capacity),
+                written,
+            };
+            // write what queued up while busy in one go
+            while let Ok(record) = rx.recv() {
+                let records: Vec<Record> = Some(record).into_iter().chain(rx.try_iter()).collect();
+                if let Err(e) = ring.write(&records) {
+                    eprintln!("Warning: level history stopped, {}", e);
+                    break;
+                }
+            }
+        });
+        Ok(LevelHistory { tx })
+    }
+
+    /// Add a level update, timestamped now.
+    pub fn record(&self, level_db: f32, is_on: bool) {
+        self.tx
+            .send(Record {
+                time: common::unix_time(SystemTime::now()),
+                level_db,
+                is_on,
+            })
+            .ok();
+    }
+}
+
+struct Ring {
+    file: File,
+    capacity: u64,
+    written: u64,
+}
+
+impl Ring {
+    /// Write `records` in at most two contiguous writes, wrapping around at
+    /// the end of the file, then the count in the header.
+    fn write(&mut self, records: &[Record]) -> io::Result<()> {
+        // only the last lap matters if more records than the capacity queued up
+        let skip = records.len().saturating_sub(self.capacity as usize);
+        self.written += skip as u64;
+        let mut records = &records[skip..];
+        while !records.is_empty() {
+            let slot = self.written % self.capacity;
+            let count = records.len().min((self.capacity - slot) as usize);
+            let bytes: Vec<u8> = records[..count].iter().flat_map(encode).collect();
+            self.file
+                .write_all_at(&bytes, HEADER_LEN + slot * RECORD_LEN)?;
+            self.written += count as u64;
+            records = &records[count..];
+        }
+        self.file
+            .write_all_at(&self.written.to_le_bytes(), WRITTEN_OFFSET)
+    }
+}
+
+fn encode(record: &Record) -> [u8; RECORD_LEN as usize] {
+    let mut bytes = [0u8; RECORD_LEN as usize];
+    bytes[0..8].copy_from_slice(&record.time.to_le_bytes());
+    bytes[8..12].copy_from_slice(&record.level_db.to_le_bytes());
+    bytes[12..16].copy_from_slice(&u32::from(record.is_on).to_le_bytes());
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Record {
+    Record {
+        time: f64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+        level_db: f32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+        is_on: u32::from_le_bytes(bytes[12..16].try_into().unwrap()) & 1 == 1,
+    }
+}
+
+/// The positional reads and writes of Unix, from those of Windows, which
+/// also move the cursor of the file, never used here.
+#[cfg(windows)]
+trait FileExt {
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+}
+
+#[cfg(windows)]
+impl FileExt for File {
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_write(self, buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match std::os::windows::fs::FileExt::seek_read(self, buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_header(file: &File, capacity: u32) -> io::Result<()> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    header[0..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&(RECORD_LEN as u32).to_le_bytes());
+    header[12..16].copy_from_slice(&capacity.to_le_bytes());
+    file.write_all_at(&header, 0)
+}
+
+/// Capacity and total count of records written, from a valid header.
+fn read_header(file: &File) -> io::Result<(u32, u64)> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    file.read_exact_at(&mut header, 0)?;
+    let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+    if &header[0..8] != MAGIC || u64::from(u32_at(8)) != RECORD_LEN || u32_at(12) == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a level history file",
+        ));
+    }
+    let capacity = u32_at(12);
+    let written = u64::from_le_bytes(header[24..32].try_into().unwrap());
+    let len = file.metadata()?.len();
+    if len < HEADER_LEN + u64::from(capacity) * RECORD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated level history file",
+        ));
+    }
+    Ok((capacity, written))
+}
+
+/// Records of the level history file at `path`, oldest first.
+pub fn read(path: &str) -> io::Result<Vec<Record>> {
+    let file = File::open(path)?;
+    let (capacity, written) = read_header(&file)?;
+    let capacity = u64::from(capacity);
+    let mut bytes = vec![0u8; (capacity * RECORD_LEN) as usize];
+    file.read_exact_at(&mut bytes, HEADER_LEN)?;
+
+    let first = written.saturating_sub(capacity);
+    Ok((first..written)
+        .map(|n| {
+            let offset = ((n % capacity) * RECORD_LEN) as usize;
+            decode(&bytes[offset..offset + RECORD_LEN as usize])
+        })
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_file: String,
+}
+
+/// Print a level history file, from the arguments following `silentcmd history`.
+pub fn run(args: Vec<String>) {
+    let argv = ["silentcmd", "history"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain(args);
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.argv(argv).deserialize())
+        .unwrap_or_else(|e| e.exit());
+
+    let records = read(&args.arg_file).unwrap_or_else(|e| {
+        eprintln!("Unable to read level history {}: {}", args.arg_file, e);
+        process::exit(1);
+    });
+    for record in records {
+        println!(
+            "{:.3} {:.1} {}",
+            record.time,
+            record.level_db,
+            if record.is_on { "on" } else { "off" }
+        );
+    }
+}
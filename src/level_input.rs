@@ -0,0 +1,7 @@
+extern crate silentcmd;
+
+use std::env;
+
+fn main() {
+    silentcmd::backend::level::run(env::args().collect());
+}
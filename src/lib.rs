@@ -0,0 +1,42 @@
+#[macro_use]
+extern crate serde_derive;
+#[cfg(target_os = "linux")]
+extern crate alsa;
+#[cfg(feature = "cpal")]
+extern crate cpal;
+extern crate dasp;
+extern crate docopt;
+extern crate hound;
+extern crate jack;
+extern crate midir;
+
+#[macro_use]
+pub mod cli;
+
+pub mod action;
+pub mod backend;
+pub mod common;
+pub mod control;
+pub mod detect;
+pub mod dump;
+pub mod duty;
+pub mod dylib;
+pub mod fifo;
+pub mod filter;
+pub mod http_status;
+pub mod journal;
+pub mod json;
+pub mod level_history;
+pub mod midi;
+pub mod noise_floor;
+pub mod onset;
+pub mod output;
+pub mod processor;
+pub mod quiet_hours;
+pub mod shutdown;
+pub mod spectrogram;
+pub mod statsd;
+pub mod status_file;
+pub mod switch;
+pub mod wav_file;
+pub mod xrun;
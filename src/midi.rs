@@ -0,0 +1,71 @@
+use midir::{MidiOutput, MidiOutputConnection};
+
+use crate::action::SwitchAction;
+use crate::switch::Transition;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xb0;
+
+/// Send a note, or a control change when `cc` is set, on each transition.
+pub struct MidiAction {
+    connection: MidiOutputConnection,
+    channel: u8,
+    note: u8,
+    cc: Option<u8>,
+}
+
+impl MidiAction {
+    /// Connect to the first output port whose name contains `port_name`.
+    /// `channel` is 1 to 16.
+    pub fn connect(
+        port_name: &str,
+        channel: u8,
+        note: u8,
+        cc: Option<u8>,
+    ) -> Result<MidiAction, String> {
+        let output = MidiOutput::new("silentcmd").map_err(|e| e.to_string())?;
+        let port = output
+            .ports()
+            .into_iter()
+            .find(|p| {
+                output
+                    .port_name(p)
+                    .map(|name| name.contains(port_name))
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| format!("no MIDI output port matching {:?}", port_name))?;
+        eprintln!(
+            "MIDI output: {}",
+            output.port_name(&port).unwrap_or_default()
+        );
+
+        let connection = output
+            .connect(&port, "silentcmd")
+            .map_err(|e| e.to_string())?;
+        Ok(MidiAction {
+            connection,
+            channel: channel.clamp(1, 16) - 1,
+            note: note & 0x7f,
+            cc: cc.map(|cc| cc & 0x7f),
+        })
+    }
+}
+
+impl SwitchAction for MidiAction {
+    fn run(&mut self, transition: &Transition) {
+        let state = transition.state;
+        let message = match self.cc {
+            Some(cc) => [
+                CONTROL_CHANGE | self.channel,
+                cc,
+                if state { 127 } else { 0 },
+            ],
+            None if state => [NOTE_ON | self.channel, self.note, 127],
+            None => [NOTE_OFF | self.channel, self.note, 0],
+        };
+        if let Err(e) = self.connection.send(&message) {
+            eprintln!("MIDI send error: {}", e);
+        }
+    }
+}
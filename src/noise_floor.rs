@@ -0,0 +1,47 @@
+use std::time::Instant;
+
+/// Lowest level taken as the noise floor, digital silence having none.
+const MIN_FLOOR_DB: f32 = -120.0;
+
+/// Noise floor estimate from the level, following it down right away and up
+/// slowly, only when asked to so that a long signal is not taken for noise.
+#[derive(Clone, Debug)]
+pub struct NoiseFloor {
+    rise_db_per_s: f32,
+    floor_db: Option<f32>,
+    last: Instant,
+}
+
+impl NoiseFloor {
+    pub fn new(rise_db_per_s: f32) -> NoiseFloor {
+        NoiseFloor {
+            rise_db_per_s,
+            floor_db: None,
+            last: Instant::now(),
+        }
+    }
+
+    /// Track `level_db`, the floor rising toward it only if `rise`, returning
+    /// the floor in dB.
+    pub fn update(&mut self, level_db: f32, rise: bool) -> f32 {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f32();
+        self.last = now;
+
+        let level_db = level_db.max(MIN_FLOOR_DB);
+        let floor_db = match self.floor_db {
+            Some(floor_db) if level_db > floor_db && rise => {
+                (floor_db + self.rise_db_per_s * elapsed).min(level_db)
+            }
+            Some(floor_db) if level_db > floor_db => floor_db,
+            _ => level_db,
+        };
+        self.floor_db = Some(floor_db);
+        floor_db
+    }
+
+    /// Current estimate, once a level was given.
+    pub fn floor_db(&self) -> Option<f32> {
+        self.floor_db
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Time over which the level has to rise for an onset.
+const SPAN: Duration = Duration::from_millis(200);
+
+/// Onset detection on the level: a quick rise, as at the start of a sound,
+/// rather than a slow ramp of background noise.
+pub struct Onset {
+    rise_db: f32,
+    /// levels over the span, oldest first
+    recent: VecDeque<(Instant, f32)>,
+}
+
+impl Onset {
+    /// Onset on a rise of at least `rise_db` within the span.
+    pub fn new(rise_db: f32) -> Onset {
+        Onset {
+            rise_db,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Whether `level_db` is at least the rise above the lowest level of the
+    /// span before it.
+    pub fn update(&mut self, level_db: f32) -> bool {
+        let now = Instant::now();
+        while self
+            .recent
+            .front()
+            .is_some_and(|&(at, _)| now.duration_since(at) > SPAN)
+        {
+            self.recent.pop_front();
+        }
+
+        let lowest = self
+            .recent
+            .iter()
+            .map(|&(_, db)| db)
+            .fold(f32::INFINITY, f32::min);
+        self.recent.push_back((now, level_db));
+        level_db - lowest >= self.rise_db
+    }
+}
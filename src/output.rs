@@ -0,0 +1,195 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::common;
+use crate::switch::SwitchStatus;
+
+/// Switch status value printed when on, to plot alongside the dB level.
+const STATUS_ON: f32 = 20.0;
+
+/// Unit of the printed level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LevelUnit {
+    Db,
+    Linear,
+    /// dB then linear, in two columns
+    Both,
+}
+
+impl LevelUnit {
+    pub fn parse(name: &str) -> Option<LevelUnit> {
+        match name {
+            "db" => Some(LevelUnit::Db),
+            "linear" => Some(LevelUnit::Linear),
+            "both" => Some(LevelUnit::Both),
+            _ => None,
+        }
+    }
+
+    /// Level column(s) for a linear envelope value, tab separated.
+    pub fn format(self, level: f32) -> String {
+        match self {
+            LevelUnit::Db => format!("{:?}", common::to_db(level)),
+            LevelUnit::Linear => format!("{:?}", level),
+            LevelUnit::Both => format!("{:?}\t{:?}", common::to_db(level), level),
+        }
+    }
+}
+
+/// Verbose level and status output.
+pub struct Output {
+    verbose: bool,
+    status_ramp: Duration,
+    unit: LevelUnit,
+    knee_db: f32,
+    clip_ratio: bool,
+    /// printed status values averaged, 1 for none
+    status_smoothing: usize,
+    /// last status values, for the smoothing
+    status_history: RefCell<VecDeque<f32>>,
+    /// first column telling the input, if several
+    label: Option<String>,
+    /// minimal time between lines, and when the last one was printed
+    interval: Duration,
+    last_print: Cell<Option<Instant>>,
+}
+
+impl Output {
+    /// `status_ramp` in seconds, 0 for a hard step of the status value.
+    pub fn new(verbose: bool, status_ramp: f32, unit: LevelUnit) -> Output {
+        Output {
+            verbose,
+            status_ramp: Duration::from_secs_f32(status_ramp),
+            unit,
+            knee_db: 0.0,
+            clip_ratio: false,
+            status_smoothing: 1,
+            status_history: RefCell::new(VecDeque::new()),
+            label: None,
+            interval: Duration::ZERO,
+            last_print: Cell::new(None),
+        }
+    }
+
+    /// While off, raise the printed status softly with the level over this
+    /// many dB below the threshold, reaching on at the threshold.
+    pub fn set_knee(&mut self, knee_db: f32) {
+        self.knee_db = knee_db;
+    }
+
+    /// Print the clip ratio of the level after it.
+    pub fn set_clip_ratio(&mut self) {
+        self.clip_ratio = true;
+    }
+
+    /// Print the status as the average of the last `count` values, for a
+    /// cleaner plot. Detection is unaffected.
+    pub fn set_status_smoothing(&mut self, count: usize) {
+        self.status_smoothing = count.max(1);
+    }
+
+    /// Print at most one line per `interval`, the latest values when due,
+    /// whatever the rate of the level updates.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Start each line with this label, to tell inputs apart.
+    pub fn set_label(&mut self, label: &str) {
+        self.label = Some(label.to_string());
+    }
+
+    /// Print the label if any, level in `unit`, peak-hold value, clip ratio and
+    /// SNR if enabled, switch status and the off countdown (s) if any, tab separated.
+    pub fn print_status(&self, level: f32, switch: &SwitchStatus) {
+        if !self.verbose {
+            return;
+        }
+        // the smoothing takes the lines skipped too
+        let status = self.smooth(self.status_value(switch));
+        if !self.due() {
+            return;
+        }
+
+        let mut level = self.unit.format(level);
+        if let Some(label) = &self.label {
+            level = format!("{}\t{}", label, level);
+        }
+        if let Some(peak_hold_db) = switch.status().peak_hold_db {
+            level = format!(
+                "{}\t{}",
+                level,
+                self.unit.format(common::from_db(peak_hold_db))
+            );
+        }
+        if self.clip_ratio {
+            level = format!("{}\t{:.4}", level, switch.status().clip_ratio);
+        }
+        if let Some(noise_floor_db) = switch.status().noise_floor_db {
+            let snr_db = switch.status().level_db - noise_floor_db;
+            level = format!("{}\t{:.1}", level, snr_db);
+        }
+
+        match switch.remaining() {
+            Some(remaining) => println!("{}\t{:?}\t{:.1}", level, status, remaining.as_secs_f32()),
+            None => println!("{}\t{:?}", level, status),
+        }
+    }
+
+    /// Whether a line is due, taking it as printed if so.
+    fn due(&self) -> bool {
+        let now = Instant::now();
+        match self.last_print.get() {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last_print.set(Some(now));
+                true
+            }
+        }
+    }
+
+    /// Status value, ramping over `status_ramp` after each transition
+    /// and following the level through the knee while off.
+    fn status_value(&self, switch: &SwitchStatus) -> f32 {
+        let progress = if self.status_ramp.is_zero() {
+            1.0
+        } else {
+            let elapsed = SystemTime::now()
+                .duration_since(switch.status().since)
+                .unwrap_or_default();
+            (elapsed.as_secs_f32() / self.status_ramp.as_secs_f32()).min(1.0)
+        };
+
+        if switch.is_on() {
+            STATUS_ON * progress
+        } else {
+            STATUS_ON * (1.0 - progress).max(self.knee(switch))
+        }
+    }
+
+    /// Average of `status` with the previous values.
+    fn smooth(&self, status: f32) -> f32 {
+        if self.status_smoothing == 1 {
+            return status;
+        }
+
+        let mut history = self.status_history.borrow_mut();
+        if history.len() == self.status_smoothing {
+            history.pop_front();
+        }
+        history.push_back(status);
+        history.iter().sum::<f32>() / history.len() as f32
+    }
+
+    /// Position in the knee below the threshold, 0 to 1 with a quadratic curve.
+    fn knee(&self, switch: &SwitchStatus) -> f32 {
+        if self.knee_db <= 0.0 {
+            return 0.0;
+        }
+
+        let status = switch.status();
+        let x = ((status.level_db - switch.threshold_db()) / self.knee_db + 1.0).clamp(0.0, 1.0);
+        x * x
+    }
+}
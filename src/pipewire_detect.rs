@@ -0,0 +1,7 @@
+extern crate silentcmd;
+
+use std::env;
+
+fn main() {
+    silentcmd::backend::pipewire::run(env::args().collect());
+}
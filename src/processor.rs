@@ -0,0 +1,140 @@
+use dasp::sample::ToSample;
+use dasp::Sample;
+
+use crate::common;
+use crate::detect::{Detector, Measure};
+use crate::switch::{SwitchStatus, Transition};
+
+/// Settings of a `Processor`, see `ProcessorConfig::new` for the defaults.
+#[derive(Clone, Debug)]
+pub struct ProcessorConfig {
+    pub sample_rate: u32,
+    /// detection window in frames, whatever the size of the buffers processed
+    pub window: usize,
+    /// minimal level to switch on
+    pub threshold_db: f32,
+    /// gap below the threshold for the level to count as silence once on
+    pub hysteresis_db: f32,
+    /// time without signal before switching off, in seconds
+    pub timeout_s: u64,
+    /// envelope attack and release in milliseconds, None for the defaults
+    pub attack_ms: Option<f32>,
+    pub release_ms: Option<f32>,
+    pub measure: Measure,
+}
+
+impl ProcessorConfig {
+    /// The defaults of the binaries: RMS over 1024 frames, -60 dB and 30 s.
+    pub fn new(sample_rate: u32) -> ProcessorConfig {
+        ProcessorConfig {
+            sample_rate,
+            window: 1024,
+            threshold_db: -60.0,
+            hysteresis_db: 0.0,
+            timeout_s: 30,
+            attack_ms: None,
+            release_ms: None,
+            measure: Measure::Rms,
+        }
+    }
+}
+
+/// Detection and switch for embedding in an audio application: feed it the
+/// mono buffers of a capture callback and react to the transitions returned.
+///
+/// ```no_run
+/// use silentcmd::processor::{Processor, ProcessorConfig};
+///
+/// let mut processor = Processor::new(&ProcessorConfig::new(48000));
+/// let buffer = [0.0f32; 256];
+/// // in the capture callback
+/// if let Some(transition) = processor.process(&buffer) {
+///     println!("switched {}", if transition.state { "on" } else { "off" });
+/// }
+/// ```
+///
+/// A processor is `Send`, to be moved into the audio thread, and used from
+/// one thread at a time: it holds no lock and runs no action itself.
+///
+/// `process` of a processor made by `new` is real-time safe once warmed up: it
+/// does not allocate after its first buffers, which size the analysis frame and
+/// the detection buffers, and does no I/O. Parts set up with `from_parts` are
+/// not, as the binaries set them up: debug audio, heartbeat, level history or
+/// logging of the switch print or write from the calling thread, so the JACK
+/// process callback does I/O with these options, as with its transition dump
+/// and status output. A shared status is published with a `try_lock` that
+/// never blocks.
+pub struct Processor {
+    detector: Detector,
+    switch: SwitchStatus,
+}
+
+impl Processor {
+    pub fn new(config: &ProcessorConfig) -> Processor {
+        let envelope =
+            common::envelope_frames(config.attack_ms, config.release_ms, config.sample_rate);
+        let mut detector = Detector::new(config.window, envelope);
+        detector.set_measure(config.measure);
+        detector.set_frame_len(config.window);
+        let mut switch = SwitchStatus::detached(config.threshold_db, config.timeout_s);
+        switch.set_hysteresis(config.hysteresis_db);
+        Processor::from_parts(detector, switch)
+    }
+
+    /// Processor of a detector and switch set up by the caller, as the binaries do
+    /// with their options, the switch possibly running actions.
+    pub fn from_parts(detector: Detector, switch: SwitchStatus) -> Processor {
+        Processor { detector, switch }
+    }
+
+    /// Detect on a mono buffer and update the switch, returning the last
+    /// transition it caused, if any.
+    pub fn process(&mut self, buf: &[f32]) -> Option<Transition> {
+        self.process_with(buf, |_, _| {})
+    }
+
+    /// Like `process`, also calling `on_level` with the linear level and the
+    /// switch after each level update.
+    pub fn process_with<S, F>(&mut self, buf: &[S], mut on_level: F) -> Option<Transition>
+    where
+        S: Sample + ToSample<f32>,
+        F: FnMut(f32, &SwitchStatus),
+    {
+        let switch = &mut self.switch;
+        let mut transition = None;
+        self.detector.process(buf, |level, detector| {
+            switch.set_clip_ratio(detector.clip_ratio());
+            switch.set_peak_hold(detector.peak_hold().map(common::to_db));
+            switch.update_window(
+                common::to_db(level),
+                detector.silent(switch.active_threshold_db()),
+            );
+            if let Some(last) = switch.take_transition() {
+                transition = Some(last);
+            }
+            on_level(level, switch);
+        });
+        transition
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.switch.is_on()
+    }
+
+    pub fn detector_mut(&mut self) -> &mut Detector {
+        &mut self.detector
+    }
+
+    pub fn switch(&self) -> &SwitchStatus {
+        &self.switch
+    }
+
+    pub fn switch_mut(&mut self) -> &mut SwitchStatus {
+        &mut self.switch
+    }
+
+    /// The switch, to shut it down.
+    pub fn into_switch(self) -> SwitchStatus {
+        self.switch
+    }
+}
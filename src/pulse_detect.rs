@@ -0,0 +1,7 @@
+extern crate silentcmd;
+
+use std::env;
+
+fn main() {
+    silentcmd::backend::pulse::run(env::args().collect());
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Interval between checks of the local time.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Daily window of local time in which the switch stays off, from `start`
+/// included to `end` excluded in minutes since midnight, wrapping past
+/// midnight when `end` comes first.
+#[derive(Clone, Debug)]
+pub struct QuietHours {
+    start: u32,
+    end: u32,
+    /// last check of the local time and its result
+    checked: Option<(Instant, bool)>,
+}
+
+impl QuietHours {
+    /// Window from `HH:MM-HH:MM`, None if invalid or empty.
+    pub fn parse(window: &str) -> Option<QuietHours> {
+        let (start, end) = window.split_once('-')?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        (start != end).then_some(QuietHours {
+            start,
+            end,
+            checked: None,
+        })
+    }
+
+    /// Whether `minute` since midnight is within the window.
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    /// Whether the local time is within the window, read at most every second.
+    pub fn now(&mut self) -> bool {
+        match self.checked {
+            Some((at, quiet)) if at.elapsed() < CHECK_INTERVAL => quiet,
+            _ => {
+                let quiet = self.contains(local_minute());
+                self.checked = Some((Instant::now(), quiet));
+                quiet
+            }
+        }
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+/// Minutes since midnight of `HH:MM`.
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Minutes since midnight in local time.
+fn local_minute() -> u32 {
+    // localtime_r and localtime_s only write to the given struct
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        #[cfg(unix)]
+        libc::localtime_r(&now, &mut tm);
+        #[cfg(windows)]
+        libc::localtime_s(&mut tm, &now);
+        (tm.tm_hour * 60 + tm.tm_min) as u32
+    }
+}
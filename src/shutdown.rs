@@ -0,0 +1,93 @@
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::ptr;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+static REQUESTED: Mutex<bool> = Mutex::new(false);
+static CONDVAR: Condvar = Condvar::new();
+
+/// Turn SIGINT and SIGTERM into a shutdown request, and request one
+/// after `max_runtime` if given. On Windows, Ctrl+C, Ctrl+Break and closing
+/// the console take the place of the signals.
+///
+/// Must be called before spawning any thread: the signals are blocked here,
+/// inherited by every thread created afterwards, and received by a dedicated
+/// thread instead of interrupting whatever code happens to run.
+pub fn install(max_runtime: Option<Duration>) {
+    handle_signals();
+
+    if let Some(max_runtime) = max_runtime {
+        thread::spawn(move || {
+            thread::sleep(max_runtime);
+            eprintln!("Maximum runtime reached, shutting down");
+            request();
+        });
+    }
+}
+
+#[cfg(unix)]
+fn handle_signals() {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+
+        thread::spawn(move || {
+            let mut signal = 0;
+            if libc::sigwait(&set, &mut signal) == 0 {
+                eprintln!("Received signal {}, shutting down", signal);
+                request();
+            }
+        });
+    }
+}
+
+#[cfg(windows)]
+fn handle_signals() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(
+            handler: Option<unsafe extern "system" fn(u32) -> i32>,
+            add: i32,
+        ) -> i32;
+    }
+
+    /// Runs on a thread of its own. Windows ends the process once it returns
+    /// from closing the console or the session, so it then gives the shutdown
+    /// some time to run the off command.
+    unsafe extern "system" fn on_ctrl(event: u32) -> i32 {
+        eprintln!("Received console event {}, shutting down", event);
+        request();
+        // CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT and CTRL_SHUTDOWN_EVENT
+        if event >= 2 {
+            thread::sleep(Duration::from_secs(5));
+        }
+        1
+    }
+
+    unsafe {
+        SetConsoleCtrlHandler(Some(on_ctrl), 1);
+    }
+}
+
+pub fn request() {
+    *REQUESTED.lock().unwrap() = true;
+    CONDVAR.notify_all();
+}
+
+pub fn requested() -> bool {
+    *REQUESTED.lock().unwrap()
+}
+
+/// Block until a shutdown is requested.
+pub fn wait() {
+    let mut requested = REQUESTED.lock().unwrap();
+    while !*requested {
+        requested = CONDVAR.wait(requested).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate docopt;
+extern crate silentcmd;
+
+use docopt::Docopt;
+use silentcmd::{backend, common, level_history};
+use std::iter;
+use std::process;
+
+const USAGE: &str = "
+Silent Command.
+
+Usage:
+  silentcmd <backend> [<args>...]
+  silentcmd --version
+
+Backends:
+  alsa      Record from an ALSA device.
+  coreaudio Record from a CoreAudio input device, on macOS.
+  cpal      Record from an input device of the default cpal host, when built
+            with the cpal feature.
+  jack      Run as a JACK client.
+  level     Switch on levels in dB read from stdin.
+  pipewire  Record from a PipeWire node.
+  pulse     Record from a PulseAudio source.
+  wasapi    Record what a render endpoint plays, on Windows.
+  wav       Detect signal in a WAV file.
+
+Run 'silentcmd <backend> --help' for the options of a backend, and
+'silentcmd history <file>' to print a --level-history file.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    arg_backend: String,
+    arg_args: Vec<String>,
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| {
+            d.options_first(true)
+                .version(Some(common::version(&backends())))
+                .deserialize()
+        })
+        .unwrap_or_else(|e| e.exit());
+
+    if args.arg_backend == "history" {
+        level_history::run(args.arg_args);
+        return;
+    }
+
+    // the backend parses its options from the remaining arguments
+    let argv = iter::once(format!("silentcmd {}", args.arg_backend))
+        .chain(args.arg_args)
+        .collect();
+    match args.arg_backend.as_str() {
+        #[cfg(target_os = "linux")]
+        "alsa" => backend::alsa::run(argv),
+        #[cfg(target_os = "macos")]
+        "coreaudio" => backend::coreaudio::run(argv),
+        #[cfg(feature = "cpal")]
+        "cpal" => backend::cpal::run(argv),
+        "jack" => backend::jack::run(argv),
+        "level" => backend::level::run(argv),
+        "pipewire" => backend::pipewire::run(argv),
+        "pulse" => backend::pulse::run(argv),
+        #[cfg(windows)]
+        "wasapi" => backend::wasapi::run(argv),
+        "wav" => backend::wav::run(argv),
+        name => {
+            eprintln!("Unknown backend: {}", name);
+            process::exit(1);
+        }
+    }
+}
+
+/// Backends built for this platform and features, as listed by --version.
+fn backends() -> String {
+    let mut backends = Vec::new();
+    if cfg!(target_os = "linux") {
+        backends.push("ALSA");
+    }
+    if cfg!(target_os = "macos") {
+        backends.push("CoreAudio");
+    }
+    if cfg!(feature = "cpal") {
+        backends.push("cpal");
+    }
+    backends.extend(["JACK", "levels", "PipeWire", "PulseAudio"]);
+    if cfg!(windows) {
+        backends.push("WASAPI");
+    }
+    backends.push("WAV");
+    backends.join(", ")
+}
@@ -0,0 +1,296 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::common;
+
+/// FFT length, giving half as many frequency rows.
+const FFT_LEN: usize = 1024;
+/// Columns kept before merging them in pairs, the image being up to twice as wide.
+const MAX_WIDTH: usize = 2048;
+/// Power range mapped to the colors, in dB relative to a full-scale sine.
+const FLOOR_DB: f32 = -120.0;
+/// Height of the strip marking the windows reaching the threshold.
+const STRIP_HEIGHT: usize = 8;
+
+/// Colors from silent to full scale, interpolated.
+const COLORS: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [0, 0, 140],
+    [160, 0, 160],
+    [240, 80, 0],
+    [255, 220, 0],
+    [255, 255, 255],
+];
+const BAND_COLOR: [u8; 3] = [0, 255, 255];
+const DETECTED_COLOR: [u8; 3] = [0, 200, 0];
+const SILENT_COLOR: [u8; 3] = [60, 60, 60];
+
+/// Spectrogram of a mono signal, one column per detection window, written as
+/// a PNG image with the windows reaching the threshold marked below it.
+///
+/// Columns are merged in pairs, keeping the loudest, whenever there are too
+/// many of them, so that long files give an image of bounded width.
+pub struct Spectrogram {
+    sample_rate: u32,
+    hann: Vec<f32>,
+    /// power of each frequency in 0..=255 from `FLOOR_DB` to 0 dB, low frequencies
+    /// first, with the level of the windows merged in the column
+    columns: Vec<(Vec<u8>, f32)>,
+    /// windows per column
+    merge: usize,
+    /// column merging windows so far, with their count
+    pending: Option<(Vec<u8>, f32, usize)>,
+}
+
+impl Spectrogram {
+    pub fn new(sample_rate: u32) -> Spectrogram {
+        Spectrogram {
+            sample_rate,
+            hann: hann(FFT_LEN),
+            columns: Vec::new(),
+            merge: 1,
+            pending: None,
+        }
+    }
+
+    /// Add the window `mono` detected at `level`, its power averaged over the
+    /// FFTs of its successive blocks.
+    ///
+    /// A block shorter than the FFT, as the last one, gets a Hann window of
+    /// its own length and is zero-padded.
+    pub fn add(&mut self, mono: &[f32], level: f32) {
+        let mut power = vec![0.0f32; FFT_LEN / 2];
+        let mut buf = vec![(0.0f32, 0.0f32); FFT_LEN];
+        let blocks = mono.chunks(FFT_LEN);
+        let count = blocks.len().max(1);
+        for block in blocks {
+            let short_hann;
+            let hann = if block.len() == FFT_LEN {
+                &self.hann
+            } else {
+                short_hann = hann(block.len());
+                &short_hann
+            };
+            for (i, value) in buf.iter_mut().enumerate() {
+                let sample = block.get(i).map_or(0.0, |sample| sample * hann[i]);
+                *value = (sample, 0.0);
+            }
+            fft(&mut buf);
+            // a full-scale sine peaks at a quarter of the block length through the window
+            let full_scale = (block.len() as f32 / 4.0).powi(2);
+            for (power, &(re, im)) in power.iter_mut().zip(&buf) {
+                *power += (re * re + im * im) / full_scale;
+            }
+        }
+
+        let column: Vec<u8> = power
+            .iter()
+            .map(|&p| {
+                let db = 10.0 * (p / count as f32).log10();
+                ((db - FLOOR_DB) / -FLOOR_DB * 255.0).clamp(0.0, 255.0) as u8
+            })
+            .collect();
+
+        let (pending, pending_level, windows) = self
+            .pending
+            .get_or_insert_with(|| (vec![0; FFT_LEN / 2], 0.0, 0));
+        merge_into(pending, &column);
+        *pending_level = pending_level.max(level);
+        *windows += 1;
+        if *windows == self.merge {
+            let (column, level, _) = self.pending.take().unwrap();
+            self.columns.push((column, level));
+        }
+
+        if self.columns.len() == 2 * MAX_WIDTH {
+            self.columns = self
+                .columns
+                .chunks(2)
+                .map(|pair| {
+                    let (mut column, level) = pair[0].clone();
+                    merge_into(&mut column, &pair[1].0);
+                    (column, level.max(pair[1].1))
+                })
+                .collect();
+            self.merge *= 2;
+        }
+    }
+
+    /// Windows per column, each column being the loudest of them.
+    pub fn windows_per_column(&self) -> usize {
+        self.merge
+    }
+
+    /// Write the image to `path`, high frequencies at the top, marking the
+    /// columns reaching `threshold_db` below it, and the edges of `band` (Hz)
+    /// as dotted lines.
+    pub fn write(&self, path: &str, threshold_db: f32, band: Option<(f32, f32)>) -> io::Result<()> {
+        let mut columns = self.columns.clone();
+        columns.extend(
+            self.pending
+                .clone()
+                .map(|(column, level, _)| (column, level)),
+        );
+        let width = columns.len().max(1);
+        let rows = FFT_LEN / 2;
+        let height = rows + STRIP_HEIGHT;
+
+        let bin_hz = self.sample_rate as f32 / FFT_LEN as f32;
+        let band_rows: Vec<usize> = band
+            .map(|(low, high)| vec![low, high])
+            .unwrap_or_default()
+            .into_iter()
+            .map(|hz| ((hz / bin_hz).round() as usize).min(rows - 1))
+            .collect();
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let color = if y < rows {
+                    let row = rows - 1 - y;
+                    if band_rows.contains(&row) && x % 2 == 0 {
+                        BAND_COLOR
+                    } else {
+                        columns
+                            .get(x)
+                            .map_or([0; 3], |(column, _)| color(column[row]))
+                    }
+                } else {
+                    match columns.get(x) {
+                        Some(&(_, level)) if common::to_db(level) >= threshold_db => DETECTED_COLOR,
+                        _ => SILENT_COLOR,
+                    }
+                };
+                pixels.extend_from_slice(&color);
+            }
+        }
+        write_png(path, width as u32, height as u32, &pixels)
+    }
+}
+
+/// Hann window of `len` samples.
+fn hann(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / len as f32).cos())
+        .collect()
+}
+
+/// Keep the louder of each frequency.
+fn merge_into(column: &mut [u8], other: &[u8]) {
+    for (value, &other) in column.iter_mut().zip(other) {
+        *value = (*value).max(other);
+    }
+}
+
+/// Color of a power value, interpolated between `COLORS`.
+fn color(value: u8) -> [u8; 3] {
+    let position = value as f32 / 255.0 * (COLORS.len() - 1) as f32;
+    let i = (position as usize).min(COLORS.len() - 2);
+    let t = position - i as f32;
+    let mut color = [0; 3];
+    for (c, channel) in color.iter_mut().enumerate() {
+        let (a, b) = (COLORS[i][c] as f32, COLORS[i + 1][c] as f32);
+        *channel = (a + (b - a) * t).round() as u8;
+    }
+    color
+}
+
+/// In-place radix-2 FFT of complex values as (re, im), of a power of two length.
+fn fft(buf: &mut [(f32, f32)]) {
+    let n = buf.len();
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            buf.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (re, im) = buf[start + k + len / 2];
+                let odd = (re * cos - im * sin, re * sin + im * cos);
+                let even = buf[start + k];
+                buf[start + k] = (even.0 + odd.0, even.1 + odd.1);
+                buf[start + k + len / 2] = (even.0 - odd.0, even.1 - odd.1);
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// Write 8-bit RGB `pixels`, row by row, as a PNG file.
+///
+/// The image data is stored without compression, which needs neither a
+/// compression library nor much time, at the cost of a larger file.
+fn write_png(path: &str, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filter and no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut file, b"IHDR", &header)?;
+
+    // each row starts with its filter type, none
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width as usize * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib stream of stored deflate blocks
+    let mut data = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    while let Some(block) = blocks.next() {
+        data.push(u8::from(blocks.peek().is_none()));
+        let len = block.len() as u16;
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&(!len).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(&raw).to_be_bytes());
+    write_chunk(&mut file, b"IDAT", &data)?;
+
+    write_chunk(&mut file, b"IEND", &[])?;
+    file.flush()
+}
+
+fn write_chunk(file: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    file.write_all(&(data.len() as u32).to_be_bytes())?;
+    file.write_all(kind)?;
+    file.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data));
+    file.write_all(&crc.to_be_bytes())
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
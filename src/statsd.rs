@@ -0,0 +1,74 @@
+use std::io;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::action::SwitchAction;
+use crate::switch::{Status, Transition};
+
+/// Send metrics to a StatsD server over UDP: a counter per transition, and
+/// the level and state as gauges at an interval, from a thread.
+///
+/// The socket does not block: metrics are dropped rather than delaying the
+/// switch, as UDP would lose them anyway.
+pub struct StatsdAction {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdAction {
+    /// Send to `addr` with metric names starting with `prefix`, e.g.
+    /// `silentcmd.level`.
+    pub fn connect(addr: &str, prefix: &str) -> io::Result<StatsdAction> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(StatsdAction {
+            socket,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Send the level and state gauges every `interval`.
+    pub fn start_gauges(&self, interval: Duration, status: Arc<Mutex<Status>>) -> io::Result<()> {
+        let socket = self.socket.try_clone()?;
+        let prefix = self.prefix.clone();
+        thread::spawn(move || loop {
+            let (is_on, level_db) = {
+                let status = status.lock().unwrap();
+                (status.is_on, status.level_db)
+            };
+            let mut packet = gauge(&prefix, "state", if is_on { "1" } else { "0" });
+            // digital silence has no dB value to report
+            if level_db.is_finite() {
+                packet = packet + "\n" + &gauge(&prefix, "level", &format!("{:.1}", level_db));
+            }
+            // a server not listening yet refuses, unreachable until it does
+            socket.send(packet.as_bytes()).ok();
+            thread::sleep(interval);
+        });
+        Ok(())
+    }
+}
+
+/// Gauge line, set to 0 first when negative: a leading sign means a change
+/// of the current value in StatsD.
+fn gauge(prefix: &str, name: &str, value: &str) -> String {
+    if value.starts_with('-') {
+        format!("{}.{}:0|g\n{}.{}:{}|g", prefix, name, prefix, name, value)
+    } else {
+        format!("{}.{}:{}|g", prefix, name, value)
+    }
+}
+
+impl SwitchAction for StatsdAction {
+    fn run(&mut self, transition: &Transition) {
+        let line = format!(
+            "{}.transitions.{}:1|c",
+            self.prefix,
+            if transition.state { "on" } else { "off" }
+        );
+        self.socket.send(line.as_bytes()).ok();
+    }
+}
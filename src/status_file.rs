@@ -0,0 +1,59 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::switch::Status;
+
+/// How often the status file is rewritten at most.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keep the file at `path` up to date with the state and level, as `on -23.4`,
+/// rewriting it when changed, at most once per second.
+///
+/// The file is replaced by renaming a temporary one next to it, so that
+/// readers never see a partial write.
+pub fn start(path: &str, status: Arc<Mutex<Status>>) -> io::Result<()> {
+    let path = PathBuf::from(path);
+    let mut last = line(&status);
+    write(&path, &last)?;
+
+    thread::spawn(move || loop {
+        thread::sleep(INTERVAL);
+        let line = line(&status);
+        if line != last {
+            if let Err(e) = write(&path, &line) {
+                eprintln!("Warning: unable to write the status file, {}", e);
+            }
+            last = line;
+        }
+    });
+    Ok(())
+}
+
+/// State left in the file at `path` by a previous run, if readable.
+pub fn read_state(path: &str) -> Option<bool> {
+    match fs::read_to_string(path).ok()?.split_whitespace().next()? {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn line(status: &Mutex<Status>) -> String {
+    let status = status.lock().unwrap();
+    format!(
+        "{} {:.1}\n",
+        if status.is_on { "on" } else { "off" },
+        status.level_db
+    )
+}
+
+fn write(path: &Path, line: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, line)?;
+    fs::rename(&tmp, path)
+}
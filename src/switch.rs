@@ -1,66 +1,846 @@
-use std::time::{Duration, Instant};
-use std::process::Command;
+use std::collections::VecDeque;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::action::SwitchAction;
+use crate::common;
+use crate::control::Control;
+use crate::duty::DutyCycle;
+use crate::level_history::LevelHistory;
+use crate::noise_floor::NoiseFloor;
+use crate::onset::Onset;
+use crate::quiet_hours::QuietHours;
+
+/// Snapshot of the switch state, shared with status reporting threads.
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    pub is_on: bool,
+    /// on and waiting for an acknowledgment to switch off
+    pub unacknowledged: bool,
+    pub level_db: f32,
+    /// fraction of clipped samples behind the level
+    pub clip_ratio: f32,
+    /// peak-hold level in dB, if enabled
+    pub peak_hold_db: Option<f32>,
+    /// noise floor in dB the threshold follows, if switching on the SNR
+    pub noise_floor_db: Option<f32>,
+    /// fraction of the duty window spent on, if enabled
+    pub duty_cycle: Option<f32>,
+    pub since: SystemTime,
+}
+
+impl Status {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"state\":\"{}\",\"unacknowledged\":{},\"level\":{},\"linear\":{},\"clip\":{:.4},\"peak_hold\":{},\"noise_floor\":{},\"duty\":{},\"since\":{:.3}}}",
+            if self.is_on { "on" } else { "off" },
+            self.unacknowledged,
+            common::json_number(self.level_db, 2),
+            common::json_number(common::from_db(self.level_db), 6),
+            self.clip_ratio,
+            self.peak_hold_db
+                .map_or("null".to_string(), |db| common::json_number(db, 2)),
+            self.noise_floor_db
+                .map_or("null".to_string(), |db| common::json_number(db, 2)),
+            self.duty_cycle
+                .map_or("null".to_string(), |duty| common::json_number(duty, 4)),
+            common::unix_time(self.since)
+        )
+    }
+}
+
+/// A switch state change, as sent to the actions.
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    pub state: bool,
+    pub level_db: f32,
+    pub time: SystemTime,
+    /// how long the switch stayed in the previous state
+    pub previous: Duration,
+    /// first switch on since started
+    pub first: bool,
+    /// when the switch decided, to measure the latency of the actions
+    pub instant: Instant,
+}
+
+impl Transition {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"state\":\"{}\",\"level\":{},\"linear\":{},\"time\":{:.3},\"previous\":{:.3}}}",
+            if self.state { "on" } else { "off" },
+            common::json_number(self.level_db, 2),
+            common::json_number(common::from_db(self.level_db), 6),
+            common::unix_time(self.time),
+            self.previous.as_secs_f32()
+        )
+    }
+}
 
 pub struct SwitchStatus {
     threshold_db: f32,
+    /// SNR in dB to switch on, the threshold following the noise floor
+    snr: Option<(f32, NoiseFloor)>,
+    /// gap below the threshold for the level to count as silence once on
+    hysteresis_db: f32,
     timeout_s: Duration,
     on_trigger_last: Instant,
+    level_db: f32,
+    clip_ratio: f32,
+    peak_hold_db: Option<f32>,
     is_on: bool,
-    tx: mpsc::Sender<bool>,
+    since: SystemTime,
+    post_cmd_mute: Duration,
+    mute_until: Option<Instant>,
+    off_deadtime: Duration,
+    /// no switching on before this, after switching off
+    off_until: Option<Instant>,
+    wait_silence: bool,
+    ack_required: bool,
+    /// no switching on for this time after an ack switched off
+    ack_cooldown: Duration,
+    /// levels count as silence, the input stalling
+    stalled: bool,
+    /// turned on at least once
+    detected: bool,
+    control: Option<mpsc::Receiver<Control>>,
+    /// window after a threshold change in which a transition runs no actions
+    reload_suppression: Duration,
+    suppress_until: Option<Instant>,
+    /// took a level update, the threshold read on startup not being a change
+    updated: bool,
+    shared: Option<Arc<Mutex<Status>>>,
+    /// per-channel switches, combined with a logical or
+    channels: Vec<SwitchStatus>,
+    /// sound needed continuously to switch on
+    min_sound: Duration,
+    /// start of the sound going on
+    sound_since: Option<Instant>,
+    /// onset needed to switch on, and whether the current sound started with one
+    onset: Option<Onset>,
+    onset_seen: bool,
+    duty: Option<DutyCycle>,
+    /// daily window forcing off, and whether in it
+    quiet_hours: Option<QuietHours>,
+    quiet: bool,
+    /// windows voting on sound, 0 not to vote, and their last decisions
+    vote_window: usize,
+    votes: VecDeque<bool>,
+    /// interval and time of the last heartbeat
+    heartbeat: Option<(Duration, Instant)>,
+    level_history: Option<LevelHistory>,
+    tx: Option<mpsc::Sender<Transition>>,
+    /// longest wait for a quiet level update before running the actions
+    defer_to_quiet: Duration,
+    /// transitions waiting for a quiet level update to be sent, oldest first
+    deferred: Vec<Transition>,
+    /// last transition, until taken
+    last_transition: Option<Transition>,
 }
 
 impl SwitchStatus {
-    pub fn new(threshold_db: f32, timeout_s: u64, tx: mpsc::Sender<bool>) -> SwitchStatus {
+    pub fn new(threshold_db: f32, timeout_s: u64, tx: mpsc::Sender<Transition>) -> SwitchStatus {
+        SwitchStatus {
+            tx: Some(tx),
+            ..SwitchStatus::detached(threshold_db, timeout_s)
+        }
+    }
+
+    /// A switch running no actions, its transitions being taken with `take_transition`.
+    pub fn detached(threshold_db: f32, timeout_s: u64) -> SwitchStatus {
         SwitchStatus {
             threshold_db,
+            snr: None,
+            hysteresis_db: 0.0,
             timeout_s: Duration::from_secs(timeout_s),
             on_trigger_last: Instant::now(),
+            level_db: f32::NEG_INFINITY,
+            clip_ratio: 0.0,
+            peak_hold_db: None,
             is_on: false,
-            tx,
+            since: SystemTime::now(),
+            post_cmd_mute: Duration::ZERO,
+            mute_until: None,
+            off_deadtime: Duration::ZERO,
+            off_until: None,
+            wait_silence: false,
+            ack_required: false,
+            ack_cooldown: Duration::ZERO,
+            stalled: false,
+            detected: false,
+            control: None,
+            reload_suppression: Duration::ZERO,
+            suppress_until: None,
+            updated: false,
+            shared: None,
+            channels: Vec::new(),
+            min_sound: Duration::ZERO,
+            sound_since: None,
+            onset: None,
+            onset_seen: false,
+            duty: None,
+            quiet_hours: None,
+            quiet: false,
+            vote_window: 0,
+            votes: VecDeque::new(),
+            heartbeat: None,
+            level_history: None,
+            tx: None,
+            defer_to_quiet: Duration::ZERO,
+            deferred: Vec::new(),
+            last_transition: None,
+        }
+    }
+
+    /// Switch on while any channel is on, each with its own threshold (dB) and
+    /// timeout (s), levels then being given by `update_channel_levels`.
+    pub fn set_channels(&mut self, settings: &[(f32, u64)]) {
+        self.channels = settings
+            .iter()
+            .map(|&(threshold_db, timeout_s)| SwitchStatus::detached(threshold_db, timeout_s))
+            .collect();
+        self.threshold_db = settings
+            .iter()
+            .map(|&(threshold_db, _)| threshold_db)
+            .fold(f32::INFINITY, f32::min);
+    }
+
+    /// Switch on `snr_db` above the noise floor tracked from the level, on each
+    /// channel, rather than on a set threshold. The floor rises by up to
+    /// `rise_db_per_s` while off only, as a long signal is no noise.
+    pub fn set_snr_threshold(&mut self, snr_db: f32, rise_db_per_s: f32) {
+        self.snr = Some((snr_db, NoiseFloor::new(rise_db_per_s)));
+        for channel in self.channels.iter_mut() {
+            channel.snr = self.snr.clone();
+        }
+    }
+
+    /// Once on, count the level as silence only below the threshold minus this
+    /// gap in dB, on each channel. The gap is kept when the threshold changes.
+    pub fn set_hysteresis(&mut self, gap_db: f32) {
+        self.hysteresis_db = gap_db;
+        for channel in self.channels.iter_mut() {
+            channel.hysteresis_db = gap_db;
         }
     }
 
-    pub fn start(cmd_on: String, cmd_off: String, rx: mpsc::Receiver<bool>) {
+    /// Ignore the level for this time after each transition, while the commands settle.
+    pub fn set_post_cmd_mute(&mut self, mute: Duration) {
+        self.post_cmd_mute = mute;
+    }
+
+    /// Do not switch on again for this time after switching off, whatever the level.
+    pub fn set_off_deadtime(&mut self, deadtime: Duration) {
+        self.off_deadtime = deadtime;
+    }
+
+    /// Hold the actions of each transition until a level update below the
+    /// threshold, for commands whose noise would otherwise be detected, running
+    /// them anyway once held for `max_wait`. The post command mute restarts
+    /// when they run.
+    pub fn set_defer_to_quiet(&mut self, max_wait: Duration) {
+        self.defer_to_quiet = max_wait;
+    }
+
+    /// Take commands from the control socket, on each level update.
+    pub fn set_control(&mut self, control: mpsc::Receiver<Control>) {
+        self.control = Some(control);
+    }
+
+    /// Run no actions for the first transition within this time after a
+    /// threshold change from the control, only logging it.
+    pub fn set_reload_suppression(&mut self, window: Duration) {
+        self.reload_suppression = window;
+    }
+
+    /// Once on, stay on whatever the level until acknowledged on the control socket.
+    pub fn set_ack_required(&mut self) {
+        self.ack_required = true;
+    }
+
+    /// Do not switch on again for this time after an ack switched off, whatever
+    /// the level, for a lingering signal not to undo the reset right away.
+    pub fn set_ack_cooldown(&mut self, cooldown: Duration) {
+        self.ack_cooldown = cooldown;
+    }
+
+    /// Switch on only once sound lasted this long without interruption,
+    /// on each channel.
+    pub fn set_min_sound(&mut self, min_sound: Duration) {
+        self.min_sound = min_sound;
+        for channel in self.channels.iter_mut() {
+            channel.min_sound = min_sound;
+        }
+    }
+
+    /// Switch on only on sound starting with an onset, a rise of at least
+    /// `rise_db` within 200 ms, on each channel. Switching off is unchanged.
+    pub fn set_require_onset(&mut self, rise_db: f32) {
+        self.onset = Some(Onset::new(rise_db));
+        for channel in self.channels.iter_mut() {
+            channel.set_require_onset(rise_db);
+        }
+    }
+
+    /// Track the fraction of time on over the last `window`, logged once per
+    /// window and given in the status.
+    pub fn set_duty_window(&mut self, window: Duration) {
+        self.duty = Some(DutyCycle::new(window, self.is_on));
+    }
+
+    /// Force off on entering `quiet_hours`, ignoring levels and forced on
+    /// until they end.
+    pub fn set_quiet_hours(&mut self, quiet_hours: QuietHours) {
+        self.quiet_hours = Some(quiet_hours);
+    }
+
+    /// Count sound only when most of the last `windows` decisions were sound,
+    /// on each channel.
+    pub fn set_vote_window(&mut self, windows: usize) {
+        self.vote_window = windows;
+        self.votes = VecDeque::with_capacity(windows);
+        for channel in self.channels.iter_mut() {
+            channel.set_vote_window(windows);
+        }
+    }
+
+    /// Count the levels as silence while the input is `stalled`, on each
+    /// channel, the level still being reported.
+    pub fn set_stalled(&mut self, stalled: bool) {
+        self.stalled = stalled;
+        for channel in self.channels.iter_mut() {
+            channel.stalled = stalled;
+        }
+    }
+
+    /// Log the state and level at this interval, from the level updates.
+    pub fn set_heartbeat(&mut self, interval: Duration) {
+        self.heartbeat = Some((interval, Instant::now()));
+    }
+
+    /// Record each level update and the state to this ring file.
+    pub fn set_level_history(&mut self, level_history: LevelHistory) {
+        self.level_history = Some(level_history);
+    }
+
+    /// Start as on, without running the on actions, to switch off and be
+    /// `finished` once silent for the timeout, counted from now.
+    pub fn wait_silence(&mut self) {
+        self.is_on = true;
+        self.on_trigger_last = Instant::now();
+        self.wait_silence = true;
+        if let Some(duty) = &mut self.duty {
+            duty.set(true);
+        }
+        for channel in self.channels.iter_mut() {
+            channel.is_on = true;
+            channel.on_trigger_last = self.on_trigger_last;
+        }
+    }
+
+    /// Whether the switch turned on since started, starting as on with
+    /// `wait_silence` not counting.
+    pub fn detected(&self) -> bool {
+        self.detected
+    }
+
+    /// Whether the silence waited for was reached.
+    pub fn finished(&self) -> bool {
+        self.wait_silence && !self.is_on
+    }
+
+    /// Run all the actions for each transition received, in order, an action
+    /// failing with a panic being disabled without stopping the others.
+    pub fn start(
+        mut actions: Vec<Box<dyn SwitchAction>>,
+        rx: mpsc::Receiver<Transition>,
+    ) -> thread::JoinHandle<()> {
         thread::spawn(move || {
-            for state in rx {
-                let cmd = if state { cmd_on.clone() } else { cmd_off.clone() };
-                println!("Run {:?}", cmd);
-                Command::new(cmd)
-                    .spawn()
-                    .expect("Unable to run command")
-                    .wait()
-                    .unwrap();
+            for transition in rx {
+                // an action panicking is dropped, the others keep running
+                actions.retain_mut(|action| {
+                    let ran = panic::catch_unwind(AssertUnwindSafe(|| action.run(&transition)));
+                    if ran.is_err() {
+                        eprintln!("Warning: a switch action failed, disabling it");
+                    }
+                    ran.is_ok()
+                });
             }
-        });
+        })
+    }
+
+    /// Stop switching: run the off command if still on and `final_off` is set,
+    /// then close the channel and wait for the commands still queued to complete.
+    pub fn shutdown(mut self, worker: thread::JoinHandle<()>, final_off: bool) {
+        // nothing is going to be quiet anymore
+        self.defer_to_quiet = Duration::ZERO;
+        self.send_deferred(true);
+        if final_off && self.is_on {
+            self.turn_off();
+        }
+        drop(self);
+
+        if worker.join().is_err() {
+            eprintln!("Switch command thread panicked");
+        }
+    }
+
+    /// Fraction of clipped samples behind the next level update, for status reporting.
+    pub fn set_clip_ratio(&mut self, clip_ratio: f32) {
+        self.clip_ratio = clip_ratio;
+    }
+
+    /// Peak-hold level (dB) behind the next level update, for status reporting.
+    pub fn set_peak_hold(&mut self, peak_hold_db: Option<f32>) {
+        self.peak_hold_db = peak_hold_db;
     }
 
     pub fn update_level(&mut self, level: f32) {
-        if level >= self.threshold_db {
+        self.update_window(level, None);
+    }
+
+    /// Like `update_level`, with the window classified as `silent` or not
+    /// deciding instead of the level when given.
+    pub fn update_window(&mut self, level: f32, silent: Option<bool>) {
+        self.poll_control();
+        self.level_db = level;
+        if let Some((snr_db, noise_floor)) = &mut self.snr {
+            self.threshold_db = noise_floor.update(level, !self.is_on) + *snr_db;
+        }
+        let above =
+            !self.stalled && silent.map_or(level >= self.active_threshold_db(), |silent| !silent);
+        let sound = self.vote(above);
+        let onset = self.onset.as_mut().is_some_and(|onset| onset.update(level));
+        let quiet = self.in_quiet_hours();
+        // the level is still reported while muted, it just does not switch
+        if !quiet && !self.is_muted() {
+            if sound {
+                let now = Instant::now();
+                let sound_since = *self.sound_since.get_or_insert(now);
+                self.on_trigger_last = now;
+                self.onset_seen |= onset;
+                if !self.is_on
+                    && (self.onset.is_none() || self.onset_seen)
+                    && !self.in_off_deadtime()
+                    && now.duration_since(sound_since) >= self.min_sound
+                {
+                    self.turn_on();
+                }
+            } else {
+                self.sound_since = None;
+                self.onset_seen = false;
+                if self.is_on
+                    && !self.ack_required
+                    && Instant::now().duration_since(self.on_trigger_last) > self.timeout_s
+                {
+                    self.turn_off();
+                }
+            }
+        }
+        self.send_deferred(!above);
+
+        self.updated = true;
+        self.publish();
+        self.heartbeat();
+        if let Some(level_history) = &self.level_history {
+            level_history.record(self.level_db, self.is_on);
+        }
+    }
+
+    /// Update each channel switch, in the order given to `set_channels`,
+    /// then the combined state.
+    pub fn update_channel_levels(&mut self, levels_db: &[f32]) {
+        self.poll_control();
+        // the channels stay as forced off
+        let quiet = self.in_quiet_hours();
+        if !quiet {
+            for (channel, &level) in self.channels.iter_mut().zip(levels_db) {
+                channel.update_level(level);
+            }
+        }
+        self.level_db = levels_db.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+
+        if !quiet && !self.is_muted() {
+            let on = self.channels.iter().any(|channel| channel.is_on);
+            if on && !self.is_on && !self.in_off_deadtime() {
+                self.turn_on();
+            } else if !on && self.is_on && !self.ack_required {
+                self.turn_off();
+            }
+        }
+        self.send_deferred(self.level_db < self.threshold_db);
+
+        self.updated = true;
+        self.publish();
+        self.heartbeat();
+        if let Some(level_history) = &self.level_history {
+            level_history.record(self.level_db, self.is_on);
+        }
+    }
+
+    /// Whether in the quiet hours, forcing off on entering them whatever the
+    /// ack or latch.
+    fn in_quiet_hours(&mut self) -> bool {
+        let Some(quiet_hours) = &mut self.quiet_hours else {
+            return false;
+        };
+        let quiet = quiet_hours.now();
+        if quiet != self.quiet {
+            self.quiet = quiet;
+            if quiet {
+                eprintln!("Quiet hours {}: forcing off", quiet_hours);
+                self.force(false);
+            } else {
+                eprintln!("Quiet hours {} over", quiet_hours);
+            }
+        }
+        quiet
+    }
+
+    /// Majority of the last decisions with `sound`, over the whole window so
+    /// that the first ones do not decide alone.
+    fn vote(&mut self, sound: bool) -> bool {
+        if self.vote_window == 0 {
+            return sound;
+        }
+        if self.votes.len() == self.vote_window {
+            self.votes.pop_front();
+        }
+        self.votes.push_back(sound);
+        2 * self.votes.iter().filter(|&&sound| sound).count() > self.vote_window
+    }
+
+    fn poll_control(&mut self) {
+        while let Some(Ok(control)) = self.control.as_ref().map(mpsc::Receiver::try_recv) {
+            match control {
+                Control::Ack => {
+                    if self.ack_required && self.is_on {
+                        self.turn_off();
+                        if !self.ack_cooldown.is_zero() {
+                            // extends the off deadtime if shorter
+                            let until = Instant::now() + self.ack_cooldown;
+                            self.off_until = Some(self.off_until.map_or(until, |t| t.max(until)));
+                        }
+                    }
+                }
+                Control::Threshold(_) if self.snr.is_some() => {
+                    eprintln!("Threshold ignored, following the noise floor");
+                }
+                Control::Threshold(threshold_db) => {
+                    eprintln!("Threshold set to {} dB", threshold_db);
+                    let changed = self.updated && threshold_db != self.threshold_db;
+                    self.threshold_db = threshold_db;
+                    for channel in self.channels.iter_mut() {
+                        channel.threshold_db = threshold_db;
+                    }
+                    if changed && !self.reload_suppression.is_zero() {
+                        self.suppress_until = Some(Instant::now() + self.reload_suppression);
+                    }
+                }
+                Control::ForceOn if self.quiet => {
+                    eprintln!("Forced on ignored in quiet hours");
+                }
+                Control::ForceOn => {
+                    eprintln!("Forced on");
+                    self.force(true);
+                }
+                Control::ForceOff => {
+                    eprintln!("Forced off");
+                    self.force(false);
+                }
+            }
+        }
+    }
+
+    /// Switch on or off whatever the level, with the channels so that they
+    /// do not switch back right away.
+    pub fn force(&mut self, on: bool) {
+        // an explicit request is never an artifact of a threshold change
+        self.suppress_until = None;
+        for channel in self.channels.iter_mut() {
+            channel.force(on);
+        }
+        self.sound_since = None;
+        if on {
             self.on_trigger_last = Instant::now();
             if !self.is_on {
                 self.turn_on();
             }
-        } else if self.is_on &&
-            Instant::now().duration_since(self.on_trigger_last) > self.timeout_s {
+        } else if self.is_on {
             self.turn_off();
         }
     }
 
+    fn is_muted(&mut self) -> bool {
+        match self.mute_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                self.mute_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn in_off_deadtime(&self) -> bool {
+        self.off_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// The last transition since last taken, if any.
+    pub fn take_transition(&mut self) -> Option<Transition> {
+        self.last_transition.take()
+    }
+
+    pub fn threshold_db(&self) -> f32 {
+        self.threshold_db
+    }
+
+    /// Threshold the level is compared to now, lower while on with a hysteresis.
+    pub fn active_threshold_db(&self) -> f32 {
+        if self.is_on {
+            self.threshold_db - self.hysteresis_db
+        } else {
+            self.threshold_db
+        }
+    }
+
     pub fn is_on(&self) -> bool {
         self.is_on
     }
 
+    pub fn status(&self) -> Status {
+        Status {
+            is_on: self.is_on,
+            unacknowledged: self.ack_required && self.is_on,
+            level_db: self.level_db,
+            clip_ratio: self.clip_ratio,
+            peak_hold_db: self.peak_hold_db,
+            noise_floor_db: self.snr.as_ref().and_then(|(_, floor)| floor.floor_db()),
+            duty_cycle: self.duty.as_ref().map(DutyCycle::ratio),
+            since: self.since,
+        }
+    }
+
+    /// Status snapshot kept up to date on each level update.
+    pub fn share_status(&mut self) -> Arc<Mutex<Status>> {
+        let status = self.status();
+        self.shared
+            .get_or_insert_with(|| Arc::new(Mutex::new(status)))
+            .clone()
+    }
+
+    fn publish(&self) {
+        if let Some(shared) = &self.shared {
+            // never block the audio thread on a reader
+            if let Ok(mut status) = shared.try_lock() {
+                *status = self.status();
+            }
+        }
+    }
+
+    /// Periodic log lines, of the heartbeat and duty cycle.
+    fn heartbeat(&mut self) {
+        if let Some((interval, last)) = &mut self.heartbeat {
+            if last.elapsed() >= *interval {
+                *last = Instant::now();
+                eprintln!(
+                    "Heartbeat: {}, level {:.1} dB",
+                    if self.is_on { "on" } else { "off" },
+                    self.level_db
+                );
+            }
+        }
+        if let Some(duty) = &mut self.duty {
+            if let Some(ratio) = duty.report() {
+                eprintln!(
+                    "Duty cycle: {:.1}% on over the last {}s",
+                    ratio * 100.0,
+                    duty.window().as_secs_f32()
+                );
+            }
+        }
+    }
+
+    /// Time left before the off switch, counting down while on and below threshold.
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.ack_required {
+            return None;
+        }
+        if !self.channels.is_empty() {
+            return self.channels_remaining();
+        }
+
+        if self.is_on && self.level_db < self.active_threshold_db() {
+            let elapsed = Instant::now().duration_since(self.on_trigger_last);
+            Some(self.timeout_s.saturating_sub(elapsed))
+        } else {
+            None
+        }
+    }
+
+    /// Longest countdown of the channels on, once all of them are counting down.
+    fn channels_remaining(&self) -> Option<Duration> {
+        if !self.is_on {
+            return None;
+        }
+
+        let mut remaining = None;
+        for channel in self.channels.iter().filter(|channel| channel.is_on) {
+            remaining = Some(channel.remaining()?).max(remaining);
+        }
+        remaining
+    }
+
+    fn transition(&self, previous: Duration, first: bool) -> Transition {
+        Transition {
+            state: self.is_on,
+            level_db: self.level_db,
+            time: self.since,
+            previous,
+            first,
+            instant: Instant::now(),
+        }
+    }
+
+    fn mute(&mut self) {
+        if !self.post_cmd_mute.is_zero() {
+            self.mute_until = Some(Instant::now() + self.post_cmd_mute);
+        }
+    }
+
+    /// Whether the transition to the current state is the first one within
+    /// the suppression window of a threshold change, logging it if so.
+    fn suppressed(&mut self) -> bool {
+        match self.suppress_until.take() {
+            Some(until) if Instant::now() < until => {
+                eprintln!(
+                    "Transition {} suppressed after the threshold change, no command run",
+                    if self.is_on { "on" } else { "off" }
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Send a transition to the actions, or hold it until quiet with
+    /// `set_defer_to_quiet`, keeping it for `take_transition`.
+    fn send(&mut self, transition: Transition) {
+        self.last_transition = Some(transition);
+        if self.defer_to_quiet.is_zero() {
+            self.send_now(transition);
+        } else {
+            self.deferred.push(transition);
+        }
+    }
+
+    /// Send the transitions held if the last level update was `quiet`, or
+    /// the oldest was held for too long, dropping those switching on in the
+    /// quiet hours.
+    fn send_deferred(&mut self, quiet: bool) {
+        // nothing switches on within the quiet hours, however long it was held
+        if self.quiet && self.deferred.iter().any(|transition| transition.state) {
+            eprintln!("Quiet hours: dropping the deferred on command");
+            self.deferred.retain(|transition| !transition.state);
+        }
+        let Some(oldest) = self.deferred.first() else {
+            return;
+        };
+        let held = oldest.instant.elapsed();
+        if !quiet && held < self.defer_to_quiet {
+            return;
+        }
+        if !quiet {
+            eprintln!(
+                "No quiet moment within {:.1}s, running the deferred commands",
+                held.as_secs_f32()
+            );
+        }
+        self.mute();
+        for transition in mem::take(&mut self.deferred) {
+            self.send_now(transition);
+        }
+    }
+
+    /// Send a transition to the actions, which stop for good if their thread died.
+    fn send_now(&mut self, transition: Transition) {
+        if let Some(tx) = &self.tx {
+            if tx.send(transition).is_err() {
+                eprintln!("Error: switch actions stopped, no more commands will run");
+                self.tx = None;
+            }
+        }
+    }
+
     fn turn_on(&mut self) {
-        eprintln!("Turn on");
-        self.tx.send(true).unwrap();
+        let previous = self.since.elapsed().unwrap_or_default();
         self.is_on = true;
+        self.since = SystemTime::now();
+        if let Some(duty) = &mut self.duty {
+            duty.set(true);
+        }
+        if self.suppressed() {
+            return;
+        }
+        // a suppressed transition is no signal detected
+        let first = !self.detected;
+        self.detected = true;
+        self.mute();
+        self.send(self.transition(previous, first));
     }
 
     fn turn_off(&mut self) {
-        eprintln!("Turn off");
-        self.tx.send(false).unwrap();
+        let previous = self.since.elapsed().unwrap_or_default();
         self.is_on = false;
+        self.since = SystemTime::now();
+        if let Some(duty) = &mut self.duty {
+            duty.set(false);
+        }
+        // the source shuts down all the same without the command
+        if !self.off_deadtime.is_zero() {
+            self.off_until = Some(Instant::now() + self.off_deadtime);
+        }
+        if self.suppressed() {
+            return;
+        }
+        self.mute();
+        self.send(self.transition(previous, false));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_hours_drop_deferred_on() {
+        let (tx, rx) = mpsc::channel();
+        let mut switch = SwitchStatus::new(-60.0, 0, tx);
+        switch.set_defer_to_quiet(Duration::from_secs(60));
+        // on, held for a quiet moment
+        switch.update_window(-10.0, None);
+        assert!(switch.is_on());
+        assert!(rx.try_recv().is_err());
+
+        // as on entering the quiet hours
+        switch.quiet = true;
+        switch.force(false);
+        switch.update_window(-90.0, None);
+        let sent: Vec<bool> = rx.try_iter().map(|transition| transition.state).collect();
+        assert_eq!(sent, [false]);
+    }
+
+    #[test]
+    fn deferred_sent_when_quiet() {
+        let (tx, rx) = mpsc::channel();
+        let mut switch = SwitchStatus::new(-60.0, 30, tx);
+        switch.set_defer_to_quiet(Duration::from_secs(60));
+        switch.update_window(-10.0, None);
+        assert!(rx.try_recv().is_err());
+        switch.update_window(-90.0, None);
+        let sent: Vec<bool> = rx.try_iter().map(|transition| transition.state).collect();
+        assert_eq!(sent, [true]);
     }
 }
@@ -0,0 +1,15 @@
+extern crate silentcmd;
+
+use std::env;
+
+#[cfg(windows)]
+fn main() {
+    silentcmd::backend::wasapi::run(env::args().collect());
+}
+
+#[cfg(not(windows))]
+fn main() {
+    let _ = env::args();
+    eprintln!("silentcmd-wasapi records on Windows only");
+    std::process::exit(1);
+}
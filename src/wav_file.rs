@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+/// WAVE_FORMAT_IEEE_FLOAT
+const FORMAT_FLOAT: u16 = 3;
+/// WAVE_FORMAT_EXTENSIBLE, the actual format being the first two bytes of the sub-format
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// A WAV file read as float samples, whatever its sample format.
+///
+/// hound reads integer samples of any width and 32-bit floats, plain or
+/// WAVE_FORMAT_EXTENSIBLE. 64-bit float files, which hound refuses, are read
+/// directly.
+pub enum WavFile {
+    Hound(hound::WavReader<BufReader<File>>),
+    Float64 {
+        spec: hound::WavSpec,
+        /// bytes left in the data chunk
+        data: io::Take<BufReader<File>>,
+    },
+}
+
+impl WavFile {
+    pub fn open(path: &str) -> Result<WavFile, String> {
+        match hound::WavReader::open(path) {
+            Ok(reader) => Ok(WavFile::Hound(reader)),
+            Err(hound_error) => match open_float64(path) {
+                Ok(Some(file)) => Ok(file),
+                Ok(None) | Err(_) => Err(hound_error.to_string()),
+            },
+        }
+    }
+
+    pub fn spec(&self) -> hound::WavSpec {
+        match self {
+            WavFile::Hound(reader) => reader.spec(),
+            WavFile::Float64 { spec, .. } => *spec,
+        }
+    }
+
+    /// Read up to `count` samples, fewer at the end of the file.
+    pub fn read(&mut self, count: usize) -> Vec<f32> {
+        match self {
+            WavFile::Hound(reader) => {
+                let spec = reader.spec();
+                match spec.sample_format {
+                    // integer samples are signed whatever their width, 8-bit included
+                    hound::SampleFormat::Int => {
+                        let full_scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+                        reader
+                            .samples::<i32>()
+                            .take(count)
+                            .filter_map(Result::ok)
+                            .map(|s| s as f32 / full_scale)
+                            .collect()
+                    }
+                    hound::SampleFormat::Float => reader
+                        .samples::<f32>()
+                        .take(count)
+                        .filter_map(Result::ok)
+                        .collect(),
+                }
+            }
+            WavFile::Float64 { data, .. } => {
+                let mut bytes = Vec::with_capacity(count * 8);
+                if let Err(e) = data.by_ref().take(count as u64 * 8).read_to_end(&mut bytes) {
+                    eprintln!("Warning: read error, {}", e);
+                }
+                bytes
+                    .chunks_exact(8)
+                    .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Open a 64-bit float file, None if the file is another format.
+fn open_float64(path: &str) -> io::Result<Option<WavFile>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut spec = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk)?;
+        let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut fmt = vec![0u8; size as usize];
+                file.read_exact(&mut fmt)?;
+                if fmt.len() < 16 {
+                    return Ok(None);
+                }
+                let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+                let mut format = u16_at(0);
+                if format == FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+                    format = u16_at(24);
+                }
+                if format != FORMAT_FLOAT || u16_at(14) != 64 {
+                    return Ok(None);
+                }
+                spec = Some(hound::WavSpec {
+                    channels: u16_at(2),
+                    sample_rate: u32::from_le_bytes(fmt[4..8].try_into().unwrap()),
+                    bits_per_sample: 64,
+                    sample_format: hound::SampleFormat::Float,
+                });
+            }
+            b"data" => {
+                return Ok(spec.map(|spec| WavFile::Float64 {
+                    spec,
+                    data: file.take(u64::from(size)),
+                }));
+            }
+            _ => {
+                file.seek(SeekFrom::Current(i64::from(size)))?;
+            }
+        }
+        // chunks are padded to an even size
+        if size % 2 == 1 {
+            file.seek(SeekFrom::Current(1))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("silentcmd-{}-{}.wav", std::process::id(), name))
+    }
+
+    /// 64-bit float file of `samples`, with a `fmt ` chunk of `fmt_extra`
+    /// bytes after the 16 common ones, and an odd-sized chunk before the data.
+    fn write_float64(
+        path: &PathBuf,
+        format: u16,
+        channels: u16,
+        rate: u32,
+        fmt_extra: &[u8],
+        samples: &[f64],
+    ) {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&format.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&rate.to_le_bytes());
+        fmt.extend_from_slice(&(rate * u32::from(channels) * 8).to_le_bytes());
+        fmt.extend_from_slice(&(channels * 8).to_le_bytes());
+        fmt.extend_from_slice(&64u16.to_le_bytes());
+        fmt.extend_from_slice(fmt_extra);
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", &fmt), (b"LIST", &vec![1, 2, 3]), (b"data", &data)] {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(chunk);
+            if chunk.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend_from_slice(&body);
+        fs::write(path, file).unwrap();
+    }
+
+    fn read_all(path: &PathBuf) -> (hound::WavSpec, Vec<f32>) {
+        let mut wav = WavFile::open(path.to_str().unwrap()).unwrap();
+        let samples = wav.read(100);
+        fs::remove_file(path).unwrap();
+        (wav.spec(), samples)
+    }
+
+    #[test]
+    fn float32_through_hound() {
+        let path = temp_path("float32");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in [0.5f32, -0.25, 1.0, 0.0] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let (spec, samples) = read_all(&path);
+        assert_eq!(spec.sample_rate, 44100);
+        assert_eq!(spec.sample_format, hound::SampleFormat::Float);
+        assert_eq!(samples, [0.5, -0.25, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn float64_plain() {
+        let path = temp_path("float64");
+        write_float64(&path, FORMAT_FLOAT, 1, 96000, &[], &[0.5, -0.25, 1.0]);
+
+        let (spec, samples) = read_all(&path);
+        assert_eq!(spec.sample_rate, 96000);
+        assert_eq!(spec.channels, 1);
+        assert_eq!(spec.bits_per_sample, 64);
+        assert_eq!(samples, [0.5, -0.25, 1.0]);
+    }
+
+    #[test]
+    fn float64_extensible() {
+        let path = temp_path("float64-extensible");
+        // extension size, valid bits, channel mask and the float sub-format GUID
+        let mut extra = Vec::new();
+        extra.extend_from_slice(&22u16.to_le_bytes());
+        extra.extend_from_slice(&64u16.to_le_bytes());
+        extra.extend_from_slice(&3u32.to_le_bytes());
+        extra.extend_from_slice(&[
+            0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38,
+            0x9b, 0x71,
+        ]);
+        write_float64(
+            &path,
+            FORMAT_EXTENSIBLE,
+            2,
+            48000,
+            &extra,
+            &[0.125, -0.5, 0.75, -1.0],
+        );
+
+        let (spec, samples) = read_all(&path);
+        assert_eq!(spec.sample_rate, 48000);
+        assert_eq!(spec.channels, 2);
+        assert_eq!(samples, [0.125, -0.5, 0.75, -1.0]);
+    }
+}
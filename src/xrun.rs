@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Time over which the xrun rate is measured.
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// Rate of xruns over the last few seconds, to take a stalled stream as silence.
+pub struct XrunRate {
+    max_per_s: f32,
+    /// xruns within the window, oldest first
+    recent: VecDeque<Instant>,
+    stalled: bool,
+}
+
+impl XrunRate {
+    /// Stalled above `max_per_s` xruns per second.
+    pub fn new(max_per_s: f32) -> XrunRate {
+        XrunRate {
+            max_per_s,
+            recent: VecDeque::new(),
+            stalled: false,
+        }
+    }
+
+    /// Count `count` xruns happening now.
+    pub fn add(&mut self, count: usize) {
+        let now = Instant::now();
+        self.recent.extend((0..count).map(|_| now));
+    }
+
+    /// Whether the rate is above the maximum, logging changes.
+    pub fn stalled(&mut self) -> bool {
+        let now = Instant::now();
+        while self
+            .recent
+            .front()
+            .is_some_and(|&xrun| now.duration_since(xrun) > WINDOW)
+        {
+            self.recent.pop_front();
+        }
+
+        let rate = self.recent.len() as f32 / WINDOW.as_secs_f32();
+        let stalled = rate > self.max_per_s;
+        if stalled != self.stalled {
+            self.stalled = stalled;
+            if stalled {
+                eprintln!(
+                    "Xruns at {:.1}/s, above {}/s: counting as silence",
+                    rate, self.max_per_s
+                );
+            } else {
+                eprintln!("Xruns back below {}/s", self.max_per_s);
+            }
+        }
+        stalled
+    }
+}